@@ -1,37 +1,178 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time;
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::HeapCons;
+use ringbuf::traits::{Consumer, Observer};
 
 use crate::audio_gen::get_sample;
 use crate::audio_gen::oscillator::OscillatorTables;
-use crate::common::constants::SAMPLE_RATE;
+use crate::audio_gen::sample_format::SampleFormat;
+use crate::common::render_config::{RenderConfig, RenderConfigBuilder};
+use crate::common::stereo::StereoFrame;
 use crate::note::playback_note::PlaybackNote;
 
-// TODO SUPPORT LOFI AND 32-BIT
-static WAV_SPEC: hound::WavSpec  = hound::WavSpec {
-    channels: 1,
-    sample_rate: SAMPLE_RATE as u32,
-    bits_per_sample: 16,
-    sample_format: hound::SampleFormat::Int,
-};
+fn wav_spec(render_config: &RenderConfig, num_channels: u16) -> hound::WavSpec {
+    hound::WavSpec {
+        channels: num_channels,
+        sample_rate: render_config.sample_rate as u32,
+        bits_per_sample: render_config.sample_format.bits_per_sample(),
+        sample_format: render_config.sample_format.wav_sample_format(),
+    }
+}
+
+// Seconds of audio the render thread may get ahead of the output stream
+pub(crate) static STREAM_BUFFER_SECONDS: f32 = 2.0;
+// Seconds of audio rendered before the output stream starts pulling samples
+static STREAM_PREFILL_SECONDS: f32 = 0.5;
+static STREAM_POLL_INTERVAL_MS: u64 = 10;
+
+// Underruns seen by the output stream callback. An underrun is a run of one or more frames where
+// the ring buffer was empty but the render thread had not yet finished
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct StreamStats {
+    pub(crate) num_underruns: usize,
+    pub(crate) num_underrun_frames: usize,
+}
+
+// Render configuration matching the rate the default output device actually runs at, so that
+// samples rendered for it play back at the right pitch and speed
+#[allow(dead_code)]
+pub(crate) fn default_output_render_config() -> RenderConfig {
+    let host = cpal::default_host();
+    let device = host.default_output_device().expect("No output device available");
+    let config = device.default_output_config().unwrap();
+    device_render_config(&config)
+}
+
+fn device_render_config(config: &cpal::SupportedStreamConfig) -> RenderConfig {
+    RenderConfigBuilder::default()
+        .sample_rate(config.sample_rate().0 as f32)
+        .build().unwrap()
+}
 
 #[allow(dead_code)]
-pub(crate) fn gen_note_stream(playback_note: PlaybackNote, oscillator_tables: OscillatorTables) {
+pub(crate) fn gen_note_stream(playback_note: PlaybackNote, mut oscillator_tables: OscillatorTables) {
     let host = cpal::default_host();
     let device = host.default_output_device().expect("No output device available");
     let config = device.default_output_config().unwrap();
+    oscillator_tables.render_config = device_render_config(&config);
 
     gen_note_stream_impl::<f32>(&device, &config.into(), oscillator_tables, playback_note);
 }
 
 #[allow(dead_code)]
 pub(crate) fn gen_notes_stream(playback_notes: Vec<PlaybackNote>,
-                               oscillator_tables: OscillatorTables)
+                               mut oscillator_tables: OscillatorTables)
 {
     let host = cpal::default_host();
     let device = host.default_output_device().expect("No output device available");
     let config = device.default_output_config().unwrap();
+    oscillator_tables.render_config = device_render_config(&config);
+
+    let (window_start_time_ms, window_end_time_ms) = window_bounds_ms(&playback_notes);
+    let window_duration_ms = (window_end_time_ms - window_start_time_ms).floor() as u64;
+    
+    gen_notes_stream_impl(&device, &config.into(), oscillator_tables, playback_notes,
+                          window_duration_ms);
+}
+
+// Opens one output stream for the whole piece and plays samples as the render thread pushes them
+// into the ring buffer. Returns when render_done is set and the buffer has been drained.
+// The render thread must render at the device rate, see default_output_render_config
+#[allow(dead_code)]
+pub(crate) fn gen_ring_buffer_stream(mut consumer: HeapCons<StereoFrame>,
+                                     render_done: Arc<AtomicBool>) -> StreamStats {
+    let host = cpal::default_host();
+    let device = host.default_output_device().expect("No output device available");
+    let config = device.default_output_config().unwrap();
+    let render_config = device_render_config(&config);
+
+    // Let the render thread get ahead before the callback starts pulling samples
+    let num_prefill_frames = (STREAM_PREFILL_SECONDS * render_config.sample_rate) as usize;
+    while consumer.occupied_len() < num_prefill_frames && !render_done.load(Ordering::SeqCst) {
+        std::thread::sleep(time::Duration::from_millis(1));
+    }
+
+    let num_underruns = Arc::new(AtomicUsize::new(0));
+    let num_underrun_frames = Arc::new(AtomicUsize::new(0));
+    let drained = Arc::new(AtomicBool::new(false));
+
+    let stream_num_underruns = num_underruns.clone();
+    let stream_num_underrun_frames = num_underrun_frames.clone();
+    let stream_drained = drained.clone();
+    let mut in_underrun = false;
+    let mut next_frame = move || {
+        // Read render_done before popping. The render thread sets it after its last push, so an
+        // empty pop after seeing it set means every frame has been played
+        let is_render_done = render_done.load(Ordering::SeqCst);
+        match consumer.try_pop() {
+            Some(frame) => {
+                in_underrun = false;
+                frame
+            }
+            None => {
+                if is_render_done {
+                    stream_drained.store(true, Ordering::SeqCst);
+                } else {
+                    if !in_underrun {
+                        stream_num_underruns.fetch_add(1, Ordering::SeqCst);
+                        in_underrun = true;
+                    }
+                    stream_num_underrun_frames.fetch_add(1, Ordering::SeqCst);
+                }
+                StereoFrame::default()
+            }
+        }
+    };
+
+    let channels = config.channels() as usize;
+    let err_fn =
+        |err| eprintln!("an error occurred on the output audio stream: {}", err);
+    let stream = device.build_output_stream(
+        &config.into(),
+        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+            write_stream::<f32>(data, channels, &mut next_frame)
+        },
+        err_fn,
+        None
+    ).unwrap();
+    stream.play().unwrap();
+
+    while !drained.load(Ordering::SeqCst) {
+        std::thread::sleep(time::Duration::from_millis(STREAM_POLL_INTERVAL_MS));
+    }
+
+    StreamStats {
+        num_underruns: num_underruns.load(Ordering::SeqCst),
+        num_underrun_frames: num_underrun_frames.load(Ordering::SeqCst),
+    }
+}
+
+// Offline version of gen_notes_stream. Mixes the window of playback_notes sample by sample into
+// a buffer instead of streaming it to the output device, so there is no real-time sleep
+#[allow(dead_code)]
+pub(crate) fn gen_notes_buffer(playback_notes: &mut [PlaybackNote],
+                               oscillator_tables: &OscillatorTables) -> Vec<StereoFrame> {
+    let render_config = oscillator_tables.render_config;
+    let sample_rate = render_config.sample_rate;
+    let (window_start_time_ms, window_end_time_ms) = window_bounds_ms(playback_notes);
+    let num_samples = render_config.ms_to_samples(window_end_time_ms)
+        .saturating_sub(render_config.ms_to_samples(window_start_time_ms));
+
+    let mut frames = Vec::with_capacity(num_samples);
+    let mut sample_clock = 0f32;
+    for sample_count in 0..num_samples as u64 {
+        frames.push(get_sample::get_notes_sample(playback_notes, oscillator_tables,
+                                                 sample_clock / sample_rate, sample_count));
+        sample_clock = (sample_clock + 1.0) % sample_rate;
+    }
+    frames
+}
 
+// Earliest start and latest end time of a window of notes emitted by a TrackGrid
+pub(crate) fn window_bounds_ms(playback_notes: &[PlaybackNote]) -> (f32, f32) {
     let window_start_time_ms = playback_notes.iter()
         .map(|playback_note| playback_note.playback_start_time_ms)
         .reduce(|a, b| a.min(b))
@@ -40,10 +181,7 @@ pub(crate) fn gen_notes_stream(playback_notes: Vec<PlaybackNote>,
         .map(|playback_note| playback_note.playback_end_time_ms)
         .reduce(|a, b| a.max(b))
         .unwrap();
-    let window_duration_ms = (window_end_time_ms - window_start_time_ms).floor() as u64;
-    
-    gen_notes_stream_impl::<f32>(&device, &config.into(), oscillator_tables, playback_notes,
-                                 window_duration_ms);
+    (window_start_time_ms, window_end_time_ms)
 }
 
 // This works to generate a note buffer from playback_note.note and load
@@ -53,35 +191,79 @@ pub(crate) fn gen_notes_stream(playback_notes: Vec<PlaybackNote>,
 #[allow(dead_code)]
 pub(crate) fn gen_note_buffer(playback_note: &mut PlaybackNote,
                               oscillator_tables: &OscillatorTables) {
-    let mut sample_count = 0;
+    let sample_rate = oscillator_tables.sample_rate();
     let num_samples = (
-        playback_note.playback_duration_ms().ceil() * 1000.0 * SAMPLE_RATE) as usize;
+        playback_note.playback_duration_ms().ceil() * 1000.0 * sample_rate) as usize;
     let mut sample_clock = 0f32;
-    for _ in 0..num_samples {
+    for sample_count in 0..num_samples as u64 {
         let sample = get_sample::get_note_sample(
-            playback_note, &oscillator_tables, sample_clock / SAMPLE_RATE, sample_count);
-        playback_note.sampled_note.append_sample(sample);
-        sample_clock = (sample_clock + 1.0) % SAMPLE_RATE;
-        sample_count += 1;
+            playback_note, oscillator_tables, sample_clock / sample_rate, sample_count);
+        playback_note.sampled_note.append_sample(sample.to_mono());
+        sample_clock = (sample_clock + 1.0) % sample_rate;
     }
 }
 
-// TODO PARAMETERIZE SAMPLE TYPE TO SUPPORT LOFI AND 32-BIT
+// Samples of a WAV file, one Vec per channel, at the rate the file was recorded at
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct AudioFile {
+    pub(crate) channels: Vec<Vec<f32>>,
+    pub(crate) sample_rate: f32,
+}
+
+// Reads the samples of a WAV file in any SampleFormat, normalized to -1.0 to 1.0
 #[allow(dead_code)]
-pub(crate) fn read_audio_file(file_path: &str) -> Vec<i16> {
-    let mut reader = hound::WavReader::open(file_path).unwrap();
-    let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
-    samples
+pub(crate) fn read_audio_file(file_path: &str) -> Result<AudioFile, String> {
+    let mut reader = hound::WavReader::open(file_path)
+        .map_err(|e| format!("Failed to open {}: {}", file_path, e))?;
+    let spec = reader.spec();
+    let sample_format = SampleFormat::from_wav_spec(&spec)?;
+    let samples: Result<Vec<f32>, hound::Error> = match sample_format {
+        SampleFormat::Float32 => reader.samples::<f32>().collect(),
+        _ => reader.samples::<i32>()
+            .map(|sample| sample.map(|sample| sample_format.int_to_normalized(sample)))
+            .collect(),
+    };
+    let samples = samples.map_err(|e| format!("Failed to read {}: {}", file_path, e))?;
+
+    // WAV samples are interleaved by channel
+    let num_channels = spec.channels.max(1) as usize;
+    let mut channels = vec![Vec::with_capacity(samples.len() / num_channels); num_channels];
+    for (i, sample) in samples.into_iter().enumerate() {
+        channels[i % num_channels].push(sample);
+    }
+    Ok(AudioFile { channels, sample_rate: spec.sample_rate as f32 })
 }
 
-// TODO PARAMETERIZE SAMPLE TYPE TO SUPPORT LOFI AND 32-BIT
+// Writes frames in render_config's SampleFormat. Int formats clip anything past full scale,
+// Float32 keeps it
 #[allow(dead_code)]
-pub(crate) fn write_audio_file(file_path: &str, samples: Vec<f32>) {
-    let mut writer = hound::WavWriter::create(file_path, WAV_SPEC).unwrap();
+pub(crate) fn write_audio_file(file_path: &str, frames: Vec<StereoFrame>,
+                               render_config: &RenderConfig) -> Result<(), String> {
+    write_samples(file_path, frames.into_iter().flat_map(|frame| [frame.left, frame.right]),
+                  render_config, 2)
+}
+
+// write_audio_file for a single channel of samples
+#[allow(dead_code)]
+pub(crate) fn write_mono_audio_file(file_path: &str, samples: Vec<f32>,
+                                    render_config: &RenderConfig) -> Result<(), String> {
+    write_samples(file_path, samples, render_config, 1)
+}
+
+// Writes samples already interleaved by channel
+fn write_samples(file_path: &str, samples: impl IntoIterator<Item = f32>,
+                 render_config: &RenderConfig, num_channels: u16) -> Result<(), String> {
+    let sample_format = render_config.sample_format;
+    let mut writer = hound::WavWriter::create(file_path, wav_spec(render_config, num_channels))
+        .map_err(|e| format!("Failed to create {}: {}", file_path, e))?;
     for sample in samples {
-        writer.write_sample(sample.round() as i16).unwrap();
+        let written = match sample_format {
+            SampleFormat::Float32 => writer.write_sample(sample),
+            _ => writer.write_sample(sample_format.normalized_to_int(sample)),
+        };
+        written.map_err(|e| format!("Failed to write {}: {}", file_path, e))?;
     }
-    writer.finalize().unwrap();
+    writer.finalize().map_err(|e| format!("Failed to write {}: {}", file_path, e))
 }
 
 #[allow(dead_code)]
@@ -90,14 +272,15 @@ fn gen_note_stream_impl<T>(device: &cpal::Device, config: &cpal::StreamConfig,
 where
     T: cpal::Sample + cpal::SizedSample + cpal::FromSample<f32>,
 {
+    let sample_rate = oscillator_tables.sample_rate();
     let mut sample_count = 0;
-    let mut sample_clock = -1.0 / SAMPLE_RATE;
+    let mut sample_clock = -1.0 / sample_rate;
     let duration_ms = playback_note.playback_duration_ms();
-    let mut next_sample = move || {
-        sample_clock = (sample_clock + 1.0) % SAMPLE_RATE;
+    let mut next_frame = move || {
+        sample_clock = (sample_clock + 1.0) % sample_rate;
         sample_count += 1;
         get_sample::get_note_sample(&mut playback_note, &oscillator_tables,
-                                        sample_clock / SAMPLE_RATE,
+                                        sample_clock / sample_rate,
                                         sample_count - 1)
     };
 
@@ -107,7 +290,7 @@ where
     let stream = device.build_output_stream(
         config,
         move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            write_stream::<T>(data, channels, &mut next_sample)
+            write_stream::<T>(data, channels, &mut next_frame)
         },
         err_fn,
         None
@@ -118,17 +301,18 @@ where
 }
 
 #[allow(dead_code)]
-fn gen_notes_stream_impl<T>(device: &cpal::Device, config: &cpal::StreamConfig,
-                            oscillator_tables: OscillatorTables, mut playback_notes: Vec<PlaybackNote>,
-                            note_duration_ms: u64)
+fn gen_notes_stream_impl(device: &cpal::Device, config: &cpal::StreamConfig,
+                         oscillator_tables: OscillatorTables, mut playback_notes: Vec<PlaybackNote>,
+                         note_duration_ms: u64)
 {
+    let sample_rate = oscillator_tables.sample_rate();
     let mut sample_count = 0;
     let mut sample_clock = -1.0;
-    let mut next_sample = move || {
-        sample_clock = (sample_clock + 1.0) % SAMPLE_RATE;
+    let mut next_frame = move || {
+        sample_clock = (sample_clock + 1.0) % sample_rate;
         sample_count += 1;
         get_sample::get_notes_sample(&mut playback_notes, &oscillator_tables,
-                                     sample_clock / SAMPLE_RATE,
+                                     sample_clock / sample_rate,
                                      sample_count - 1)
     };
 
@@ -138,7 +322,7 @@ fn gen_notes_stream_impl<T>(device: &cpal::Device, config: &cpal::StreamConfig,
     let stream = device.build_output_stream(
         config,
         move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            write_stream::<f32>(data, channels, &mut next_sample)
+            write_stream::<f32>(data, channels, &mut next_frame)
         },
         err_fn,
         None
@@ -148,15 +332,99 @@ fn gen_notes_stream_impl<T>(device: &cpal::Device, config: &cpal::StreamConfig,
     std::thread::sleep(time::Duration::from_millis(note_duration_ms));
 }
 
-fn write_stream<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32)
+// Mono devices get the downmix of each frame. Otherwise left and right go to the first two
+// channels and any further channels are left silent
+fn write_stream<T>(output: &mut [T], channels: usize, next_frame: &mut dyn FnMut() -> StereoFrame)
 where
     T: cpal::Sample + cpal::FromSample<f32>,
 {
     for output_frame in output.chunks_mut(channels) {
-        let sample_to_write = next_sample();
-        let value = T::from_sample::<f32>(sample_to_write);
-        for output_sample in output_frame.iter_mut() {
-            *output_sample = value;
+        let frame_to_write = next_frame();
+        if channels == 1 {
+            output_frame[0] = T::from_sample::<f32>(frame_to_write.to_mono());
+            continue;
+        }
+        for (channel, output_sample) in output_frame.iter_mut().enumerate() {
+            *output_sample = match channel {
+                0 => T::from_sample::<f32>(frame_to_write.left),
+                1 => T::from_sample::<f32>(frame_to_write.right),
+                _ => T::EQUILIBRIUM,
+            };
+        }
+    }
+}
+#[cfg(test)]
+mod test_audio_gen {
+    use crate::audio_gen::audio_gen::{read_audio_file, write_audio_file, write_mono_audio_file};
+    use crate::audio_gen::sample_format::SampleFormat;
+    use crate::common::render_config::{RenderConfig, RenderConfigBuilder};
+    use crate::common::stereo::StereoFrame;
+
+    #[test]
+    fn test_write_read_audio_file() {
+        let frames = vec![StereoFrame::new(0.5, -0.5), StereoFrame::new(1.0, -1.0)];
+        for sample_format in [SampleFormat::Int8, SampleFormat::Int16, SampleFormat::Int24,
+                              SampleFormat::Float32] {
+            let file_path = std::env::temp_dir()
+                .join(format!("osc_test_write_read_audio_file_{:?}.wav", sample_format));
+            let render_config = RenderConfigBuilder::default()
+                .sample_format(sample_format)
+                .build().unwrap();
+            write_audio_file(file_path.to_str().unwrap(), frames.clone(), &render_config).unwrap();
+
+            let audio_file = read_audio_file(file_path.to_str().unwrap()).unwrap();
+            assert_eq!(audio_file.channels.len(), 2);
+            let tolerance = 1.0 / (1i64 << (sample_format.bits_per_sample() - 1)) as f32;
+            let samples = audio_file.channels.concat();
+            for (sample, expected) in samples.iter().zip([0.5, 1.0, -0.5, -1.0]) {
+                assert!((sample - expected).abs() <= tolerance);
+            }
+            std::fs::remove_file(file_path).unwrap();
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_write_audio_file_headroom() {
+        let frames = vec![StereoFrame::new(1.5, -2.0)];
+        let file_path = std::env::temp_dir().join("osc_test_write_audio_file_headroom.wav");
+
+        let render_config = RenderConfigBuilder::default()
+            .sample_format(SampleFormat::Float32)
+            .build().unwrap();
+        write_audio_file(file_path.to_str().unwrap(), frames.clone(), &render_config).unwrap();
+        let audio_file = read_audio_file(file_path.to_str().unwrap()).unwrap();
+        assert_eq!(audio_file.channels, vec![vec![1.5], vec![-2.0]]);
+
+        // Int formats clip at full scale
+        let render_config = RenderConfigBuilder::default()
+            .sample_format(SampleFormat::Int16)
+            .build().unwrap();
+        write_audio_file(file_path.to_str().unwrap(), frames, &render_config).unwrap();
+        let audio_file = read_audio_file(file_path.to_str().unwrap()).unwrap();
+        assert_eq!(audio_file.channels, vec![vec![32767.0 / 32768.0], vec![-1.0]]);
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_write_audio_file_error() {
+        let file_path = std::env::temp_dir().join("osc_test_missing_dir").join("out.wav");
+        let result = write_audio_file(file_path.to_str().unwrap(),
+                                      vec![StereoFrame::default()], &RenderConfig::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_write_mono_audio_file() {
+        let file_path = std::env::temp_dir().join("osc_test_write_mono_audio_file.wav");
+        let render_config = RenderConfigBuilder::default()
+            .sample_rate(8000.0)
+            .sample_format(SampleFormat::Float32)
+            .build().unwrap();
+        write_mono_audio_file(file_path.to_str().unwrap(), vec![0.5, -0.25], &render_config)
+            .unwrap();
+        let audio_file = read_audio_file(file_path.to_str().unwrap()).unwrap();
+        assert_eq!(audio_file.channels, vec![vec![0.5, -0.25]]);
+        assert_eq!(audio_file.sample_rate, 8000.0);
+        std::fs::remove_file(file_path).unwrap();
+    }
+}
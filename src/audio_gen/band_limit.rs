@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+// Fundamental at the bottom of the lowest mip-map octave. Lower notes use the lowest table
+static MIP_MAP_BASE_FREQUENCY: f64 = 20.0;
+static MIN_MIP_MAP_TABLE_SAMPLES: usize = 2048;
+
+// Tables are expensive to build and only depend on the sample rate, so each rate is built once
+// and shared by every OscillatorTables rendering at it
+static MIP_MAP_TABLES: LazyLock<Mutex<HashMap<u32, Arc<MipMapTables>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// How saw, square and triangle waveforms avoid aliasing
+// Naive: lookup into a table of the ideal waveform, aliases heavily above a few hundred Hz
+// MipMap: lookup into per-octave tables that only hold harmonics below Nyquist
+// PolyBlep: ideal waveform with polynomial corrections smoothing each discontinuity
+// MipMap is the default on purpose, so existing saw, square and triangle notes lose the aliasing
+// they rendered with before and sound duller at high pitches. Naive restores the old sound
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub(crate) enum BandLimit {
+    Naive,
+    #[default]
+    MipMap,
+    PolyBlep,
+}
+
+// One table per octave of fundamental frequency, lowest first. The table for an octave holds
+// the harmonics of the highest fundamental in the octave that are below Nyquist
+#[derive(Debug, PartialEq)]
+pub(crate) struct MipMapTables {
+    pub(crate) saw: Vec<Vec<f32>>,
+    pub(crate) square: Vec<Vec<f32>>,
+    pub(crate) triangle: Vec<Vec<f32>>,
+}
+
+impl MipMapTables {
+    pub(crate) fn for_sample_rate(sample_rate: f32) -> Arc<MipMapTables> {
+        let mut tables = MIP_MAP_TABLES.lock().unwrap();
+        tables.entry(sample_rate.to_bits())
+            .or_insert_with(|| Arc::new(MipMapTables::new(sample_rate as f64)))
+            .clone()
+    }
+
+    fn new(sample_rate: f64) -> MipMapTables {
+        let nyquist = sample_rate / 2.0;
+        let mut max_harmonics = Vec::new();
+        let mut octave_top_frequency = MIP_MAP_BASE_FREQUENCY * 2.0;
+        loop {
+            max_harmonics.push(((nyquist / octave_top_frequency).floor() as usize).max(1));
+            if octave_top_frequency >= nyquist {
+                break;
+            }
+            octave_top_frequency *= 2.0;
+        }
+
+        // Oversample the richest table so interpolation between entries stays accurate
+        let num_table_samples = (4 * max_harmonics[0]).next_power_of_two()
+            .max(MIN_MIP_MAP_TABLE_SAMPLES);
+        let sin_table: Vec<f64> = (0..num_table_samples)
+            .map(|i| (2.0 * std::f64::consts::PI * i as f64 / num_table_samples as f64).sin())
+            .collect();
+        let cos_offset = num_table_samples / 4;
+
+        let sin_at = |k: usize, i: usize| sin_table[(k * i) % num_table_samples];
+        let cos_at = |k: usize, i: usize| sin_table[(k * i + cos_offset) % num_table_samples];
+
+        let pi = std::f64::consts::PI;
+        MipMapTables {
+            // 2t - 1, ramping up from -1.0
+            saw: build_octave_tables(&max_harmonics, num_table_samples, 1,
+                                     |k, i| -2.0 / pi * sin_at(k, i) / k as f64),
+            // 1.0 for the first half of the cycle and -1.0 for the second
+            square: build_octave_tables(&max_harmonics, num_table_samples, 2,
+                                        |k, i| 4.0 / pi * sin_at(k, i) / k as f64),
+            // -1.0 at the start of the cycle and 1.0 halfway through
+            triangle: build_octave_tables(&max_harmonics, num_table_samples, 2,
+                                          |k, i| -8.0 / (pi * pi) * cos_at(k, i) / (k * k) as f64),
+        }
+    }
+
+    // Index of the table whose octave contains frequency
+    pub(crate) fn table_index(&self, frequency: f32) -> usize {
+        let octave = (frequency as f64 / MIP_MAP_BASE_FREQUENCY).log2().floor();
+        (octave.max(0.0) as usize).min(self.saw.len() - 1)
+    }
+}
+
+// Sums harmonics 1, 1 + harmonic_step, ... up to each octave's max harmonic. Octaves are built
+// from the highest down, each adding its extra harmonics to the table of the octave above
+fn build_octave_tables(max_harmonics: &[usize], num_table_samples: usize, harmonic_step: usize,
+                       harmonic_value: impl Fn(usize, usize) -> f64) -> Vec<Vec<f32>> {
+    let mut sums = vec![0.0f64; num_table_samples];
+    let mut next_harmonic = 1;
+    let mut tables = Vec::with_capacity(max_harmonics.len());
+    for max_harmonic in max_harmonics.iter().rev() {
+        while next_harmonic <= *max_harmonic {
+            for (i, sum) in sums.iter_mut().enumerate() {
+                *sum += harmonic_value(next_harmonic, i);
+            }
+            next_harmonic += harmonic_step;
+        }
+        tables.push(sums.iter().map(|sum| *sum as f32).collect());
+    }
+    tables.reverse();
+    tables
+}
+
+// Difference between a band-limited and a naive unit step, tau phase away from the step and
+// dt the phase advanced per sample
+fn poly_blep(tau: f64, dt: f64) -> f64 {
+    if (0.0..dt).contains(&tau) {
+        let x = tau / dt;
+        -(1.0 - x) * (1.0 - x) / 2.0
+    } else if tau < 0.0 && tau > -dt {
+        let x = tau / dt;
+        (1.0 + x) * (1.0 + x) / 2.0
+    } else {
+        0.0
+    }
+}
+
+// Integral of poly_blep, the same correction for a unit change in slope
+fn poly_blamp(tau: f64, dt: f64) -> f64 {
+    if tau.abs() < dt {
+        let x = 1.0 - tau.abs() / dt;
+        dt * x * x * x / 6.0
+    } else {
+        0.0
+    }
+}
+
+// Signed phase distance from phase to a discontinuity at edge, wrapped to -0.5 to 0.5
+fn distance_to(phase: f64, edge: f64) -> f64 {
+    let distance = phase - edge;
+    distance - distance.round()
+}
+
+pub(crate) fn poly_blep_saw(phase: f64, dt: f64) -> f32 {
+    // drops by 2.0 at the end of each cycle
+    (2.0 * phase - 1.0 - 2.0 * poly_blep(distance_to(phase, 0.0), dt)) as f32
+}
+
+pub(crate) fn poly_blep_square(phase: f64, dt: f64) -> f32 {
+    poly_blep_pulse(phase, dt, 0.5)
+}
+
+// 1.0 for the first width of the cycle and -1.0 for the rest
+pub(crate) fn poly_blep_pulse(phase: f64, dt: f64, width: f64) -> f32 {
+    let naive = if phase < width { 1.0 } else { -1.0 };
+    (naive + 2.0 * poly_blep(distance_to(phase, 0.0), dt)
+        - 2.0 * poly_blep(distance_to(phase, width), dt)) as f32
+}
+
+pub(crate) fn poly_blep_triangle(phase: f64, dt: f64) -> f32 {
+    // slope goes from -4.0 to 4.0 per cycle at the start, and back halfway through
+    let naive = 4.0 * (phase - (phase + 0.5).floor()).abs() - 1.0;
+    (naive + 8.0 * poly_blamp(distance_to(phase, 0.0), dt)
+        - 8.0 * poly_blamp(distance_to(phase, 0.5), dt)) as f32
+}
+
+#[cfg(test)]
+mod test_band_limit {
+    use crate::audio_gen::band_limit::BandLimit;
+    use crate::audio_gen::noise::NoiseGenerator;
+    use crate::audio_gen::oscillator::{OscillatorTables, PhaseAccumulator, Waveform};
+
+    // 0.1 seconds at 44.1 kHz, so DFT bins are 10 Hz apart and every harmonic of 2 kHz lands
+    // exactly on a bin
+    static NUM_SAMPLES: usize = 4410;
+    static FREQUENCY: f32 = 2000.0;
+
+    // Fraction of the signal's energy in DFT bins that aren't harmonics of FREQUENCY. Harmonics
+    // above Nyquist fold back between the true harmonics, so this is the energy of the aliasing
+    fn alias_energy_ratio(waveform: Waveform) -> f64 {
+        let oscillator_tables = OscillatorTables::new();
+        let mut noise = NoiseGenerator::default();
+        let mut phase = PhaseAccumulator::default();
+        let samples: Vec<f64> = (0..NUM_SAMPLES)
+            .map(|_| oscillator_tables.get_waveform_sample(
+                waveform, FREQUENCY,
+                phase.next_phase(FREQUENCY, oscillator_tables.sample_rate()),
+                &mut noise) as f64)
+            .collect();
+
+        let bin_hz = oscillator_tables.sample_rate() as f64 / NUM_SAMPLES as f64;
+        let harmonic_bin_step = (FREQUENCY as f64 / bin_hz).round() as usize;
+        let mut harmonic_energy = 0.0;
+        let mut alias_energy = 0.0;
+        for bin in 1..NUM_SAMPLES / 2 {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (n, sample) in samples.iter().enumerate() {
+                let angle = 2.0 * std::f64::consts::PI * ((bin * n) % NUM_SAMPLES) as f64 /
+                    NUM_SAMPLES as f64;
+                re += sample * angle.cos();
+                im -= sample * angle.sin();
+            }
+            let energy = re * re + im * im;
+            if bin % harmonic_bin_step == 0 {
+                harmonic_energy += energy;
+            } else {
+                alias_energy += energy;
+            }
+        }
+        alias_energy / (harmonic_energy + alias_energy)
+    }
+
+    #[test]
+    fn test_saw_alias_energy() {
+        let naive = alias_energy_ratio(Waveform::Saw(BandLimit::Naive));
+        let mip_map = alias_energy_ratio(Waveform::Saw(BandLimit::MipMap));
+        let poly_blep = alias_energy_ratio(Waveform::Saw(BandLimit::PolyBlep));
+
+        // Naive lookup has aliasing within 20 dB of the signal, mip-mapped tables hold no
+        // harmonics above Nyquist and PolyBLEP suppresses the aliasing by more than 10 dB
+        assert!(naive > 1e-2);
+        assert!(mip_map < 1e-5);
+        assert!(poly_blep < 2e-3);
+        assert!(poly_blep < naive / 10.0);
+    }
+
+    #[test]
+    fn test_band_limited_waveforms_match_naive_shape() {
+        // At low frequencies nearly all harmonics are kept, so band limiting barely changes the
+        // waveform away from its discontinuities
+        let oscillator_tables = OscillatorTables::new();
+        let mut noise = NoiseGenerator::default();
+        for band_limit in [BandLimit::MipMap, BandLimit::PolyBlep] {
+            for (band_limited, naive) in [
+                (Waveform::Saw(band_limit), Waveform::Saw(BandLimit::Naive)),
+                (Waveform::Square(band_limit), Waveform::Square(BandLimit::Naive)),
+                (Waveform::Triangle(band_limit), Waveform::Triangle(BandLimit::Naive)),
+            ] {
+                // a quarter of the way into a 100 Hz cycle
+                let phase = 0.25;
+                let band_limited_sample = oscillator_tables.get_waveform_sample(
+                    band_limited, 100.0, phase, &mut noise);
+                let naive_sample = oscillator_tables.get_waveform_sample(
+                    naive, 100.0, phase, &mut noise);
+                assert!((band_limited_sample - naive_sample).abs() < 0.05,
+                        "{:?}: {} != {}", band_limited, band_limited_sample, naive_sample);
+            }
+        }
+    }
+}
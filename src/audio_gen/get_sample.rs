@@ -1,30 +1,27 @@
-use crate::audio_gen::oscillator;
-use crate::audio_gen::oscillator::{get_gaussian_noise_sample, OscillatorTables};
-use crate::audio_gen::oscillator::Waveform;
-use crate::common::constants::NYQUIST_FREQUENCY;  // khz samples per second
+use crate::audio_gen::oscillator::{OscillatorTables, Waveform};
+use crate::common::stereo::StereoFrame;
 use crate::note::playback_note::{NoteType, PlaybackNote};
 
 pub(crate) fn get_note_sample(playback_note: &mut PlaybackNote, osc_tables: &OscillatorTables,
-                              sample_position: f32, sample_count: u64) -> f32 {
+                              sample_position: f32, sample_count: u64) -> StereoFrame {
     match playback_note.note_type {
         NoteType::Oscillator => {
-            let mut sample = 0.0;
-            for waveform in playback_note.note.waveforms.clone() {
-                sample += match waveform {
-                    Waveform::GaussianNoise => get_gaussian_noise_sample(),
-                    Waveform::Saw => oscillator::get_sample(
-                        &osc_tables.saw_table, playback_note.note.frequency, sample_count),
-                    Waveform::Sine => oscillator::get_sample(
-                        &osc_tables.sine_table, playback_note.note.frequency, sample_count),
-                    Waveform::Square => oscillator::get_sample(
-                        &osc_tables.square_table, playback_note.note.frequency, sample_count),
-                    Waveform::Triangle => oscillator::get_sample(
-                        &osc_tables.triangle_table, playback_note.note.frequency, sample_count),
-                }
-            }
-
-            playback_note.apply_effects(playback_note.note.volume * sample, sample_position,
-                                        sample_count)
+            get_oscillator_sample(playback_note, osc_tables, sample_position, sample_count)
+        }
+        NoteType::FM => {
+            let volume = playback_note.fm_note.volume;
+            let sample = playback_note.fm_note.next_sample(osc_tables);
+            playback_note.apply_effects(volume * sample, sample_position, sample_count)
+        }
+        NoteType::Additive => {
+            let volume = playback_note.additive_note.volume;
+            let sample = playback_note.additive_note.next_sample(osc_tables);
+            playback_note.apply_effects(volume * sample, sample_position, sample_count)
+        }
+        NoteType::Sample if playback_note.sampled_note.is_stereo() => {
+            let volume = playback_note.sampled_note.volume;
+            let frame = playback_note.sampled_note.next_frame();
+            playback_note.apply_stereo_effects(frame * volume, sample_position, sample_count)
         }
         NoteType::Sample => {
             let volume = playback_note.sampled_note.volume;
@@ -35,23 +32,178 @@ pub(crate) fn get_note_sample(playback_note: &mut PlaybackNote, osc_tables: &Osc
     }
 }
 
-pub(crate) fn get_notes_sample(playback_notes: &mut Vec<PlaybackNote>,
+// Sums the note's layers, each at its level and its offset from the note's pitch. A unison
+// note plays every layer once per voice, at the voice's detuned frequency and phase and panned
+// to its place in the stereo spread. With no spread the voices are mixed to mono. Pitch envelopes
+// move every voice and layer together
+fn get_oscillator_sample(playback_note: &mut PlaybackNote, osc_tables: &OscillatorTables,
+                         sample_position: f32, sample_count: u64) -> StereoFrame {
+    let unison = playback_note.note.unison;
+    let num_layers = playback_note.note.layers.len();
+    if playback_note.oscillator_phases.len() != unison.num_voices * num_layers {
+        playback_note.oscillator_phases = playback_note.oscillator_phases_at(0.0);
+    }
+    playback_note.resolve_wavetables();
+    // modulate once per sample, so every voice plays the same modulated waveforms
+    let mut waveforms = std::mem::take(&mut playback_note.modulated_waveforms);
+    waveforms.clear();
+    for index in 0..num_layers {
+        waveforms.push(playback_note.modulated_waveform(playback_note.note.layers[index].waveform,
+                                                        sample_count));
+    }
+    let note_frequency = playback_note.note.frequency * playback_note.pitch_ratio(sample_count);
+
+    let mut frame = StereoFrame::default();
+    let mut mono_sample = 0.0;
+    for voice in 0..unison.num_voices {
+        let voice_frequency = note_frequency * unison.frequency_ratio(voice);
+        let mut sample = 0.0;
+        for (index, waveform) in waveforms.iter().enumerate() {
+            let layer = playback_note.note.layers[index];
+            let frequency = voice_frequency * layer.frequency_ratio();
+            let phase = playback_note.oscillator_phases[voice * num_layers + index]
+                .next_phase(frequency, osc_tables.sample_rate());
+            sample += layer.level * get_waveform_sample(playback_note, osc_tables, index,
+                                                        *waveform, frequency, phase);
+        }
+        sample *= unison.voice_gain();
+        mono_sample += sample;
+        frame += playback_note.pan_law.pan(sample, unison.pan(voice));
+    }
+    playback_note.modulated_waveforms = waveforms;
+
+    let volume = playback_note.note.volume;
+    if unison.stereo_spread == 0.0 {
+        playback_note.apply_effects(volume * mono_sample, sample_position, sample_count)
+    } else {
+        playback_note.apply_stereo_effects(frame * volume, sample_position, sample_count)
+    }
+}
+
+// Sample of the waveform of the note's layer at index, from the wavetable looked up when the
+// note was built rather than from the registry
+fn get_waveform_sample(playback_note: &mut PlaybackNote, osc_tables: &OscillatorTables,
+                       index: usize, waveform: Waveform, frequency: f32, phase: f64) -> f32 {
+    match (waveform, &playback_note.wavetables[index]) {
+        (Waveform::Wavetable(_, position), Some((_, wavetable))) =>
+            osc_tables.get_wavetable_sample(wavetable, position, phase),
+        _ => osc_tables.get_waveform_sample(waveform, frequency, phase, &mut playback_note.noise),
+    }
+}
+
+// Sum of the notes' samples. Levels aren't limited here, that is the master bus's job
+pub(crate) fn get_notes_sample(playback_notes: &mut [PlaybackNote],
                                oscillator_tables: &OscillatorTables,
-                               sample_position: f32, sample_count: u64) -> f32 {
-    let mut out_sample = 0.0;
+                               sample_position: f32, sample_count: u64) -> StereoFrame {
+    let mut out_frame = StereoFrame::default();
     for playback_note in playback_notes.iter_mut() {
         if sample_count > playback_note.playback_sample_end_time {
             continue;
         }
-        out_sample += get_note_sample(playback_note, oscillator_tables, sample_position,
-                                      sample_count);
+        out_frame += get_note_sample(playback_note, oscillator_tables, sample_position,
+                                     sample_count);
     }
+    out_frame
+}
 
-    if out_sample >= NYQUIST_FREQUENCY {
-        out_sample = NYQUIST_FREQUENCY - 1.0;
-    } else if out_sample <= -NYQUIST_FREQUENCY {
-        out_sample = -NYQUIST_FREQUENCY + 1.0;
+#[cfg(test)]
+mod test_get_sample {
+    use crate::audio_gen::get_sample::get_note_sample;
+    use crate::audio_gen::oscillator::{OscillatorTables, Waveform};
+    use crate::common::stereo::{PanLaw, PAN_CENTER};
+    use crate::envelope::envelope::EnvelopeBuilder;
+    use crate::envelope::envelope_pair::EnvelopePair;
+    use crate::envelope::modulation_envelope::ModulationEnvelopeBuilder;
+    use crate::note::note::NoteBuilder;
+    use crate::note::oscillator_layer::OscillatorLayerBuilder;
+    use crate::note::playback_note::PlaybackNoteBuilder;
+    use crate::note::unison::UnisonBuilder;
+
+    #[test]
+    fn test_oscillator_layers() {
+        let oscillator_tables = OscillatorTables::new();
+        // a sine an octave up at half level, starting a quarter cycle in
+        let layer = OscillatorLayerBuilder::default()
+            .level(0.5)
+            .octaves(1)
+            .phase(0.25)
+            .build().unwrap();
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .note(NoteBuilder::default()
+                .frequency(220.5)
+                .layers(vec![layer])
+                .build().unwrap())
+            .playback_sample_end_time(100)
+            .build().unwrap();
+        // 100 samples per cycle at 441 Hz
+        for i in 0..100 {
+            let frame = get_note_sample(&mut playback_note, &oscillator_tables, 0.0, i);
+            let phase = 0.25 + i as f32 / 100.0;
+            let expected = PanLaw::default()
+                .pan(0.5 * (2.0 * std::f32::consts::PI * phase).sin(), PAN_CENTER);
+            assert!((frame.left - expected.left).abs() < 1e-3, "{} != {}", frame.left,
+                    expected.left);
+        }
     }
-    out_sample
-}
 
+    #[test]
+    fn test_pitch_envelope() {
+        let oscillator_tables = OscillatorTables::new();
+        // an octave up for the first half of the note, then sliding down to the note's pitch
+        let envelope = EnvelopeBuilder::default()
+            .attack(EnvelopePair(0.0, 1.0))
+            .decay(EnvelopePair(0.5, 1.0))
+            .sustain(EnvelopePair(0.5, 1.0))
+            .build().unwrap();
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .note(NoteBuilder::default()
+                .frequency(220.5)
+                .waveforms(vec![Waveform::Sine])
+                .build().unwrap())
+            .pitch_envelope(Some(ModulationEnvelopeBuilder::default()
+                .envelope(envelope)
+                .depth(12.0)
+                .build().unwrap()))
+            .playback_sample_end_time(400)
+            .build().unwrap();
+        // 100 samples per cycle at 441 Hz
+        for i in 0..200 {
+            let frame = get_note_sample(&mut playback_note, &oscillator_tables, 0.0, i);
+            let phase = i as f32 / 100.0;
+            let expected = PanLaw::default()
+                .pan((2.0 * std::f32::consts::PI * phase).sin(), PAN_CENTER);
+            assert!((frame.left - expected.left).abs() < 1e-3, "{} != {}", frame.left,
+                    expected.left);
+        }
+    }
+
+    #[test]
+    fn test_unison_stereo_spread() {
+        let oscillator_tables = OscillatorTables::new();
+        let unison_note = |stereo_spread: f32| PlaybackNoteBuilder::default()
+            .note(NoteBuilder::default()
+                .frequency(441.0)
+                .waveforms(vec![Waveform::Sine])
+                .unison(UnisonBuilder::default()
+                    .num_voices(2)
+                    .detune_cents(50.0)
+                    .stereo_spread(stereo_spread)
+                    .build().unwrap())
+                .build().unwrap())
+            .playback_sample_end_time(1000)
+            .build().unwrap();
+
+        // Unspread, the detuned voices are mixed to mono and panned to the center
+        let mut centered = unison_note(0.0);
+        let frames: Vec<_> = (0..1000)
+            .map(|i| get_note_sample(&mut centered, &oscillator_tables, 0.0, i)).collect();
+        assert!(frames.iter().all(|frame| frame.left == frame.right));
+        assert!(frames.iter().any(|frame| frame.left != 0.0));
+
+        // Spread, the voices are panned apart so each channel hears a different mix
+        let mut spread = unison_note(1.0);
+        let frames: Vec<_> = (0..1000)
+            .map(|i| get_note_sample(&mut spread, &oscillator_tables, 0.0, i)).collect();
+        assert!(frames.iter().any(|frame| (frame.left - frame.right).abs() > 0.1));
+    }
+}
@@ -0,0 +1,361 @@
+// Golden file tests render small TrackGrids and DSL scripts offline and compare the frames with
+// reference WAVs checked in under src/audio_gen/test_data/golden, so that a change to how
+// anything sounds fails a test rather than going unheard. After a change that is meant to alter
+// the sound, rerun the tests with OSC_UPDATE_GOLDEN=1 set to rewrite the references, and listen
+// to the new ones before committing them
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::audio_gen::audio_gen::{read_audio_file, write_audio_file, write_mono_audio_file};
+use crate::audio_gen::sample_format::SampleFormat;
+use crate::common::render_config::RenderConfig;
+use crate::common::stereo::StereoFrame;
+use crate::composition::comp_utils::render_track_grid;
+use crate::dsl::parser::parse_dsl_with_config;
+
+static GOLDEN_DIR: &str = "src/audio_gen/test_data/golden";
+static UPDATE_GOLDEN_VAR: &str = "OSC_UPDATE_GOLDEN";
+// Renders are deterministic, this only allows for libm differences between platforms
+pub(crate) static DEFAULT_TOLERANCE: f32 = 1e-4;
+static REPORT_WINDOW_MS: f32 = 10.0;
+
+// The first sample where a render differs from its reference by more than the tolerance
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Divergence {
+    pub(crate) frame_index: usize,
+    pub(crate) channel: &'static str,
+    pub(crate) expected: f32,
+    pub(crate) actual: f32,
+}
+
+// How a render compares with its reference. Frames past the end of the shorter of the two are
+// compared with silence
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct GoldenReport {
+    pub(crate) name: String,
+    pub(crate) tolerance: f32,
+    pub(crate) num_expected_frames: usize,
+    pub(crate) num_actual_frames: usize,
+    pub(crate) max_error: f32,
+    pub(crate) first_divergence: Option<Divergence>,
+    pub(crate) window_ms: f32,
+    // RMS of the error across both channels, for each window_ms of the render
+    pub(crate) window_rms_errors: Vec<f32>,
+}
+
+impl GoldenReport {
+    pub(crate) fn passed(&self) -> bool {
+        self.num_expected_frames == self.num_actual_frames && self.first_divergence.is_none()
+    }
+}
+
+impl fmt::Display for GoldenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = if self.passed() { "passed" } else { "FAILED" };
+        writeln!(f, "golden {}: {}", self.name, result)?;
+        writeln!(f, "  frames: expected {}, actual {}",
+                 self.num_expected_frames, self.num_actual_frames)?;
+        writeln!(f, "  max error: {:.6}, tolerance {:.6}", self.max_error, self.tolerance)?;
+        match self.first_divergence {
+            Some(divergence) => writeln!(
+                f, "  first divergent sample: frame {} {}, expected {:.6}, actual {:.6}",
+                divergence.frame_index, divergence.channel, divergence.expected,
+                divergence.actual)?,
+            None => writeln!(f, "  no divergent samples")?,
+        }
+        // windows over the tolerance are marked
+        writeln!(f, "  RMS error per {} ms window:", self.window_ms)?;
+        for (i, rms_error) in self.window_rms_errors.iter().enumerate() {
+            let marker = if *rms_error > self.tolerance { " *" } else { "" };
+            writeln!(f, "  {:>8} ms  {:.6}{}", i as f32 * self.window_ms, rms_error, marker)?;
+        }
+        Ok(())
+    }
+}
+
+// Compares a render with its reference, sample by sample and window by window
+#[allow(dead_code)]
+pub(crate) fn compare_frames(name: &str, expected: &[StereoFrame], actual: &[StereoFrame],
+                             render_config: &RenderConfig, tolerance: f32) -> GoldenReport {
+    let num_frames = expected.len().max(actual.len());
+    let window_frames = (render_config.ms_to_samples(REPORT_WINDOW_MS)).max(1);
+    let mut max_error = 0.0f32;
+    let mut first_divergence = None;
+    let mut window_rms_errors = Vec::with_capacity(num_frames.div_ceil(window_frames));
+    let mut window_sum_squares = 0.0f32;
+    let mut window_num_samples = 0;
+    for frame_index in 0..num_frames {
+        let expected_frame = expected.get(frame_index).copied().unwrap_or_default();
+        let actual_frame = actual.get(frame_index).copied().unwrap_or_default();
+        for (channel, expected_sample, actual_sample) in
+                [("left", expected_frame.left, actual_frame.left),
+                 ("right", expected_frame.right, actual_frame.right)] {
+            let error = (actual_sample - expected_sample).abs();
+            max_error = max_error.max(error);
+            if error > tolerance && first_divergence.is_none() {
+                first_divergence = Some(Divergence {
+                    frame_index, channel, expected: expected_sample, actual: actual_sample,
+                });
+            }
+            window_sum_squares += error * error;
+            window_num_samples += 1;
+        }
+        if (frame_index + 1) % window_frames == 0 || frame_index + 1 == num_frames {
+            window_rms_errors.push((window_sum_squares / window_num_samples as f32).sqrt());
+            window_sum_squares = 0.0;
+            window_num_samples = 0;
+        }
+    }
+
+    GoldenReport {
+        name: name.to_string(),
+        tolerance,
+        num_expected_frames: expected.len(),
+        num_actual_frames: actual.len(),
+        max_error,
+        first_divergence,
+        window_ms: REPORT_WINDOW_MS,
+        window_rms_errors,
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(GOLDEN_DIR).join(format!("{}.wav", name))
+}
+
+fn read_golden(name: &str, render_config: &RenderConfig) -> Result<Vec<StereoFrame>, String> {
+    let file_path = golden_path(name);
+    let audio_file = read_audio_file(file_path.to_str().unwrap())
+        .map_err(|e| format!("{}. Run with {}=1 to create it", e, UPDATE_GOLDEN_VAR))?;
+    if audio_file.sample_rate != render_config.sample_rate {
+        return Err(format!("Golden {} is at {} Hz, the render is at {} Hz",
+                           name, audio_file.sample_rate, render_config.sample_rate));
+    }
+    match audio_file.channels.as_slice() {
+        [mono] => Ok(mono.iter().map(|sample| StereoFrame::from_mono(*sample)).collect()),
+        [left, right] => Ok(left.iter().zip(right.iter())
+            .map(|(left, right)| StereoFrame::new(*left, *right))
+            .collect()),
+        _ => Err(format!("Golden {} must be mono or stereo", name)),
+    }
+}
+
+// Compares frames with the reference WAV for name, and panics with the report if they differ
+// by more than tolerance. With OSC_UPDATE_GOLDEN set the frames are written as the new
+// reference instead, as 32-bit float so nothing is lost to quantization. A render whose channels
+// are the same all the way through is written as mono, to keep the reference small
+#[allow(dead_code)]
+pub(crate) fn assert_golden_with_tolerance(name: &str, frames: &[StereoFrame],
+                                           render_config: &RenderConfig, tolerance: f32) {
+    if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        let file_path = golden_path(name);
+        std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        let mut golden_render_config = *render_config;
+        golden_render_config.sample_format = SampleFormat::Float32;
+        let file_path = file_path.to_str().unwrap();
+        let written = if frames.iter().all(|frame| frame.left == frame.right) {
+            let samples = frames.iter().map(|frame| frame.left).collect();
+            write_mono_audio_file(file_path, samples, &golden_render_config)
+        } else {
+            write_audio_file(file_path, frames.to_vec(), &golden_render_config)
+        };
+        written.unwrap_or_else(|e| panic!("{}", e));
+        return;
+    }
+
+    let expected = read_golden(name, render_config).unwrap_or_else(|e| panic!("{}", e));
+    let report = compare_frames(name, &expected, frames, render_config, tolerance);
+    assert!(report.passed(), "\n{}", report);
+}
+
+#[allow(dead_code)]
+pub(crate) fn assert_golden(name: &str, frames: &[StereoFrame], render_config: &RenderConfig) {
+    assert_golden_with_tolerance(name, frames, render_config, DEFAULT_TOLERANCE);
+}
+
+// Parses and renders a script offline through the master bus, as bounce_track_grid does
+#[allow(dead_code)]
+pub(crate) fn render_dsl(script: &str, render_config: RenderConfig) -> Vec<StereoFrame> {
+    render_track_grid(parse_dsl_with_config(script, render_config).unwrap())
+}
+
+#[cfg(test)]
+mod test_golden {
+    use crate::audio_gen::golden::{assert_golden, compare_frames, render_dsl, Divergence,
+                                   DEFAULT_TOLERANCE};
+    use crate::audio_gen::band_limit::BandLimit;
+    use crate::audio_gen::oscillator::Waveform;
+    use crate::common::render_config::{RenderConfig, RenderConfigBuilder};
+    use crate::common::stereo::StereoFrame;
+    use crate::composition::comp_utils::render_track_grid;
+    use crate::envelope::envelope::EnvelopeBuilder;
+    use crate::envelope::envelope_pair::EnvelopePair;
+    use crate::note::note::NoteBuilder;
+    use crate::note::playback_note::PlaybackNoteBuilder;
+    use crate::sequence::time_note_sequence::TimeNoteSequenceBuilder;
+    use crate::track::track::TrackBuilder;
+    use crate::track::track_grid::TrackGridBuilder;
+
+    #[test]
+    fn test_compare_frames() {
+        let render_config = RenderConfig::default();
+        let expected = vec![StereoFrame::new(0.5, -0.5); 882];
+        let report = compare_frames("same", &expected, &expected, &render_config,
+                                    DEFAULT_TOLERANCE);
+        assert!(report.passed());
+        assert_eq!(report.window_rms_errors, vec![0.0, 0.0]);
+
+        let mut actual = expected.clone();
+        actual[500].right = -0.4;
+        actual.truncate(800);
+        let report = compare_frames("changed", &expected, &actual, &render_config,
+                                    DEFAULT_TOLERANCE);
+        assert!(!report.passed());
+        assert_eq!(report.first_divergence, Some(Divergence {
+            frame_index: 500, channel: "right", expected: -0.5, actual: -0.4,
+        }));
+        assert!((report.max_error - 0.5).abs() < 1e-6);
+        // the first window is untouched, the second has the changed sample and the missing end
+        assert_eq!(report.window_rms_errors[0], 0.0);
+        assert!(report.window_rms_errors[1] > 0.0);
+        let text = report.to_string();
+        assert!(text.contains("FAILED"));
+        assert!(text.contains("frame 500 right"));
+        assert!(text.contains("expected 882, actual 800"));
+    }
+
+    // An oscillator note through get_note_sample, built without the DSL
+    #[test]
+    fn test_golden_oscillator_note() {
+        let mut sequence = TimeNoteSequenceBuilder::default().build().unwrap();
+        sequence.append_note(
+            PlaybackNoteBuilder::default()
+                .note(
+                    NoteBuilder::default()
+                        .frequency(220.0)
+                        .volume(0.5)
+                        .start_time_ms(0.0)
+                        .end_time_ms(150.0)
+                        .waveforms(vec![Waveform::Sine, Waveform::Saw(BandLimit::default())])
+                        .build().unwrap()
+                )
+                .playback_start_time_ms(0.0)
+                .playback_end_time_ms(150.0)
+                .envelopes(vec![
+                    EnvelopeBuilder::default()
+                        .attack(EnvelopePair(0.1, 1.0))
+                        .decay(EnvelopePair(0.3, 0.6))
+                        .sustain(EnvelopePair(0.8, 0.6))
+                        .build().unwrap()
+                ])
+                .pan(-0.3)
+                .build().unwrap()
+        );
+        let track_grid = TrackGridBuilder::default()
+            .tracks(vec![TrackBuilder::default().sequence(sequence).build().unwrap()])
+            .build().unwrap();
+        let render_config = track_grid.render_config;
+        assert_golden("oscillator_note", &render_track_grid(track_grid), &render_config);
+    }
+
+    #[test]
+    fn test_golden_layers_and_voices() {
+        let script = r#"
+            FixedTimeNoteSequence dur Sixteenth tempo 60 num_steps 4
+            a 0.05,1.0 d 0.2,0.7 s 0.8,0.7 r 1.0,0.0
+            osc:sine/lvl=0.7,saw/lvl=0.3/oct=-1:4,C:0.5:0
+            osc:pulse=0.25,tri/semi=7:4,E:0.3:1
+            fm:stack:1.0/1.0,3.5/2.0/0.0/0.5/0.1:4,A:0.4:2
+            add:1.0/0.5,2.0/0.25,3.0/0.125:3,C:0.5:3
+        "#;
+        let render_config = RenderConfig::default();
+        assert_golden("layers_and_voices", &render_dsl(script, render_config), &render_config);
+    }
+
+    #[test]
+    fn test_golden_filter_and_modulation() {
+        let script = r#"
+            FixedTimeNoteSequence dur Eighth tempo 60 num_steps 2
+            a 0.0,1.0 d 0.3,0.5 s 0.8,0.5 r 1.0,0.0
+            filter lp cutoff 1200.0 q 2.0
+            pitch_env depth 12 a 0.0,1.0 d 0.2,0.0 s 0.9,0.0 r 1.0,0.0
+            cutoff_env depth 2 a 0.0,1.0 d 0.5,0.0 s 0.9,0.0 r 1.0,0.0
+            osc:saw:2,A:0.6:0
+            osc:square:3,E:0.4:1
+        "#;
+        let render_config = RenderConfig::default();
+        assert_golden("filter_and_modulation", &render_dsl(script, render_config),
+                      &render_config);
+    }
+
+    #[test]
+    fn test_golden_reverb() {
+        let script = r#"
+            FixedTimeNoteSequence dur Eighth tempo 60 num_steps 2
+            a 0.0,1.0 d 0.1,0.3 s 0.3,0.0 r 1.0,0.0
+            reverb room_size 0.3 damping 0.4 predelay_ms 5.0 width 0.0 mix 0.4
+            osc:tri:4,G:0.5:0
+            osc:tri:5,D:0.4:1
+        "#;
+        let render_config = RenderConfigBuilder::default()
+            .sample_rate(8000.0)
+            .build().unwrap();
+        assert_golden("reverb", &render_dsl(script, render_config), &render_config);
+    }
+
+    // Two delays in one block, one with a filtered feedback path and a fractional delay time
+    #[test]
+    fn test_golden_delay() {
+        let script = r#"
+            FixedTimeNoteSequence dur Eighth tempo 60 num_steps 2
+            a 0.0,1.0 d 0.05,0.2 s 0.1,0.0 r 1.0,0.0
+            delay time_ms 45.0 feedback 0.4 mix 0.5
+            delay time_ms 67.5 feedback 0.5 mix 0.3 cutoff 1500.0
+            osc:saw:3,C:0.5:0
+            osc:sine:4,G:0.4:1
+        "#;
+        let render_config = RenderConfigBuilder::default()
+            .sample_rate(8000.0)
+            .build().unwrap();
+        assert_golden("delay", &render_dsl(script, render_config), &render_config);
+    }
+
+    #[test]
+    fn test_golden_flanger_and_chorus() {
+        let script = r#"
+            FixedTimeNoteSequence dur Eighth tempo 60 num_steps 2
+            a 0.0,1.0 d 0.1,0.8 s 0.9,0.8 r 1.0,0.0
+            flanger window_size 80 mix 0.5 rate 4.0 depth 0.9 feedback 0.6
+            osc:saw:3,A:0.5:0
+
+            FixedTimeNoteSequence dur Eighth tempo 60 num_steps 2
+            a 0.0,1.0 d 0.1,0.8 s 0.9,0.8 r 1.0,0.0
+            flanger window_size 600 mix 0.5 rate 2.0 depth 0.3 chorus 3
+            osc:saw:4,E:0.4:1
+        "#;
+        let render_config = RenderConfig::default();
+        assert_golden("flanger_and_chorus", &render_dsl(script, render_config), &render_config);
+    }
+
+    // Two tracks, noise from a fixed seed and a unison note, at a sample rate other than the
+    // default
+    #[test]
+    fn test_golden_noise_and_unison() {
+        let script = r#"
+            FixedTimeNoteSequence dur Sixteenth tempo 60 num_steps 2
+            a 0.0,1.0 d 0.1,0.4 s 0.6,0.0 r 1.0,0.0
+            osc:pink:440.0:0.4:0
+            osc:white:440.0:0.2:1
+
+            FixedTimeNoteSequence dur Eighth tempo 60 num_steps 1
+            a 0.1,1.0 d 0.3,0.8 s 0.8,0.8 r 1.0,0.0
+            unison 5 detune 15 spread 0.8
+            osc:saw:3,C:0.3:0
+        "#;
+        let render_config = RenderConfigBuilder::default()
+            .sample_rate(48000.0)
+            .seed(Some(7))
+            .build().unwrap();
+        assert_golden("noise_and_unison", &render_dsl(script, render_config), &render_config);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::audio_gen::audio_gen::AudioFile;
+use crate::audio_gen::resample::{blackman, sinc};
+use crate::common::decibels::{db_to_gain, gain_to_db};
+use crate::common::stereo::StereoFrame;
+
+// Gating blocks of the integrated loudness measurement, 400 ms overlapping by 75%
+static BLOCK_MS: f64 = 400.0;
+static BLOCK_STEP_MS: f64 = 100.0;
+// Blocks quieter than this are silence and don't count towards the loudness
+static ABSOLUTE_GATE_LUFS: f64 = -70.0;
+// Blocks this far below the loudness of the blocks passing the absolute gate don't count either
+static RELATIVE_GATE_LU: f64 = -10.0;
+// Offset that makes a 1 kHz sine read the same in LUFS as its RMS level in dBFS
+static LOUDNESS_OFFSET: f64 = -0.691;
+// Peaks between samples are found by upsampling 44.1 and 48 kHz at least this many times, with
+// an interpolation filter reaching this many samples either side
+static TRUE_PEAK_OVERSAMPLING: f32 = 4.0;
+static TRUE_PEAK_HALF_WIDTH: i64 = 12;
+
+// Levels of a render or WAV file. Peaks and RMS are in dBFS, with silence at negative infinity
+// sample_peak: loudest sample
+// true_peak: loudest point of the reconstructed waveform, which can be above the sample peak
+// rms: average power across all channels
+// integrated_lufs: gated loudness of the whole piece, per EBU R128 and ITU-R BS.1770
+// dc_offset: mean of each channel, ideally 0.0
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LoudnessReport {
+    pub(crate) sample_peak_db: f32,
+    pub(crate) true_peak_db: f32,
+    pub(crate) rms_db: f32,
+    pub(crate) integrated_lufs: f32,
+    pub(crate) dc_offset: Vec<f32>,
+}
+
+#[allow(dead_code)]
+pub(crate) fn analyze_frames(frames: &[StereoFrame], sample_rate: f32) -> LoudnessReport {
+    let left = frames.iter().map(|frame| frame.left).collect();
+    let right = frames.iter().map(|frame| frame.right).collect();
+    analyze_channels(&[left, right], sample_rate)
+}
+
+#[allow(dead_code)]
+pub(crate) fn analyze_audio_file(audio_file: &AudioFile) -> LoudnessReport {
+    analyze_channels(&audio_file.channels, audio_file.sample_rate)
+}
+
+#[allow(dead_code)]
+pub(crate) fn analyze_channels(channels: &[Vec<f32>], sample_rate: f32) -> LoudnessReport {
+    let num_samples: usize = channels.iter().map(|channel| channel.len()).sum();
+    let sum_of_squares: f64 = channels.iter().flatten()
+        .map(|sample| (*sample as f64) * (*sample as f64))
+        .sum();
+
+    LoudnessReport {
+        sample_peak_db: gain_to_db(sample_peak(channels)),
+        true_peak_db: gain_to_db(true_peak(channels, sample_rate)),
+        rms_db: gain_to_db((sum_of_squares / num_samples.max(1) as f64).sqrt() as f32),
+        integrated_lufs: integrated_lufs(channels, sample_rate),
+        dc_offset: channels.iter()
+            .map(|channel| {
+                let sum: f64 = channel.iter().map(|sample| *sample as f64).sum();
+                (sum / channel.len().max(1) as f64) as f32
+            })
+            .collect(),
+    }
+}
+
+// Gain that brings frames to target_lufs, 1.0 for silence
+pub(crate) fn normalization_gain(frames: &[StereoFrame], sample_rate: f32,
+                                 target_lufs: f32) -> f32 {
+    let integrated_lufs = analyze_frames(frames, sample_rate).integrated_lufs;
+    if integrated_lufs.is_finite() {
+        db_to_gain(target_lufs - integrated_lufs)
+    } else {
+        1.0
+    }
+}
+
+fn sample_peak(channels: &[Vec<f32>]) -> f32 {
+    channels.iter().flatten().fold(0.0, |peak, sample| peak.max(sample.abs()))
+}
+
+// Peak of the channels upsampled to at least 176.4 kHz, so the peaks between samples show up.
+// Each point between two samples is interpolated with its own windowed sinc kernel, computed once
+fn true_peak(channels: &[Vec<f32>], sample_rate: f32) -> f32 {
+    let oversampling = (TRUE_PEAK_OVERSAMPLING * 44100.0 / sample_rate).ceil().max(1.0) as usize;
+    let kernels: Vec<Vec<f32>> = (1..oversampling)
+        .map(|point| {
+            let offset = point as f64 / oversampling as f64;
+            (-TRUE_PEAK_HALF_WIDTH + 1..=TRUE_PEAK_HALF_WIDTH)
+                .map(|tap| {
+                    let distance = offset - tap as f64;
+                    (sinc(distance) * blackman(distance / TRUE_PEAK_HALF_WIDTH as f64)) as f32
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut peak = sample_peak(channels);
+    let half_width = TRUE_PEAK_HALF_WIDTH as usize;
+    for channel in channels {
+        // silence either side, so the kernels reach past the ends
+        let mut padded = vec![0.0; half_width - 1];
+        padded.extend_from_slice(channel);
+        padded.extend(vec![0.0; half_width]);
+        for window in padded.windows(2 * half_width) {
+            for kernel in kernels.iter() {
+                let interpolated: f32 = kernel.iter().zip(window)
+                    .map(|(coefficient, sample)| coefficient * sample)
+                    .sum();
+                peak = peak.max(interpolated.abs());
+            }
+        }
+    }
+    peak
+}
+
+// Mean square of each channel after K-weighting, over 400 ms blocks, and then gated
+fn integrated_lufs(channels: &[Vec<f32>], sample_rate: f32) -> f32 {
+    let weighted: Vec<Vec<f64>> = channels.iter()
+        .map(|channel| k_weight(channel, sample_rate as f64))
+        .collect();
+    let num_samples = weighted.iter().map(|channel| channel.len()).min().unwrap_or(0);
+    let block_samples = (BLOCK_MS * sample_rate as f64 / 1000.0).round() as usize;
+    let step_samples = (BLOCK_STEP_MS * sample_rate as f64 / 1000.0).round() as usize;
+    if block_samples == 0 || num_samples < block_samples {
+        return f32::NEG_INFINITY;
+    }
+
+    // each block's power summed across channels
+    let block_powers: Vec<f64> = (0..=(num_samples - block_samples) / step_samples)
+        .map(|block| {
+            let start = block * step_samples;
+            weighted.iter()
+                .map(|channel| channel[start..start + block_samples].iter()
+                    .map(|sample| sample * sample)
+                    .sum::<f64>() / block_samples as f64)
+                .sum()
+        })
+        .collect();
+
+    let loudness = |power: f64| LOUDNESS_OFFSET + 10.0 * power.log10();
+    let mean_power = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+    let above_absolute_gate: Vec<f64> = block_powers.into_iter()
+        .filter(|power| loudness(*power) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if above_absolute_gate.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let relative_gate = loudness(mean_power(&above_absolute_gate)) + RELATIVE_GATE_LU;
+    let above_relative_gate: Vec<f64> = above_absolute_gate.into_iter()
+        .filter(|power| loudness(*power) > relative_gate)
+        .collect();
+    loudness(mean_power(&above_relative_gate)) as f32
+}
+
+// The K-weighting of BS.1770, a high shelf for the head's boost of high frequencies followed
+// by a high-pass that discounts the lowest bass. The filters are derived for the sample rate,
+// rather than using the standard's 48 kHz coefficients
+fn k_weight(samples: &[f32], sample_rate: f64) -> Vec<f64> {
+    let mut shelf = {
+        let k = (std::f64::consts::PI * 1681.974450955533 / sample_rate).tan();
+        let q = 0.7071752369554196;
+        let vh = 10.0f64.powf(3.999843853973347 / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::new([(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0,
+                     (vh - vb * k / q + k * k) / a0],
+                    [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0])
+    };
+    let mut high_pass = {
+        let k = (std::f64::consts::PI * 38.13547087602444 / sample_rate).tan();
+        let q = 0.5003270373238773;
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0])
+    };
+    samples.iter().map(|sample| high_pass.apply(shelf.apply(*sample as f64))).collect()
+}
+
+// Direct form I biquad, with a0 normalized to 1.0
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    inputs: [f64; 2],
+    outputs: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Biquad {
+        Biquad { b, a, inputs: [0.0; 2], outputs: [0.0; 2] }
+    }
+
+    fn apply(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.inputs[0] + self.b[2] * self.inputs[1] -
+            self.a[0] * self.outputs[0] - self.a[1] * self.outputs[1];
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+#[cfg(test)]
+mod test_loudness {
+    use crate::audio_gen::loudness::{analyze_channels, analyze_frames, normalization_gain};
+    use crate::common::decibels::db_to_gain;
+    use crate::common::stereo::StereoFrame;
+
+    fn sine(amplitude: f32, frequency: f32, sample_rate: f32, seconds: f32) -> Vec<f32> {
+        (0..(seconds * sample_rate) as usize)
+            .map(|i| amplitude *
+                (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_reference_sine() {
+        // EBU Tech 3341 case 1, a 1 kHz sine at -23 dBFS in both channels reads -23 LUFS
+        for sample_rate in [44100.0, 48000.0] {
+            let channel = sine(db_to_gain(-23.0), 1000.0, sample_rate, 1.0);
+            let report = analyze_channels(&[channel.clone(), channel], sample_rate);
+            assert!((report.integrated_lufs + 23.0).abs() < 0.1, "{}", report.integrated_lufs);
+            assert!((report.sample_peak_db + 23.0).abs() < 0.01);
+            assert!((report.rms_db + 26.01).abs() < 0.01);
+            assert!(report.dc_offset.iter().all(|offset| offset.abs() < 1e-4));
+        }
+    }
+
+    #[test]
+    fn test_gating() {
+        // silence before and after doesn't lower the loudness, and a quiet passage far below it
+        // only does through the few blocks that straddle its edges
+        let sample_rate = 48000.0;
+        let mut channel = vec![0.0; 24000];
+        channel.extend(sine(db_to_gain(-23.0), 1000.0, sample_rate, 3.0));
+        channel.extend(sine(db_to_gain(-50.0), 1000.0, sample_rate, 1.0));
+        channel.extend(vec![0.0; 24000]);
+        let report = analyze_channels(&[channel.clone(), channel], sample_rate);
+        assert!((report.integrated_lufs + 23.0).abs() < 0.5, "{}", report.integrated_lufs);
+
+        let silence = analyze_channels(&[vec![0.0; 48000]], sample_rate);
+        assert_eq!(silence.integrated_lufs, f32::NEG_INFINITY);
+        assert_eq!(silence.sample_peak_db, f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_true_peak_and_dc_offset() {
+        // a sine at a quarter of the sample rate sampled 45 degrees off its peaks, so every
+        // sample is 3 dB below the true peak
+        let sample_rate = 48000.0;
+        let channel: Vec<f32> = (0..4800)
+            .map(|i| (std::f32::consts::PI * (0.5 * i as f32 + 0.25)).sin() + 0.1)
+            .collect();
+        let report = analyze_channels(&[channel], sample_rate);
+        let sample_peak = std::f32::consts::FRAC_1_SQRT_2 + 0.1;
+        assert!((report.sample_peak_db - 20.0 * sample_peak.log10()).abs() < 0.01);
+        // the abrupt start and end of the signal ring a little past its true peak
+        assert!((report.true_peak_db - 20.0 * 1.1f32.log10()).abs() < 0.2,
+                "{}", report.true_peak_db);
+        assert!((report.dc_offset[0] - 0.1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_normalization_gain() {
+        let sample_rate = 44100.0;
+        let frames: Vec<StereoFrame> = sine(0.5, 440.0, sample_rate, 3.0).into_iter()
+            .map(StereoFrame::from_mono)
+            .collect();
+        let gain = normalization_gain(&frames, sample_rate, -16.0);
+        let frames: Vec<StereoFrame> = frames.into_iter().map(|frame| frame * gain).collect();
+        let integrated_lufs = analyze_frames(&frames, sample_rate).integrated_lufs;
+        assert!((integrated_lufs + 16.0).abs() < 0.01, "{}", integrated_lufs);
+
+        let silence = vec![StereoFrame::default(); 44100];
+        assert_eq!(normalization_gain(&silence, sample_rate, -16.0), 1.0);
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::VecDeque;
+
+use derive_builder::Builder;
+
+use crate::audio_gen::loudness::normalization_gain;
+use crate::common::decibels::db_to_gain;
+use crate::common::render_config::RenderConfig;
+use crate::common::stereo::StereoFrame;
+
+// Just under full scale, leaving room for the peaks between samples that a DAC reconstructs
+static DEFAULT_CEILING_DB: f32 = -0.3;
+static DEFAULT_LOOKAHEAD_MS: f32 = 5.0;
+static DEFAULT_RELEASE_MS: f32 = 100.0;
+// The soft clipper is linear up to this level and bends the rest into what is left below 1.0
+static SOFT_CLIP_KNEE: f32 = 0.8;
+
+// Counts of the samples of a render, per channel, that went over full scale
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ClipReport {
+    pub(crate) num_samples: usize,
+    // over 1.0 after the bus gain, before the soft clipper and limiter
+    pub(crate) num_overs: usize,
+    // still over 1.0 at the output, and hard clipped
+    pub(crate) num_clipped: usize,
+    pub(crate) peak_in: f32,
+    pub(crate) peak_out: f32,
+}
+
+// The last stage of a render, applied to the mix of every track. Applies gain, then optionally
+// soft clips, then holds the peaks under the ceiling with a brickwall limiter that looks ahead so
+// it is already turned down when a peak arrives. Anything still over full scale is hard clipped
+// and counted in the clip report
+#[allow(dead_code)]
+#[derive(Builder, Clone, Debug, PartialEq)]
+pub(crate) struct MasterBus {
+    #[builder(default = "0.0")]
+    pub(crate) gain_db: f32,
+
+    // loudness in LUFS offline renders are brought to before they are limited, in place of
+    // gain_db. Streamed playback can't measure ahead and uses gain_db
+    #[builder(default = "None")]
+    pub(crate) target_lufs: Option<f32>,
+
+    #[builder(default = "false")]
+    pub(crate) soft_clip: bool,
+
+    #[builder(default = "true")]
+    pub(crate) limiter: bool,
+
+    // level in dBFS the limiter holds peaks under
+    #[builder(default = "DEFAULT_CEILING_DB")]
+    pub(crate) ceiling_db: f32,
+
+    // how far ahead the limiter reads, which is also the latency it adds
+    #[builder(default = "DEFAULT_LOOKAHEAD_MS")]
+    pub(crate) lookahead_ms: f32,
+
+    // time the limiter takes to recover most of the way after a peak
+    #[builder(default = "DEFAULT_RELEASE_MS")]
+    pub(crate) release_ms: f32,
+
+    #[builder(default = "RenderConfig::default()")]
+    pub(crate) render_config: RenderConfig,
+
+    // frames waiting out the lookahead
+    #[builder(default = "VecDeque::new()", setter(skip))]
+    delay_line: VecDeque<StereoFrame>,
+    // gain each waiting frame needs to stay under the ceiling, after release
+    #[builder(default = "VecDeque::new()", setter(skip))]
+    release_gains: VecDeque<f32>,
+    #[builder(default = "1.0", setter(skip))]
+    release_gain: f32,
+    // the last lookahead of min-held gains, averaged to smooth the gain into each peak
+    #[builder(default = "VecDeque::new()", setter(skip))]
+    held_gains: VecDeque<f32>,
+
+    #[builder(default = "ClipReport::default()", setter(skip))]
+    clip_report: ClipReport,
+
+    // gain that brings the render to target_lufs, applied with gain_db
+    #[builder(default = "1.0", setter(skip))]
+    normalization_gain: f32,
+}
+
+#[allow(dead_code)]
+pub(crate) fn default_master_bus() -> MasterBus {
+    MasterBusBuilder::default().build().unwrap()
+}
+
+#[allow(dead_code)]
+impl MasterBus {
+    // Frames the bus delays its output by
+    pub(crate) fn latency(&self) -> usize {
+        if self.limiter {
+            self.lookahead_samples() - 1
+        } else {
+            0
+        }
+    }
+
+    fn lookahead_samples(&self) -> usize {
+        ((self.lookahead_ms * self.render_config.samples_per_ms()).round() as usize).max(1)
+    }
+
+    pub(crate) fn clip_report(&self) -> ClipReport {
+        self.clip_report
+    }
+
+    // Runs one frame through the bus. With the limiter on, the frame returned is the one passed
+    // latency() frames ago
+    pub(crate) fn process(&mut self, frame: StereoFrame) -> StereoFrame {
+        let gain = db_to_gain(self.gain_db) * self.normalization_gain;
+        let mut frame = frame * gain;
+        for sample in [frame.left, frame.right] {
+            self.clip_report.num_samples += 1;
+            self.clip_report.peak_in = self.clip_report.peak_in.max(sample.abs());
+            if sample.abs() > 1.0 {
+                self.clip_report.num_overs += 1;
+            }
+        }
+
+        if self.soft_clip {
+            frame = frame.map(soft_clip);
+        }
+        if self.limiter {
+            frame = self.limit(frame);
+        }
+
+        for sample in [frame.left, frame.right] {
+            self.clip_report.peak_out = self.clip_report.peak_out.max(sample.abs());
+            if sample.abs() > 1.0 {
+                self.clip_report.num_clipped += 1;
+            }
+        }
+        frame.map(|sample| sample.clamp(-1.0, 1.0))
+    }
+
+    // Offline processing of a whole render. The latency is compensated, so the frames returned
+    // line up with the frames passed in
+    pub(crate) fn process_buffer(&mut self, frames: &[StereoFrame]) -> Vec<StereoFrame> {
+        if let Some(target_lufs) = self.target_lufs {
+            self.normalization_gain = normalization_gain(frames, self.render_config.sample_rate,
+                                                         target_lufs) / db_to_gain(self.gain_db);
+        }
+        let latency = self.latency();
+        let mut output: Vec<StereoFrame> = frames.iter()
+            .map(|frame| self.process(*frame))
+            .collect();
+        output.extend(self.flush());
+        output.drain(..latency.min(output.len()));
+        output
+    }
+
+    // Frames still in the lookahead at the end of a stream, pushed out by silence
+    pub(crate) fn flush(&mut self) -> Vec<StereoFrame> {
+        (0..self.latency()).map(|_| self.process(StereoFrame::default())).collect()
+    }
+
+    // The gain each frame needs is held at its minimum across the lookahead, so every frame in
+    // the delay line is covered, and then averaged across the lookahead, so the gain ramps down
+    // into a peak rather than stepping down on it. Every average that lands on a frame is of
+    // gains no higher than the frame needs, so the frame comes out under the ceiling
+    fn limit(&mut self, frame: StereoFrame) -> StereoFrame {
+        let lookahead_samples = self.lookahead_samples();
+        let ceiling = db_to_gain(self.ceiling_db);
+        let peak = frame.left.abs().max(frame.right.abs());
+        let required_gain = if peak > ceiling { ceiling / peak } else { 1.0 };
+
+        // release recovers towards the required gain, and falls to it immediately
+        let release_samples = (self.release_ms * self.render_config.samples_per_ms()).max(1.0);
+        let release_coefficient = 1.0 - (-1.0 / release_samples).exp();
+        self.release_gain += (required_gain - self.release_gain) * release_coefficient;
+        self.release_gain = self.release_gain.min(required_gain);
+
+        self.release_gains.push_back(self.release_gain);
+        while self.release_gains.len() > lookahead_samples {
+            self.release_gains.pop_front();
+        }
+        let held_gain = self.release_gains.iter().copied().fold(1.0, f32::min);
+
+        self.held_gains.push_back(held_gain);
+        while self.held_gains.len() > lookahead_samples {
+            self.held_gains.pop_front();
+        }
+
+        // silence until the lookahead fills
+        self.delay_line.push_back(frame);
+        if self.delay_line.len() < lookahead_samples {
+            return StereoFrame::default();
+        }
+        // summed afresh each time, as a running sum drifts far enough to let peaks through
+        let smoothed_gain = self.held_gains.iter().sum::<f32>() / lookahead_samples as f32;
+        self.delay_line.pop_front().unwrap() * smoothed_gain
+    }
+}
+
+// Linear below the knee and saturating smoothly towards 1.0 above it
+fn soft_clip(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude <= SOFT_CLIP_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - SOFT_CLIP_KNEE;
+    let saturated = headroom * ((magnitude - SOFT_CLIP_KNEE) / headroom).tanh();
+    sample.signum() * (SOFT_CLIP_KNEE + saturated)
+}
+
+#[cfg(test)]
+mod test_master_bus {
+    use crate::audio_gen::loudness::analyze_frames;
+    use crate::common::decibels::db_to_gain;
+    use crate::common::stereo::StereoFrame;
+    use crate::audio_gen::master_bus::{soft_clip, MasterBusBuilder};
+
+    // A sine that swells from quiet to well over full scale and back
+    fn swelling_sine() -> Vec<StereoFrame> {
+        (0..44100)
+            .map(|i| {
+                let swell = 3.0 * (std::f32::consts::PI * i as f32 / 44100.0).sin();
+                let phase = 2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0;
+                let sample = swell * phase.sin();
+                StereoFrame::new(sample, 0.5 * sample)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_limiter_holds_ceiling() {
+        let mut master_bus = MasterBusBuilder::default().build().unwrap();
+        let input = swelling_sine();
+        let output = master_bus.process_buffer(&input);
+        assert_eq!(output.len(), input.len());
+
+        let ceiling = db_to_gain(-0.3);
+        assert!(output.iter().all(|frame| frame.left.abs() <= ceiling + 1e-6));
+        let report = master_bus.clip_report();
+        assert!(report.num_overs > 0);
+        assert_eq!(report.num_clipped, 0);
+        assert!(report.peak_in > 2.9);
+        assert!(report.peak_out <= ceiling + 1e-6);
+
+        // quiet passages at the start come through untouched and in time
+        for i in 0..1000 {
+            assert!((output[i].left - input[i].left).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_clip_report_without_limiter() {
+        let mut master_bus = MasterBusBuilder::default()
+            .limiter(false)
+            .gain_db(-6.0)
+            .build().unwrap();
+        assert_eq!(master_bus.latency(), 0);
+        let output = master_bus.process_buffer(&[StereoFrame::new(1.5, 0.5),
+                                                 StereoFrame::new(4.0, -4.0)]);
+        assert_eq!(output[1], StereoFrame::new(1.0, -1.0));
+        let report = master_bus.clip_report();
+        assert_eq!(report.num_samples, 4);
+        assert_eq!(report.num_overs, 2);
+        assert_eq!(report.num_clipped, 2);
+        assert!((output[0].left - 1.5 * db_to_gain(-6.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_target_lufs() {
+        let input: Vec<StereoFrame> = swelling_sine().iter().map(|frame| *frame * 0.01).collect();
+        let mut master_bus = MasterBusBuilder::default()
+            .gain_db(12.0)
+            .target_lufs(Some(-20.0))
+            .build().unwrap();
+        let output = master_bus.process_buffer(&input);
+        let integrated_lufs = analyze_frames(&output, 44100.0).integrated_lufs;
+        assert!((integrated_lufs + 20.0).abs() < 0.01, "{}", integrated_lufs);
+    }
+
+    #[test]
+    fn test_soft_clip() {
+        assert_eq!(soft_clip(0.5), 0.5);
+        assert_eq!(soft_clip(-0.8), -0.8);
+        assert!(soft_clip(0.9) < 0.9 && soft_clip(0.9) > 0.8);
+        assert!(soft_clip(10.0) <= 1.0);
+        assert_eq!(soft_clip(-10.0), -soft_clip(10.0));
+    }
+}
@@ -1,3 +1,12 @@
 pub mod audio_gen;
+pub mod band_limit;
 pub mod get_sample;
+#[cfg(test)]
+pub mod golden;
+pub mod loudness;
+pub mod master_bus;
+pub mod noise;
 pub mod oscillator;
+pub mod resample;
+pub mod sample_format;
+pub mod wavetable;
@@ -0,0 +1,201 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+// Samples run through a generator's pink and brown filters before its first pink or brown
+// sample, so the filters start from a typical state rather than from silence, which would dip
+// at the start of every note. Generators that never play pink or brown noise skip it
+static WARM_UP_SAMPLES: usize = 1024;
+
+// Output scaling that brings pink and brown noise to roughly the same loudness as white noise
+static PINK_GAIN: f32 = 0.11;
+static BROWN_GAIN: f32 = 3.5;
+// How much of the previous brown sample leaks away each sample, so the walk stays near 0.0
+static BROWN_LEAK: f32 = 1.02;
+static BROWN_STEP: f32 = 0.02;
+
+// Source of noise for one voice. Each voice owns its generator, so voices don't share a random
+// stream and a render seeded with the same value produces the same noise every time
+// White: uniform in -1.0 to 1.0, equal energy per Hz
+// Pink: white filtered to fall 3 dB per octave, equal energy per octave
+// Brown: leaky integral of white, falls 6 dB per octave
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct NoiseGenerator {
+    rng: StdRng,
+    // state of Paul Kellet's pink noise filter, a sum of one-pole filters of the white noise
+    pink_state: [f32; 7],
+    brown_state: f32,
+    is_warmed_up: bool,
+}
+
+// Seeded with 0, so notes built the same way are equal. The TrackGrid re-seeds each voice from
+// the render seed
+impl Default for NoiseGenerator {
+    fn default() -> Self {
+        NoiseGenerator::from_seed(0)
+    }
+}
+
+#[allow(dead_code)]
+impl NoiseGenerator {
+    pub(crate) fn from_seed(seed: u64) -> NoiseGenerator {
+        NoiseGenerator::from_rng(StdRng::seed_from_u64(seed))
+    }
+
+    // Unseeded, different on every run
+    pub(crate) fn from_entropy() -> NoiseGenerator {
+        NoiseGenerator::from_rng(StdRng::from_os_rng())
+    }
+
+    // Generator for one voice of a render. With a seed, the voice's stream is derived from the
+    // seed and the values identifying the voice, so it is reproducible and distinct from every
+    // other voice. Without one it comes from entropy
+    pub(crate) fn for_voice(seed: Option<u64>, voice: &[u64]) -> NoiseGenerator {
+        match seed {
+            Some(seed) => NoiseGenerator::from_seed(
+                voice.iter().fold(seed, |mixed, value| split_mix(mixed ^ value))),
+            None => NoiseGenerator::from_entropy(),
+        }
+    }
+
+    fn from_rng(rng: StdRng) -> NoiseGenerator {
+        NoiseGenerator {
+            rng,
+            pink_state: [0.0; 7],
+            brown_state: 0.0,
+            is_warmed_up: false,
+        }
+    }
+
+    pub(crate) fn white(&mut self) -> f32 {
+        self.rng.random_range(-1.0..1.0)
+    }
+
+    // Normally distributed, with a standard deviation of 1.0
+    pub(crate) fn gaussian(&mut self) -> f32 {
+        self.rng.sample(StandardNormal)
+    }
+
+    pub(crate) fn pink(&mut self) -> f32 {
+        self.warm_up();
+        self.next_pink()
+    }
+
+    pub(crate) fn brown(&mut self) -> f32 {
+        self.warm_up();
+        self.next_brown()
+    }
+
+    fn warm_up(&mut self) {
+        if self.is_warmed_up {
+            return;
+        }
+        self.is_warmed_up = true;
+        for _ in 0..WARM_UP_SAMPLES {
+            self.next_pink();
+            self.next_brown();
+        }
+    }
+
+    fn next_pink(&mut self) -> f32 {
+        let white = self.white();
+        let b = &mut self.pink_state;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.153852;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        PINK_GAIN * pink
+    }
+
+    fn next_brown(&mut self) -> f32 {
+        let white = self.white();
+        self.brown_state = (self.brown_state + BROWN_STEP * white) / BROWN_LEAK;
+        BROWN_GAIN * self.brown_state
+    }
+}
+
+// SplitMix64 finalizer, spreads similar inputs such as consecutive track indexes far apart
+fn split_mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test_noise {
+    use crate::audio_gen::noise::NoiseGenerator;
+
+    static NUM_SAMPLES: usize = 44100;
+
+    fn samples(noise: impl FnMut() -> f32) -> Vec<f32> {
+        std::iter::repeat_with(noise).take(NUM_SAMPLES).collect()
+    }
+
+    // Fraction of the signal's energy in its first difference. Noise with more high frequency
+    // energy changes more from one sample to the next
+    fn high_frequency_ratio(samples: &[f32]) -> f32 {
+        let energy: f32 = samples.iter().map(|sample| sample * sample).sum();
+        let difference_energy: f32 = samples.windows(2)
+            .map(|pair| (pair[1] - pair[0]) * (pair[1] - pair[0]))
+            .sum();
+        difference_energy / energy
+    }
+
+    #[test]
+    fn test_seed_is_reproducible() {
+        let mut first = NoiseGenerator::from_seed(7);
+        let mut second = NoiseGenerator::from_seed(7);
+        let mut other = NoiseGenerator::from_seed(8);
+        let first_samples = samples(|| first.pink());
+        assert_eq!(first_samples, samples(|| second.pink()));
+        assert_ne!(first_samples, samples(|| other.pink()));
+
+        let voice = NoiseGenerator::for_voice(Some(7), &[0, 1]);
+        assert_eq!(voice, NoiseGenerator::for_voice(Some(7), &[0, 1]));
+        assert_ne!(voice, NoiseGenerator::for_voice(Some(7), &[1, 0]));
+    }
+
+    #[test]
+    fn test_white_noise() {
+        let mut noise = NoiseGenerator::from_seed(1);
+        let white = samples(|| noise.white());
+        assert!(white.iter().all(|sample| (-1.0..1.0).contains(sample)));
+        let mean = white.iter().sum::<f32>() / NUM_SAMPLES as f32;
+        assert!(mean.abs() < 0.02);
+    }
+
+    #[test]
+    fn test_warm_up_on_first_pink_or_brown_sample() {
+        let mut noise = NoiseGenerator::from_seed(3);
+        noise.white();
+        noise.gaussian();
+        assert!(!noise.is_warmed_up);
+        let mut brown_noise = noise.clone();
+        brown_noise.brown();
+        assert!(brown_noise.is_warmed_up);
+        noise.pink();
+        assert!(noise.is_warmed_up);
+    }
+
+    #[test]
+    fn test_noise_colors() {
+        let mut noise = NoiseGenerator::from_seed(1);
+        let white = samples(|| noise.white());
+        let pink = samples(|| noise.pink());
+        let brown = samples(|| noise.brown());
+
+        // white noise's first difference has twice its energy, pink and brown are progressively
+        // weighted towards low frequencies
+        assert!((high_frequency_ratio(&white) - 2.0).abs() < 0.1);
+        assert!(high_frequency_ratio(&pink) < high_frequency_ratio(&white) / 2.0);
+        assert!(high_frequency_ratio(&brown) < high_frequency_ratio(&pink) / 2.0);
+        for colored in [&pink, &brown] {
+            assert!(colored.iter().all(|sample| sample.abs() < 1.5));
+        }
+    }
+}
@@ -1,21 +1,97 @@
-use rand::thread_rng;
-use rand_distr::{Distribution, Normal};
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
-use crate::common::constants::SAMPLE_RATE;
+use crate::audio_gen::band_limit;
+use crate::audio_gen::band_limit::{BandLimit, MipMapTables};
+use crate::audio_gen::noise::NoiseGenerator;
+use crate::audio_gen::wavetable;
+use crate::audio_gen::wavetable::{Wavetable, WavetableId};
+use crate::common::render_config::RenderConfig;
 
 static TWO_PI: f32 = 2.0 * std::f32::consts::PI;
 static NUM_TABLE_SAMPLES: usize = 1024;
-static SAMPLE_COUNT_FACTOR: f32 = SAMPLE_RATE / NUM_TABLE_SAMPLES as f32;
+
+// Pulse widths are kept this far from 0.0 and 1.0, where the pulse would disappear into silence
+pub(crate) static MIN_PULSE_WIDTH: f32 = 0.01;
+pub(crate) static DEFAULT_PULSE_WIDTH: f32 = 0.5;
 
 #[allow(dead_code)]
-#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) enum Waveform {
+    BrownNoise,
     GaussianNoise,
-    Saw,
+    PinkNoise,
+    // the f32 is the duty cycle, the fraction of each cycle the pulse is high
+    Pulse(BandLimit, f32),
+    Saw(BandLimit),
     Sine,
-    Square,
-    Triangle,
+    Square(BandLimit),
+    Triangle(BandLimit),
+    // a registered wavetable, and the position from 0.0 to 1.0 across its frames
+    Wavetable(WavetableId, f32),
+    WhiteNoise,
+}
+
+impl Hash for Waveform {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Waveform::Pulse(band_limit, width) => {
+                band_limit.hash(state);
+                width.to_bits().hash(state);
+            }
+            Waveform::Wavetable(id, position) => {
+                id.hash(state);
+                position.to_bits().hash(state);
+            }
+            Waveform::Saw(band_limit) | Waveform::Square(band_limit) |
+            Waveform::Triangle(band_limit) => band_limit.hash(state),
+            Waveform::BrownNoise | Waveform::GaussianNoise | Waveform::PinkNoise |
+            Waveform::Sine | Waveform::WhiteNoise => {}
+        }
+    }
+}
+
+// How table reads between two entries are computed
+// Linear: straight line between the two nearest entries
+// Cubic: Catmull-Rom spline through the four nearest entries, smoother at the cost of 4 reads
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum TableInterpolation {
+    #[default]
+    Linear,
+    Cubic,
+}
+
+// Position in a waveform's cycle, from 0.0 to 1.0, advanced by frequency / sample_rate each
+// sample. Kept in f64 and wrapped every cycle so it is as accurate ten minutes into a render as
+// at the start, and the frequency can change between samples without the phase jumping
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct PhaseAccumulator {
+    phase: f64,
+}
+
+#[allow(dead_code)]
+impl PhaseAccumulator {
+    pub(crate) fn new(phase: f64) -> PhaseAccumulator {
+        PhaseAccumulator { phase: phase.rem_euclid(1.0) }
+    }
+
+    // Phase of a constant frequency elapsed_ms after it started at phase 0.0
+    pub(crate) fn at_elapsed_ms(frequency: f32, elapsed_ms: f32) -> PhaseAccumulator {
+        PhaseAccumulator::new(frequency as f64 * elapsed_ms.max(0.0) as f64 / 1000.0)
+    }
+
+    pub(crate) fn phase(&self) -> f64 {
+        self.phase
+    }
+
+    // Returns the phase of the current sample and advances to the next one
+    pub(crate) fn next_phase(&mut self, frequency: f32, sample_rate: f32) -> f64 {
+        let phase = self.phase;
+        self.phase = (self.phase + frequency as f64 / sample_rate as f64).rem_euclid(1.0);
+        phase
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -24,17 +100,103 @@ pub(crate) struct OscillatorTables {
     pub(crate) saw_table: Arc<Vec<f32>>,
     pub(crate) square_table: Arc<Vec<f32>>,
     pub(crate) triangle_table: Arc<Vec<f32>>,
+    pub(crate) mip_map_tables: Arc<MipMapTables>,
+    pub(crate) render_config: RenderConfig,
 }
 
 impl OscillatorTables {
+    #[allow(dead_code)]
     pub(crate) fn new() -> OscillatorTables {
+        OscillatorTables::with_render_config(RenderConfig::default())
+    }
+
+    pub(crate) fn with_render_config(render_config: RenderConfig) -> OscillatorTables {
         OscillatorTables {
             sine_table: Arc::new(generate_sine_table()),
             saw_table: Arc::new(generate_saw_table()),
             square_table: Arc::new(generate_square_table()),
             triangle_table: Arc::new(generate_triangle_table()),
+            mip_map_tables: MipMapTables::for_sample_rate(render_config.sample_rate),
+            render_config,
         }
     }
+
+    pub(crate) fn sample_rate(&self) -> f32 {
+        self.render_config.sample_rate
+    }
+
+    // Sample of waveform at frequency, at a phase from 0.0 to 1.0 into its cycle. Noise
+    // waveforms ignore both and draw from the voice's noise generator
+    pub(crate) fn get_waveform_sample(&self, waveform: Waveform, frequency: f32, phase: f64,
+                                      noise: &mut NoiseGenerator) -> f32 {
+        match waveform {
+            Waveform::BrownNoise => noise.brown(),
+            Waveform::GaussianNoise => noise.gaussian(),
+            Waveform::PinkNoise => noise.pink(),
+            Waveform::WhiteNoise => noise.white(),
+            Waveform::Pulse(band_limit, width) =>
+                self.get_pulse_sample(band_limit, width, frequency, phase),
+            Waveform::Sine => self.get_table_sample(&self.sine_table, phase),
+            Waveform::Saw(band_limit) => self.get_band_limited_sample(
+                band_limit, &self.saw_table, &self.mip_map_tables.saw,
+                band_limit::poly_blep_saw, frequency, phase),
+            Waveform::Square(band_limit) => self.get_band_limited_sample(
+                band_limit, &self.square_table, &self.mip_map_tables.square,
+                band_limit::poly_blep_square, frequency, phase),
+            Waveform::Triangle(band_limit) => self.get_band_limited_sample(
+                band_limit, &self.triangle_table, &self.mip_map_tables.triangle,
+                band_limit::poly_blep_triangle, frequency, phase),
+            Waveform::Wavetable(id, position) =>
+                self.get_wavetable_sample(&wavetable::get_wavetable(id), position, phase),
+        }
+    }
+
+    pub(crate) fn get_wavetable_sample(&self, wavetable: &Wavetable, position: f32,
+                                       phase: f64) -> f32 {
+        wavetable.sample(position, phase, self.render_config.table_interpolation)
+    }
+
+    pub(crate) fn get_sine_sample(&self, phase: f64) -> f32 {
+        self.get_table_sample(&self.sine_table, phase)
+    }
+
+    fn get_band_limited_sample(&self, band_limit: BandLimit, naive_table: &[f32],
+                               mip_map_tables: &[Vec<f32>], poly_blep: fn(f64, f64) -> f32,
+                               frequency: f32, phase: f64) -> f32 {
+        match band_limit {
+            BandLimit::Naive => self.get_table_sample(naive_table, phase),
+            BandLimit::MipMap => self.get_table_sample(
+                &mip_map_tables[self.mip_map_tables.table_index(frequency)], phase),
+            BandLimit::PolyBlep => {
+                let dt = frequency as f64 / self.sample_rate() as f64;
+                poly_blep(phase, dt.min(0.5))
+            }
+        }
+    }
+
+    // The naive and PolyBLEP pulses are computed directly. The mip-mapped pulse is the difference
+    // of two band-limited saws offset by the width, which has the pulse's edges and no others
+    fn get_pulse_sample(&self, band_limit: BandLimit, width: f32, frequency: f32,
+                        phase: f64) -> f32 {
+        let width = width.clamp(MIN_PULSE_WIDTH, 1.0 - MIN_PULSE_WIDTH);
+        match band_limit {
+            BandLimit::Naive => if phase < width as f64 { 1.0 } else { -1.0 },
+            BandLimit::MipMap => {
+                let saw_table = &self.mip_map_tables.saw[
+                    self.mip_map_tables.table_index(frequency)];
+                self.get_table_sample(saw_table, phase - width as f64)
+                    - self.get_table_sample(saw_table, phase) + 2.0 * width - 1.0
+            }
+            BandLimit::PolyBlep => {
+                let dt = frequency as f64 / self.sample_rate() as f64;
+                band_limit::poly_blep_pulse(phase, dt.min(0.5), width as f64)
+            }
+        }
+    }
+
+    fn get_table_sample(&self, table: &[f32], phase: f64) -> f32 {
+        get_table_sample(table, phase, self.render_config.table_interpolation)
+    }
 }
 
 pub(crate) fn generate_sine_table() -> Vec<f32> {
@@ -80,14 +242,26 @@ pub(crate) fn generate_triangle_table() -> Vec<f32> {
     table
 }
 
-pub(crate) fn get_sample(table: &Vec<f32>, frequency: f32, sample_count: u64) -> f32 {
-    table[((frequency * sample_count as f32) / SAMPLE_COUNT_FACTOR) as usize % NUM_TABLE_SAMPLES]
-}
-
-pub(crate) fn get_gaussian_noise_sample() -> f32 {
-    let normal = Normal::new(0.0, 1.0).unwrap();
-    let mut rng = thread_rng();
-    normal.sample(&mut rng)
+// Reads a table holding one cycle at a phase from 0.0 to 1.0, interpolating between entries
+pub(crate) fn get_table_sample(table: &[f32], phase: f64,
+                               interpolation: TableInterpolation) -> f32 {
+    let len = table.len();
+    let position = phase.rem_euclid(1.0) * len as f64;
+    let index = position.floor() as usize % len;
+    let x = (position - position.floor()) as f32;
+    let y1 = table[index];
+    let y2 = table[(index + 1) % len];
+    match interpolation {
+        TableInterpolation::Linear => y1 + (y2 - y1) * x,
+        TableInterpolation::Cubic => {
+            let y0 = table[(index + len - 1) % len];
+            let y3 = table[(index + 2) % len];
+            let c1 = 0.5 * (y2 - y0);
+            let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+            let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+            ((c3 * x + c2) * x + c1) * x + y1
+        }
+    }
 }
 
 // TODO DEPRECATE THESE?
@@ -115,3 +289,79 @@ pub(crate) fn get_saw_sample(frequency: f32, sample_position: f32) -> f32 {
         .floor()).abs()
         - 1.0
 }
+
+#[cfg(test)]
+mod test_oscillator {
+    use crate::audio_gen::band_limit::BandLimit;
+    use crate::audio_gen::noise::NoiseGenerator;
+    use crate::audio_gen::oscillator::{generate_sine_table, get_table_sample, OscillatorTables,
+                                       PhaseAccumulator, TableInterpolation, Waveform};
+
+    #[test]
+    fn test_phase_accumulator_long_render() {
+        // Ten minutes of 440 Hz accumulated sample by sample lands where the exact phase is
+        let sample_rate = 44100.0;
+        let mut phase = PhaseAccumulator::default();
+        for _ in 0..(600 * sample_rate as usize) {
+            phase.next_phase(440.0, sample_rate);
+        }
+        // distance around the cycle, since either side may have just wrapped
+        let expected = PhaseAccumulator::at_elapsed_ms(440.0, 600000.0);
+        let distance = phase.phase() - expected.phase();
+        assert!((distance - distance.round()).abs() < 1e-6,
+                "{} != {}", phase.phase(), expected.phase());
+    }
+
+    #[test]
+    fn test_frequency_change_is_continuous() {
+        // The step between consecutive sine samples is bounded by the frequency, so a phase
+        // jump when the frequency changes would show up as a larger step
+        let oscillator_tables = OscillatorTables::new();
+        let sample_rate = oscillator_tables.sample_rate();
+        let mut phase = PhaseAccumulator::default();
+        let mut prev_sample = 0.0;
+        for i in 0..1000 {
+            let frequency = if i < 517 { 440.0 } else { 660.0 };
+            let sample = oscillator_tables.get_sine_sample(
+                phase.next_phase(frequency, sample_rate));
+            let max_step = 2.0 * std::f32::consts::PI * 660.0 / sample_rate;
+            assert!((sample - prev_sample).abs() <= max_step * 1.01, "jump at sample {}", i);
+            prev_sample = sample;
+        }
+    }
+
+    #[test]
+    fn test_pulse_duty_cycle() {
+        // The average of a pulse over a cycle is 2 * width - 1, so it shows how long it's high
+        let oscillator_tables = OscillatorTables::new();
+        let mut noise = NoiseGenerator::default();
+        for band_limit in [BandLimit::Naive, BandLimit::MipMap, BandLimit::PolyBlep] {
+            for width in [0.1, 0.25, 0.5, 0.8] {
+                let num_samples = 441;
+                let mut phase = PhaseAccumulator::default();
+                let mean = (0..num_samples).map(|_| oscillator_tables.get_waveform_sample(
+                    Waveform::Pulse(band_limit, width), 100.0,
+                    phase.next_phase(100.0, oscillator_tables.sample_rate()), &mut noise))
+                    .sum::<f32>() / num_samples as f32;
+                assert!((mean - (2.0 * width - 1.0)).abs() < 0.01,
+                        "{:?} width {}: mean {}", band_limit, width, mean);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cubic_more_accurate_than_linear() {
+        let table = generate_sine_table();
+        let max_error = |interpolation: TableInterpolation| {
+            (0..10000).map(|i| {
+                let phase = i as f64 / 10000.0 + 0.3 / table.len() as f64;
+                let expected = (2.0 * std::f64::consts::PI * phase).sin() as f32;
+                (get_table_sample(&table, phase, interpolation) - expected).abs()
+            }).fold(0.0f32, f32::max)
+        };
+        let linear = max_error(TableInterpolation::Linear);
+        let cubic = max_error(TableInterpolation::Cubic);
+        assert!(linear < 1e-5);
+        assert!(cubic < linear / 10.0, "cubic {} linear {}", cubic, linear);
+    }
+}
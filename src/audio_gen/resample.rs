@@ -0,0 +1,98 @@
+use std::f64::consts::PI;
+
+// Zero crossings of the sinc kernel on each side of the interpolated point. More gives a steeper
+// anti-aliasing filter at the cost of more taps per output sample
+static SINC_HALF_WIDTH: f64 = 16.0;
+
+// Converts samples recorded at from_rate to to_rate with Blackman-windowed sinc interpolation.
+// When downsampling the kernel is widened to low-pass below the new Nyquist frequency, so that
+// content above it doesn't alias
+#[allow(dead_code)]
+pub(crate) fn resample(samples: &[f32], from_rate: f32, to_rate: f32) -> Vec<f32> {
+    if samples.is_empty() || from_rate == to_rate {
+        return samples.to_vec();
+    }
+
+    // input samples advanced per output sample
+    let step = from_rate as f64 / to_rate as f64;
+    // kernel cutoff as a fraction of the input Nyquist frequency
+    let cutoff = (1.0 / step).min(1.0);
+    let half_width = SINC_HALF_WIDTH / cutoff;
+    let num_out_samples = (samples.len() as f64 / step).round() as usize;
+
+    let mut out_samples = Vec::with_capacity(num_out_samples);
+    for i in 0..num_out_samples {
+        let position = i as f64 * step;
+        let first = ((position - half_width).ceil() as i64).max(0);
+        let last = ((position + half_width).floor() as i64).min(samples.len() as i64 - 1);
+        let mut out_sample = 0.0;
+        for j in first..=last {
+            let distance = position - j as f64;
+            out_sample += samples[j as usize] as f64 * cutoff * sinc(distance * cutoff) *
+                blackman(distance / half_width);
+        }
+        out_samples.push(out_sample as f32);
+    }
+    out_samples
+}
+
+pub(crate) fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Blackman window over -1.0 to 1.0, zero outside
+pub(crate) fn blackman(t: f64) -> f64 {
+    if t.abs() >= 1.0 {
+        0.0
+    } else {
+        0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos()
+    }
+}
+
+#[cfg(test)]
+mod test_resample {
+    use crate::audio_gen::resample::resample;
+
+    fn sine(frequency: f32, sample_rate: f32, num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_same_rate() {
+        let samples = sine(440.0, 44100.0, 100);
+        assert_eq!(resample(&samples, 44100.0, 44100.0), samples);
+    }
+
+    #[test]
+    fn test_resample_preserves_pitch() {
+        for (from_rate, to_rate) in [(48000.0, 44100.0), (22050.0, 44100.0), (96000.0, 44100.0)] {
+            let samples = sine(1000.0, from_rate, from_rate as usize / 10);
+            let resampled = resample(&samples, from_rate, to_rate);
+            assert_eq!(resampled.len(), to_rate as usize / 10);
+
+            // Compare away from the edges, where the kernel runs off the ends of the input
+            let expected = sine(1000.0, to_rate, resampled.len());
+            for i in 200..resampled.len() - 200 {
+                assert!((resampled[i] - expected[i]).abs() < 1e-3,
+                        "{} -> {} at {}: {} != {}", from_rate, to_rate, i, resampled[i],
+                        expected[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_resample_filters_above_nyquist() {
+        // 30 kHz is above the 22.05 kHz Nyquist frequency of the output rate
+        let samples = sine(30000.0, 96000.0, 9600);
+        let resampled = resample(&samples, 96000.0, 44100.0);
+        let peak = resampled[200..resampled.len() - 200].iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak < 0.01);
+    }
+}
@@ -0,0 +1,98 @@
+// Sample formats for reading and writing WAV files. The engine works in normalized f32 samples,
+// full scale is -1.0 to 1.0, and converts to and from the file's format at the edges
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum SampleFormat {
+    Int8,
+    #[default]
+    Int16,
+    Int24,
+    Float32,
+}
+
+#[allow(dead_code)]
+impl SampleFormat {
+    pub(crate) fn from_wav_spec(spec: &hound::WavSpec) -> Result<SampleFormat, String> {
+        match (spec.sample_format, spec.bits_per_sample) {
+            (hound::SampleFormat::Int, 8) => Ok(SampleFormat::Int8),
+            (hound::SampleFormat::Int, 16) => Ok(SampleFormat::Int16),
+            (hound::SampleFormat::Int, 24) => Ok(SampleFormat::Int24),
+            (hound::SampleFormat::Float, 32) => Ok(SampleFormat::Float32),
+            (sample_format, bits_per_sample) =>
+                Err(format!("Unsupported WAV sample format: {:?} {} bit",
+                            sample_format, bits_per_sample)),
+        }
+    }
+
+    pub(crate) fn bits_per_sample(&self) -> u16 {
+        match self {
+            SampleFormat::Int8 => 8,
+            SampleFormat::Int16 => 16,
+            SampleFormat::Int24 => 24,
+            SampleFormat::Float32 => 32,
+        }
+    }
+
+    pub(crate) fn wav_sample_format(&self) -> hound::SampleFormat {
+        match self {
+            SampleFormat::Float32 => hound::SampleFormat::Float,
+            _ => hound::SampleFormat::Int,
+        }
+    }
+
+    // Magnitude of the most negative int value, which maps to -1.0. Not used for Float32
+    fn int_scale(&self) -> f32 {
+        (1i64 << (self.bits_per_sample() - 1)) as f32
+    }
+
+    // Int sample as read from a file to normalized f32
+    pub(crate) fn int_to_normalized(&self, sample: i32) -> f32 {
+        sample as f32 / self.int_scale()
+    }
+
+    // Normalized f32 to an int sample for writing. Values past full scale are clipped, since
+    // they can't be represented in an int format
+    pub(crate) fn normalized_to_int(&self, sample: f32) -> i32 {
+        let scale = self.int_scale();
+        (sample * scale).round().clamp(-scale, scale - 1.0) as i32
+    }
+}
+
+#[cfg(test)]
+mod test_sample_format {
+    use crate::audio_gen::sample_format::SampleFormat;
+
+    #[test]
+    fn test_normalized_to_int() {
+        assert_eq!(SampleFormat::Int8.normalized_to_int(-1.0), -128);
+        assert_eq!(SampleFormat::Int8.normalized_to_int(1.0), 127);
+        assert_eq!(SampleFormat::Int16.normalized_to_int(0.5), 16384);
+        assert_eq!(SampleFormat::Int16.normalized_to_int(2.0), 32767);
+        assert_eq!(SampleFormat::Int24.normalized_to_int(-1.0), -8388608);
+        assert_eq!(SampleFormat::Int24.normalized_to_int(1.0), 8388607);
+    }
+
+    #[test]
+    fn test_int_to_normalized() {
+        assert_eq!(SampleFormat::Int8.int_to_normalized(-128), -1.0);
+        assert_eq!(SampleFormat::Int16.int_to_normalized(16384), 0.5);
+        assert_eq!(SampleFormat::Int24.int_to_normalized(-4194304), -0.5);
+    }
+
+    #[test]
+    fn test_from_wav_spec() {
+        let mut spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 24,
+            sample_format: hound::SampleFormat::Int,
+        };
+        assert_eq!(SampleFormat::from_wav_spec(&spec), Ok(SampleFormat::Int24));
+
+        spec.bits_per_sample = 32;
+        assert!(SampleFormat::from_wav_spec(&spec).is_err());
+
+        spec.sample_format = hound::SampleFormat::Float;
+        assert_eq!(SampleFormat::from_wav_spec(&spec), Ok(SampleFormat::Float32));
+    }
+}
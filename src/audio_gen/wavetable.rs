@@ -0,0 +1,209 @@
+use std::sync::{Arc, LazyLock, RwLock};
+
+use crate::audio_gen::audio_gen::read_audio_file;
+use crate::audio_gen::oscillator::{get_table_sample, TableInterpolation};
+
+// Every frame is stored at this size, whatever the length of the cycle it was loaded from
+pub(crate) static WAVETABLE_FRAME_SIZE: usize = 2048;
+
+// Wavetables registered by name. Ids index into the list, so they stay valid for the life of
+// the program and can be held in a Copy Waveform
+type RegisteredWavetable = (String, Arc<Wavetable>);
+static WAVETABLES: LazyLock<RwLock<Vec<RegisteredWavetable>>> =
+    LazyLock::new(|| RwLock::new(Vec::new()));
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct WavetableId(usize);
+
+// One or more single-cycle frames. Reading at a position between frames crossfades the two
+// nearest, so sweeping the position over a note morphs from the first frame to the last
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Wavetable {
+    pub(crate) frames: Vec<Vec<f32>>,
+}
+
+#[allow(dead_code)]
+impl Wavetable {
+    pub(crate) fn new(frames: Vec<Vec<f32>>) -> Result<Wavetable, String> {
+        if frames.is_empty() || frames.iter().any(|frame| frame.is_empty()) {
+            return Err(String::from("Wavetable: must have at least one non-empty frame"));
+        }
+        Ok(Wavetable {
+            frames: frames.iter().map(|frame| resize_cycle(frame)).collect(),
+        })
+    }
+
+    // One frame per list of harmonic amplitudes, the first entry being the fundamental. The
+    // frames are scaled together so the loudest peaks at 1.0 and they keep their relative levels
+    pub(crate) fn from_harmonics(harmonic_frames: &[Vec<f32>]) -> Result<Wavetable, String> {
+        let mut frames: Vec<Vec<f32>> = harmonic_frames.iter()
+            .map(|harmonics| (0..WAVETABLE_FRAME_SIZE)
+                .map(|i| {
+                    let phase = i as f64 / WAVETABLE_FRAME_SIZE as f64;
+                    harmonics.iter().enumerate()
+                        .map(|(k, amplitude)| *amplitude as f64 *
+                            (2.0 * std::f64::consts::PI * (k + 1) as f64 * phase).sin())
+                        .sum::<f64>() as f32
+                })
+                .collect())
+            .collect();
+
+        let peak = frames.iter().flatten().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak > 0.0 {
+            frames.iter_mut().flatten().for_each(|sample| *sample /= peak);
+        }
+        Wavetable::new(frames)
+    }
+
+    // A WAV file of num_frames consecutive single cycles of equal length, mixed down to mono
+    pub(crate) fn from_wav_file(file_path: &str, num_frames: usize) -> Result<Wavetable, String> {
+        let audio_file = read_audio_file(file_path)?;
+        let num_samples = audio_file.channels[0].len();
+        if num_frames == 0 || num_samples < num_frames {
+            return Err(format!("Wavetable: {} has {} samples, too few for {} frames",
+                               file_path, num_samples, num_frames));
+        }
+
+        let num_channels = audio_file.channels.len() as f32;
+        let mono: Vec<f32> = (0..num_samples)
+            .map(|i| audio_file.channels.iter().map(|channel| channel[i]).sum::<f32>() /
+                num_channels)
+            .collect();
+        let frame_len = num_samples / num_frames;
+        Wavetable::new(mono.chunks_exact(frame_len).take(num_frames)
+            .map(|frame| frame.to_vec())
+            .collect())
+    }
+
+    // Sample at phase into the cycle and position from 0.0, the first frame, to 1.0, the last
+    pub(crate) fn sample(&self, position: f32, phase: f64,
+                         interpolation: TableInterpolation) -> f32 {
+        let frame_position = position.clamp(0.0, 1.0) * (self.frames.len() - 1) as f32;
+        let index = frame_position.floor() as usize;
+        let fraction = frame_position - index as f32;
+        let sample = get_table_sample(&self.frames[index], phase, interpolation);
+        if fraction == 0.0 {
+            return sample;
+        }
+        let next_sample = get_table_sample(&self.frames[index + 1], phase, interpolation);
+        sample + (next_sample - sample) * fraction
+    }
+}
+
+// Resamples one cycle to WAVETABLE_FRAME_SIZE, wrapping around its ends
+fn resize_cycle(frame: &[f32]) -> Vec<f32> {
+    if frame.len() == WAVETABLE_FRAME_SIZE {
+        return frame.to_vec();
+    }
+    (0..WAVETABLE_FRAME_SIZE)
+        .map(|i| get_table_sample(frame, i as f64 / WAVETABLE_FRAME_SIZE as f64,
+                                  TableInterpolation::Cubic))
+        .collect()
+}
+
+// Registers wavetable under name, replacing any wavetable already registered under it. Notes
+// built after that play the new wavetable, notes already built keep the one they were built with
+pub(crate) fn register_wavetable(name: &str, wavetable: Wavetable) -> WavetableId {
+    let mut wavetables = WAVETABLES.write().unwrap();
+    let wavetable = Arc::new(wavetable);
+    match wavetables.iter().position(|(registered_name, _)| registered_name == name) {
+        Some(index) => {
+            wavetables[index].1 = wavetable;
+            WavetableId(index)
+        }
+        None => {
+            wavetables.push((name.to_string(), wavetable));
+            WavetableId(wavetables.len() - 1)
+        }
+    }
+}
+
+pub(crate) fn wavetable_id(name: &str) -> Option<WavetableId> {
+    WAVETABLES.read().unwrap().iter()
+        .position(|(registered_name, _)| registered_name == name)
+        .map(WavetableId)
+}
+
+pub(crate) fn get_wavetable(id: WavetableId) -> Arc<Wavetable> {
+    WAVETABLES.read().unwrap()[id.0].1.clone()
+}
+
+#[cfg(test)]
+mod test_wavetable {
+    use crate::audio_gen::audio_gen::write_audio_file;
+    use crate::audio_gen::oscillator::TableInterpolation;
+    use crate::audio_gen::sample_format::SampleFormat;
+    use crate::audio_gen::wavetable::{get_wavetable, register_wavetable, wavetable_id, Wavetable};
+    use crate::common::render_config::RenderConfigBuilder;
+    use crate::common::stereo::StereoFrame;
+
+    fn sine(phase: f64) -> f32 {
+        (2.0 * std::f64::consts::PI * phase).sin() as f32
+    }
+
+    #[test]
+    fn test_from_harmonics() {
+        let wavetable = Wavetable::from_harmonics(&[vec![1.0], vec![0.0, 0.5]]).unwrap();
+        for phase in [0.1, 0.25, 0.6] {
+            assert!((wavetable.sample(0.0, phase, TableInterpolation::Cubic) - sine(phase))
+                .abs() < 1e-4);
+            // the second frame keeps its level relative to the first
+            assert!((wavetable.sample(1.0, phase, TableInterpolation::Cubic) -
+                0.5 * sine(2.0 * phase)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_morph_between_frames() {
+        let wavetable = Wavetable::new(vec![vec![-1.0; 8], vec![0.0; 8], vec![1.0; 8]]).unwrap();
+        assert_eq!(wavetable.sample(0.0, 0.3, TableInterpolation::Linear), -1.0);
+        assert_eq!(wavetable.sample(0.25, 0.3, TableInterpolation::Linear), -0.5);
+        assert_eq!(wavetable.sample(0.75, 0.3, TableInterpolation::Linear), 0.5);
+        assert_eq!(wavetable.sample(2.0, 0.3, TableInterpolation::Linear), 1.0);
+        assert!(Wavetable::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_from_wav_file() {
+        // two 600 sample cycles, a sine then a sine an octave up
+        let frame_len = 600;
+        let frames: Vec<StereoFrame> = (0..2 * frame_len)
+            .map(|i| {
+                let harmonic = (i / frame_len + 1) as f64;
+                let phase = (i % frame_len) as f64 / frame_len as f64;
+                StereoFrame::from_mono(0.5 * sine(harmonic * phase))
+            })
+            .collect();
+        let file_path = std::env::temp_dir().join("test_wavetable_from_wav_file.wav");
+        let file_path = file_path.to_str().unwrap();
+        let render_config = RenderConfigBuilder::default()
+            .sample_format(SampleFormat::Float32)
+            .build().unwrap();
+        write_audio_file(file_path, frames, &render_config).unwrap();
+
+        let wavetable = Wavetable::from_wav_file(file_path, 2).unwrap();
+        std::fs::remove_file(file_path).unwrap();
+        assert_eq!(wavetable.frames.len(), 2);
+        for phase in [0.1, 0.25, 0.6] {
+            assert!((wavetable.sample(0.0, phase, TableInterpolation::Linear) -
+                0.5 * sine(phase)).abs() < 1e-3);
+            assert!((wavetable.sample(1.0, phase, TableInterpolation::Linear) -
+                0.5 * sine(2.0 * phase)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_register_wavetable() {
+        let first = Wavetable::new(vec![vec![0.25; 4]]).unwrap();
+        let second = Wavetable::new(vec![vec![0.75; 4]]).unwrap();
+
+        let id = register_wavetable("test_register_wavetable", first.clone());
+        assert_eq!(wavetable_id("test_register_wavetable"), Some(id));
+        assert_eq!(*get_wavetable(id), first);
+
+        // re-registering replaces the wavetable under the same id
+        assert_eq!(register_wavetable("test_register_wavetable", second.clone()), id);
+        assert_eq!(*get_wavetable(id), second);
+        assert_eq!(wavetable_id("test_register_wavetable_missing"), None);
+    }
+}
@@ -2,11 +2,12 @@ pub(crate) static FLOAT_EPSILON: f32 = 5.0 * f32::EPSILON;
 
 pub(crate) static NO_TRACK: i16 = -1;
 
+// level, -60 dB, at which an effect's tail has died away
+pub(crate) static TAIL_LEVEL: f32 = 0.001;
+
 // khz samples per second, so 44.1k samples per second
-// sample_clock samples / SAMPLE_RATE samples per second = seconds
-pub(crate) const SAMPLE_RATE: f32 = 44100.0;
-pub(crate) const SAMPLES_PER_MS: f32 = SAMPLE_RATE / 1000.0;
-#[allow(dead_code)]
-pub(crate) const NYQUIST_FREQUENCY: f32 = SAMPLE_RATE / 2.0;
+// sample_clock samples / sample_rate samples per second = seconds
+// The rate actually rendered at is set per render in RenderConfig, this is its default
+pub(crate) const DEFAULT_SAMPLE_RATE: f32 = 44100.0;
 
 pub(crate) static DEFAULT_LFO_AMPLITUDE: f32 = 0.5;
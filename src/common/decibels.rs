@@ -0,0 +1,28 @@
+// Conversions between decibels and linear gain, for levels relative to full scale
+
+#[allow(dead_code)]
+pub(crate) fn db_to_gain(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+// Silence is negative infinity
+#[allow(dead_code)]
+pub(crate) fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.abs().log10()
+}
+
+#[cfg(test)]
+mod test_decibels {
+    use crate::common::decibels::{db_to_gain, gain_to_db};
+    use crate::common::float_utils::assert_float_eq;
+
+    #[test]
+    fn test_conversions() {
+        assert_float_eq(db_to_gain(0.0), 1.0);
+        assert_float_eq(db_to_gain(-20.0), 0.1);
+        assert!((db_to_gain(-6.0) - 0.501187).abs() < 1e-6);
+        assert_float_eq(gain_to_db(0.1), -20.0);
+        assert!((gain_to_db(db_to_gain(-3.0)) + 3.0).abs() < 1e-5);
+        assert_eq!(gain_to_db(0.0), f32::NEG_INFINITY);
+    }
+}
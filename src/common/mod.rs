@@ -1,3 +1,6 @@
 pub mod constants;
+pub mod decibels;
 pub mod float_utils;
-pub mod pair;
\ No newline at end of file
+pub mod pair;
+pub mod render_config;
+pub mod stereo;
@@ -0,0 +1,91 @@
+use derive_builder::Builder;
+
+use crate::audio_gen::oscillator::TableInterpolation;
+use crate::audio_gen::sample_format::SampleFormat;
+use crate::common::constants::DEFAULT_SAMPLE_RATE;
+
+// Settings shared by everything that turns time into samples: oscillator tables, effects,
+// TrackGrid sample-time math and the output WAV spec
+#[allow(dead_code)]
+#[derive(Builder, Clone, Copy, Debug, PartialEq)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub(crate) struct RenderConfig {
+    // samples per second, i.e. 44100.0 for CD, 48000.0 for video, 96000.0 for mastering
+    #[builder(default = "DEFAULT_SAMPLE_RATE")]
+    pub(crate) sample_rate: f32,
+
+    // format of rendered WAV files
+    #[builder(default = "SampleFormat::default()")]
+    pub(crate) sample_format: SampleFormat,
+
+    // how oscillators read between wavetable entries
+    #[builder(default = "TableInterpolation::default()")]
+    pub(crate) table_interpolation: TableInterpolation,
+
+    // seeds every voice's noise generator, so the render's noise is the same every time. None
+    // seeds from entropy, different on every render
+    #[builder(default = "None")]
+    pub(crate) seed: Option<u64>,
+}
+
+impl RenderConfigBuilder {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(sample_rate) = self.sample_rate {
+            if sample_rate <= 0.0 {
+                return Err(String::from("RenderConfig: sample_rate must be greater than 0.0"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfigBuilder::default().build().unwrap()
+    }
+}
+
+#[allow(dead_code)]
+impl RenderConfig {
+    pub(crate) fn samples_per_ms(&self) -> f32 {
+        self.sample_rate / 1000.0
+    }
+
+    pub(crate) fn nyquist_frequency(&self) -> f32 {
+        self.sample_rate / 2.0
+    }
+
+    // Index of the sample playing at time_ms. Windows are converted on both ends with this so
+    // that consecutive windows line up with no gaps or overlapping samples between them
+    pub(crate) fn ms_to_samples(&self, time_ms: f32) -> usize {
+        (time_ms.max(0.0) * self.samples_per_ms()).round() as usize
+    }
+}
+
+#[cfg(test)]
+mod test_render_config {
+    use crate::common::constants::DEFAULT_SAMPLE_RATE;
+    use crate::common::render_config::{RenderConfig, RenderConfigBuilder};
+
+    #[test]
+    fn test_default() {
+        let render_config = RenderConfig::default();
+        assert_eq!(render_config.sample_rate, DEFAULT_SAMPLE_RATE);
+        assert_eq!(render_config.nyquist_frequency(), DEFAULT_SAMPLE_RATE / 2.0);
+    }
+
+    #[test]
+    fn test_ms_to_samples() {
+        let render_config = RenderConfigBuilder::default().sample_rate(48000.0).build().unwrap();
+        assert_eq!(render_config.ms_to_samples(500.0), 24000);
+        assert_eq!(render_config.ms_to_samples(-10.0), 0);
+
+        let render_config = RenderConfigBuilder::default().sample_rate(96000.0).build().unwrap();
+        assert_eq!(render_config.ms_to_samples(1000.0), 96000);
+    }
+
+    #[test]
+    fn test_invalid_sample_rate() {
+        assert!(RenderConfigBuilder::default().sample_rate(0.0).build().is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::ops::{Add, AddAssign, Mul};
+
+// Pan positions run from hard left at -1.0 through center at 0.0 to hard right at 1.0
+pub(crate) static PAN_LEFT: f32 = -1.0;
+#[allow(dead_code)]
+pub(crate) static PAN_CENTER: f32 = 0.0;
+pub(crate) static PAN_RIGHT: f32 = 1.0;
+
+// One sample per channel, the unit of output through the stereo signal path
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct StereoFrame {
+    pub(crate) left: f32,
+    pub(crate) right: f32,
+}
+
+#[allow(dead_code)]
+impl StereoFrame {
+    pub(crate) fn new(left: f32, right: f32) -> Self {
+        StereoFrame { left, right }
+    }
+
+    // Same sample in both channels, unpanned
+    pub(crate) fn from_mono(sample: f32) -> Self {
+        StereoFrame { left: sample, right: sample }
+    }
+
+    pub(crate) fn to_mono(self) -> f32 {
+        (self.left + self.right) / 2.0
+    }
+
+    pub(crate) fn map(self, f: impl Fn(f32) -> f32) -> Self {
+        StereoFrame { left: f(self.left), right: f(self.right) }
+    }
+
+    // Pan for sources that are already stereo. Turns down the channel opposite the pan position
+    // and leaves the other at unity, so a centered frame passes through unchanged
+    pub(crate) fn balance(self, pan: f32) -> Self {
+        let pan = pan.clamp(PAN_LEFT, PAN_RIGHT);
+        StereoFrame { left: self.left * (1.0 - pan).min(1.0),
+                      right: self.right * (1.0 + pan).min(1.0) }
+    }
+}
+
+impl Add for StereoFrame {
+    type Output = StereoFrame;
+
+    fn add(self, other: StereoFrame) -> StereoFrame {
+        StereoFrame { left: self.left + other.left, right: self.right + other.right }
+    }
+}
+
+impl AddAssign for StereoFrame {
+    fn add_assign(&mut self, other: StereoFrame) {
+        self.left += other.left;
+        self.right += other.right;
+    }
+}
+
+impl Mul<f32> for StereoFrame {
+    type Output = StereoFrame;
+
+    fn mul(self, factor: f32) -> StereoFrame {
+        StereoFrame { left: self.left * factor, right: self.right * factor }
+    }
+}
+
+// How gain is split between channels as a mono sample is panned. The laws differ in how much a
+// centered sample is attenuated in each channel:
+// Linear: -6 dB, sums to unity gain in mono
+// ConstantPower: -3 dB, keeps perceived loudness constant across the stereo field
+// Compromise: -4.5 dB, halfway between the two
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub(crate) enum PanLaw {
+    Linear,
+    #[default]
+    ConstantPower,
+    Compromise,
+}
+
+impl PanLaw {
+    // Returns (left, right) gains for a pan position, clamped to the range -1.0 to 1.0
+    pub(crate) fn gains(&self, pan: f32) -> (f32, f32) {
+        let position = (pan.clamp(PAN_LEFT, PAN_RIGHT) + 1.0) / 2.0;
+        let linear = (1.0 - position, position);
+        let angle = position * std::f32::consts::FRAC_PI_2;
+        let constant_power = (angle.cos(), angle.sin());
+        match self {
+            PanLaw::Linear => linear,
+            PanLaw::ConstantPower => constant_power,
+            PanLaw::Compromise => ((linear.0 * constant_power.0).sqrt(),
+                                   (linear.1 * constant_power.1).sqrt()),
+        }
+    }
+
+    pub(crate) fn pan(&self, sample: f32, pan: f32) -> StereoFrame {
+        let (left_gain, right_gain) = self.gains(pan);
+        StereoFrame { left: sample * left_gain, right: sample * right_gain }
+    }
+}
+
+#[cfg(test)]
+mod test_stereo {
+    use crate::common::float_utils::assert_float_eq;
+    use crate::common::stereo::{PanLaw, StereoFrame, PAN_CENTER, PAN_LEFT, PAN_RIGHT};
+
+    #[test]
+    fn test_pan_law_center_gains() {
+        let (left, right) = PanLaw::Linear.gains(PAN_CENTER);
+        assert_float_eq(left, 0.5);
+        assert_float_eq(right, 0.5);
+
+        let (left, right) = PanLaw::ConstantPower.gains(PAN_CENTER);
+        assert_float_eq(left, std::f32::consts::FRAC_1_SQRT_2);
+        assert_float_eq(right, std::f32::consts::FRAC_1_SQRT_2);
+
+        // -4.5 dB
+        let (left, right) = PanLaw::Compromise.gains(PAN_CENTER);
+        assert!((20.0 * left.log10() + 4.5).abs() < 0.1);
+        assert!((20.0 * right.log10() + 4.5).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_pan_law_hard_pan() {
+        for pan_law in [PanLaw::Linear, PanLaw::ConstantPower, PanLaw::Compromise] {
+            let frame = pan_law.pan(0.8, PAN_LEFT);
+            assert_float_eq(frame.left, 0.8);
+            assert!(frame.right.abs() < 1e-6);
+
+            let frame = pan_law.pan(0.8, PAN_RIGHT);
+            assert!(frame.left.abs() < 1e-6);
+            assert_float_eq(frame.right, 0.8);
+        }
+    }
+
+    #[test]
+    fn test_pan_clamped() {
+        assert_eq!(PanLaw::Linear.gains(-3.0), PanLaw::Linear.gains(PAN_LEFT));
+        assert_eq!(PanLaw::Linear.gains(3.0), PanLaw::Linear.gains(PAN_RIGHT));
+    }
+
+    #[test]
+    fn test_frame_ops() {
+        let mut frame = StereoFrame::new(0.25, -0.5) + StereoFrame::from_mono(0.25);
+        frame += StereoFrame::new(0.5, 0.0);
+        assert_eq!(frame, StereoFrame::new(1.0, -0.25));
+        assert_eq!(frame * 2.0, StereoFrame::new(2.0, -0.5));
+        assert_float_eq(frame.to_mono(), 0.375);
+    }
+
+    #[test]
+    fn test_balance() {
+        let frame = StereoFrame::new(0.5, -0.5);
+        assert_eq!(frame.balance(PAN_CENTER), frame);
+        assert_eq!(frame.balance(PAN_LEFT), StereoFrame::new(0.5, 0.0));
+        assert_eq!(frame.balance(0.5), StereoFrame::new(0.25, -0.5));
+    }
+}
@@ -1,6 +1,17 @@
-use crate::{audio_gen, common, midi, note};
-use crate::audio_gen::audio_gen::gen_notes_stream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use ringbuf::{HeapProd, HeapRb};
+use ringbuf::traits::{Producer, Split};
+
+use crate::{midi, note};
+use crate::audio_gen::audio_gen::{default_output_render_config, gen_ring_buffer_stream,
+    write_audio_file, STREAM_BUFFER_SECONDS};
+use crate::audio_gen::band_limit::BandLimit;
+use crate::audio_gen::master_bus::{ClipReport, MasterBus};
 use crate::audio_gen::oscillator::{OscillatorTables, Waveform};
+use crate::common::render_config::RenderConfig;
+use crate::common::stereo::StereoFrame;
 use crate::effect::delay::Delay;
 use crate::effect::flanger::Flanger;
 use crate::effect::lfo::LFO;
@@ -10,8 +21,9 @@ use crate::sequence::note_sequence_trait::{AppendNote, AppendNotes, BuilderWrapp
     NextNotes, SetCurPosition};
 use crate::track::track::{Track, TrackBuilder};
 use crate::track::track_grid::TrackGrid;
+use crate::note::oscillator_layer::unity_layers;
 use crate::note::note_pool::NotePool;
-use crate::note::sampled_note::SampledNote;
+use crate::note::sampled_note::{ChannelMode, SampledNote};
 
 const ARGS_DELIMITER: &str = ",";
 
@@ -21,23 +33,27 @@ pub(crate) struct SampleBuf {
     len: usize,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn build_sampled_playback_note(sampled_note_pool: &mut NotePool<SampledNote>,
                                           playback_note_pool: &mut NotePool<PlaybackNote>,
                                           file_path: &str, volume: f32, start_time: f32,
                                           envelopes: Vec<Envelope>, flangers: Vec<Flanger>,
-                                          delays: Vec<Delay>, lfos: Vec<LFO>) -> PlaybackNote {
-    let sample_buf: SampleBuf = load_sample_data(file_path);
+                                          delays: Vec<Delay>, lfos: Vec<LFO>,
+                                          render_config: &RenderConfig) -> PlaybackNote {
+    let sample_buf: SampleBuf = load_sample_data(file_path, render_config);
     let mut sampled_note = sampled_note_pool.acquire().unwrap();
     sampled_note.volume = volume;
     sampled_note.start_time_ms = start_time;
-    sampled_note.end_time_ms = (sample_buf.len as f32 / common::constants::SAMPLE_RATE) * 1000.0;
+    sampled_note.end_time_ms = (sample_buf.len as f32 / render_config.sample_rate) * 1000.0;
+    sampled_note.sample_rate = render_config.sample_rate;
     sampled_note.set_sample_buf(&sample_buf.buf);
 
     let mut playback_note = playback_note_pool.acquire().unwrap();
     playback_note.note_type = NoteType::Sample;
     playback_note.sampled_note = sampled_note;
     playback_note.playback_start_time_ms = start_time;
-    playback_note.playback_end_time_ms = start_time + ((sample_buf.len as f32 / common::constants::SAMPLE_RATE) * 1000.0);
+    playback_note.playback_end_time_ms =
+        start_time + ((sample_buf.len as f32 / render_config.sample_rate) * 1000.0);
     playback_note.playback_sample_start_time = start_time as u64;
     playback_note.playback_sample_end_time = sample_buf.len as u64;
     playback_note.envelopes = envelopes;
@@ -48,12 +64,12 @@ pub(crate) fn build_sampled_playback_note(sampled_note_pool: &mut NotePool<Sampl
     playback_note
 }
 
-pub(crate) fn load_sample_data(file_path: &str) -> SampleBuf {
-    let sample_data= audio_gen::audio_gen::read_audio_file(file_path).into_boxed_slice();
+// Loads the file's samples, mixed down to mono and resampled to render_config's rate
+pub(crate) fn load_sample_data(file_path: &str, render_config: &RenderConfig) -> SampleBuf {
+    let (sample_data, _) = note::sampled_note::load_sample_bufs(
+        file_path, ChannelMode::Downmix, render_config.sample_rate).unwrap();
     let mut sample_buf: Vec<f32> = Vec::with_capacity(note::sampled_note::BUF_STORAGE_SIZE);
-    for sample in  sample_data[..].iter() {
-        sample_buf.push(*sample as f32);
-    }
+    sample_buf.extend_from_slice(&sample_data);
     SampleBuf {
         buf: sample_buf,
         len: sample_data.len(),
@@ -74,7 +90,7 @@ pub(crate) fn load_midi_file_to_tracks<
     for track in midi_time_tracks.iter_mut() {
         for playback_notes in track.sequence.iter_mut() {
             for playback_note in playback_notes {
-                playback_note.note.waveforms = waveforms.clone();
+                playback_note.note.layers = unity_layers(&waveforms);
                 playback_note.note.volume = volume;
                 playback_note.envelopes = envelopes.clone();
                 playback_note.flangers = flangers.clone();
@@ -108,19 +124,19 @@ pub(crate) fn load_notes_to_new_track<
     SequenceType: AppendNotes + Clone + IterMutWrapper,
     SequenceBuilderType: BuilderWrapper<SequenceType>
 >
-(playback_notes: &mut Vec<PlaybackNote>, volume: f32) -> Track<SequenceType> {
+(playback_notes: &mut [PlaybackNote], volume: f32) -> Track<SequenceType> {
     let mut sequence = SequenceBuilderType::new();
     for playback_note in playback_notes.iter_mut() {
         playback_note.sampled_note.volume = volume;
     }
-    sequence.append_notes(&playback_notes.clone());
+    sequence.append_notes(playback_notes);
     TrackBuilder::default()
         .sequence(sequence)
         .build().unwrap()
 }
 
 #[allow(dead_code)]
-pub(crate) fn set_notes_offset(playback_notes: &mut Vec<PlaybackNote>, offset: f32) {
+pub(crate) fn set_notes_offset(playback_notes: &mut [PlaybackNote], offset: f32) {
     for playback_note in playback_notes.iter_mut() {
         playback_note.playback_start_time_ms += offset;
         playback_note.playback_end_time_ms += offset;
@@ -130,6 +146,12 @@ pub(crate) fn set_notes_offset(playback_notes: &mut Vec<PlaybackNote>, offset: f
         if playback_note.note_type == NoteType::Oscillator{
             playback_note.note.start_time_ms += offset;
             playback_note.note.end_time_ms += offset;
+        } else if playback_note.note_type == NoteType::FM {
+            playback_note.fm_note.start_time_ms += offset;
+            playback_note.fm_note.end_time_ms += offset;
+        } else if playback_note.note_type == NoteType::Additive {
+            playback_note.additive_note.start_time_ms += offset;
+            playback_note.additive_note.end_time_ms += offset;
         }
     }
 }
@@ -152,37 +174,432 @@ pub(crate) fn collect_args () -> String {
 pub(crate) fn get_waveforms_from_arg() -> Vec<Waveform> {
     collect_args().split(ARGS_DELIMITER)
         .map( |waveform| {
-            let matched = match waveform {
+            match waveform {
                 "gaussian_noise" => Waveform::GaussianNoise,
-                "saw" => Waveform::Saw,
+                "white_noise" => Waveform::WhiteNoise,
+                "pink_noise" => Waveform::PinkNoise,
+                "brown_noise" => Waveform::BrownNoise,
+                "saw" => Waveform::Saw(BandLimit::default()),
                 "sine" => Waveform::Sine,
-                "square" => Waveform::Square,
-                "triangle" => Waveform::Triangle,
+                "square" => Waveform::Square(BandLimit::default()),
+                "triangle" => Waveform::Triangle(BandLimit::default()),
                 _ => Waveform::Sine,
-            };
-            matched
+            }
         })
         .collect()
 }
 
+// Plays the TrackGrid through a single output stream. A render thread walks the grid and fills
+// a lock-free ring buffer that the stream callback drains, so playback is gapless across windows
+// and timing is driven by the audio device rather than by sleeping for each window.
+// The grid and its effects must be built at the device's sample rate, from
+// default_output_render_config, or it would play at the wrong pitch and speed, so a grid built at
+// any other rate is returned as an error rather than played
 pub(crate) fn play_track_grid<SequenceType>(track_grid: TrackGrid<SequenceType>)
+    -> Result<(), String>
 where
     // Add Send + 'static bounds to ensure thread safety
     SequenceType: NextNotes + Iterator + SetCurPosition + Send + 'static,
-    // The Item of the iterator must also be Send to be sent across the channel
-    <SequenceType as Iterator>::Item: Send,
 {
-    let (tx, rx) = std::sync::mpsc::channel();
-    std::thread::spawn(move || {
-        for playback_notes in track_grid {
-            if tx.send(playback_notes).is_err() {
-                // The receiver has hung up, so we can stop the thread.
-                break;
+    let device_sample_rate = default_output_render_config().sample_rate;
+    if track_grid.render_config.sample_rate != device_sample_rate {
+        return Err(format!("TrackGrid is built at {} Hz, the output device plays at {} Hz",
+                           track_grid.render_config.sample_rate, device_sample_rate));
+    }
+    if let Some(target_lufs) = track_grid.master_bus.target_lufs {
+        eprintln!("target_lufs {} is ignored in playback, which can't measure the whole render \
+                   ahead and plays at gain_db", target_lufs);
+    }
+    let ring_buffer = HeapRb::<StereoFrame>::new(
+        (STREAM_BUFFER_SECONDS * track_grid.render_config.sample_rate) as usize);
+    let (producer, consumer) = ring_buffer.split();
+    let render_done = Arc::new(AtomicBool::new(false));
+
+    let render_thread_done = render_done.clone();
+    let render_thread = std::thread::spawn(move || {
+        let clip_report = render_track_grid_to_ring_buffer(track_grid, producer);
+        render_thread_done.store(true, Ordering::SeqCst);
+        clip_report
+    });
+
+    let stream_stats = gen_ring_buffer_stream(consumer, render_done);
+    report_clipping(&render_thread.join().unwrap());
+
+    if stream_stats.num_underruns > 0 {
+        eprintln!("playback had {} underruns, {} frames of silence inserted",
+                  stream_stats.num_underruns, stream_stats.num_underrun_frames);
+    }
+    Ok(())
+}
+
+// Renders each window of the TrackGrid and pushes its frames through the master bus into the ring
+// buffer, waiting for the stream to make room when the buffer is full
+pub(crate) fn render_track_grid_to_ring_buffer<SequenceType>(
+    mut track_grid: TrackGrid<SequenceType>, mut producer: HeapProd<StereoFrame>) -> ClipReport
+where
+    SequenceType: NextNotes + Iterator + SetCurPosition,
+{
+    let oscillator_tables = OscillatorTables::with_render_config(track_grid.render_config);
+    let mut master_bus = master_bus_for(&track_grid);
+    while let Some(tracks_frames) = track_grid.next_window_frames(&oscillator_tables) {
+        push_frames(&mut producer, &process_frames(&mut master_bus, &mix_tracks(&tracks_frames)));
+    }
+    push_frames(&mut producer, &master_bus.flush());
+    master_bus.clip_report()
+}
+
+fn process_frames(master_bus: &mut MasterBus, frames: &[StereoFrame]) -> Vec<StereoFrame> {
+    frames.iter().map(|frame| master_bus.process(*frame)).collect()
+}
+
+// The TrackGrid's master bus, at the sample rate the grid renders at
+fn master_bus_for<SequenceType>(track_grid: &TrackGrid<SequenceType>) -> MasterBus
+where
+    SequenceType: NextNotes + Iterator + SetCurPosition,
+{
+    let mut master_bus = track_grid.master_bus.clone();
+    master_bus.render_config = track_grid.render_config;
+    master_bus
+}
+
+fn report_clipping(clip_report: &ClipReport) {
+    if clip_report.num_clipped > 0 {
+        eprintln!("master bus clipped {} of {} samples, peak level {:.2}",
+                  clip_report.num_clipped, clip_report.num_samples, clip_report.peak_out);
+    }
+}
+
+fn push_frames(producer: &mut HeapProd<StereoFrame>, frames: &[StereoFrame]) {
+    let mut remaining = frames;
+    while !remaining.is_empty() {
+        let num_pushed = producer.push_slice(remaining);
+        remaining = &remaining[num_pushed..];
+        if !remaining.is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+}
+
+// Non-realtime counterpart to play_track_grid. Walks every window of the TrackGrid and mixes
+// its tracks onto the end of a single buffer, then runs the mix through the master bus
+#[allow(dead_code)]
+pub(crate) fn render_track_grid<SequenceType>(track_grid: TrackGrid<SequenceType>)
+    -> Vec<StereoFrame>
+where
+    SequenceType: NextNotes + Iterator + SetCurPosition,
+{
+    render_track_grid_with_report(track_grid).0
+}
+
+// render_track_grid, along with the master bus's count of the samples that went over full scale
+#[allow(dead_code)]
+pub(crate) fn render_track_grid_with_report<SequenceType>(
+    mut track_grid: TrackGrid<SequenceType>) -> (Vec<StereoFrame>, ClipReport)
+where
+    SequenceType: NextNotes + Iterator + SetCurPosition,
+{
+    let oscillator_tables = OscillatorTables::with_render_config(track_grid.render_config);
+    let mut master_bus = master_bus_for(&track_grid);
+    let mut frames: Vec<StereoFrame> = Vec::new();
+    while let Some(tracks_frames) = track_grid.next_window_frames(&oscillator_tables) {
+        frames.extend(mix_tracks(&tracks_frames));
+    }
+    let frames = master_bus.process_buffer(&frames);
+    (frames, master_bus.clip_report())
+}
+
+// Sum of a window's tracks, which are all the same length
+fn mix_tracks(tracks_frames: &[Vec<StereoFrame>]) -> Vec<StereoFrame> {
+    let num_frames = tracks_frames.first().map_or(0, Vec::len);
+    (0..num_frames)
+        .map(|i| tracks_frames.iter()
+            .fold(StereoFrame::default(), |frame, track_frames| frame + track_frames[i]))
+        .collect()
+}
+
+// One track of a TrackGrid rendered on its own
+#[allow(dead_code)]
+pub(crate) struct Stem {
+    pub(crate) name: String,
+    pub(crate) frames: Vec<StereoFrame>,
+}
+
+// Renders each track of the TrackGrid to its own stem, for mixing elsewhere. The master bus is
+// bypassed. Every stem starts at sample zero of the piece and runs to its end, so the stems line
+// up when they are imported together and sum to the unprocessed mix
+#[allow(dead_code)]
+pub(crate) fn render_stems<SequenceType>(mut track_grid: TrackGrid<SequenceType>) -> Vec<Stem>
+where
+    SequenceType: NextNotes + Iterator + SetCurPosition,
+{
+    let oscillator_tables = OscillatorTables::with_render_config(track_grid.render_config);
+    let names: Vec<String> = track_grid.tracks.iter().enumerate()
+        .map(|(track_index, track)| track.stem_name(track_index))
+        .collect();
+    let mut stems_frames: Vec<Vec<StereoFrame>> = vec![Vec::new(); names.len()];
+    while let Some(tracks_frames) = track_grid.next_window_frames(&oscillator_tables) {
+        for (frames, track_frames) in stems_frames.iter_mut().zip(tracks_frames) {
+            frames.extend(track_frames);
+        }
+    }
+
+    names.into_iter().zip(stems_frames)
+        .map(|(name, frames)| Stem { name, frames })
+        .collect()
+}
+
+// Writes each track's stem to dir_path as NAME.wav, and returns the paths written. Tracks whose
+// stems would have the same name are an error, rather than one overwriting the other
+#[allow(dead_code)]
+pub(crate) fn bounce_stems<SequenceType>(track_grid: TrackGrid<SequenceType>, dir_path: &str)
+    -> Result<Vec<String>, String>
+where
+    SequenceType: NextNotes + Iterator + SetCurPosition,
+{
+    let mut names: Vec<String> = track_grid.tracks.iter().enumerate()
+        .map(|(track_index, track)| track.stem_name(track_index))
+        .collect();
+    names.sort();
+    if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(format!("More than one track has the stem name {}", pair[0]));
+    }
+
+    let render_config = track_grid.render_config;
+    let mut file_paths = Vec::new();
+    for stem in render_stems(track_grid) {
+        let file_path = std::path::Path::new(dir_path).join(format!("{}.wav", stem.name));
+        let file_path = file_path.to_str()
+            .ok_or(format!("Invalid stem path for {}", stem.name))?
+            .to_string();
+        write_audio_file(&file_path, stem.frames, &render_config)?;
+        file_paths.push(file_path);
+    }
+    Ok(file_paths)
+}
+
+// Offline bounce of a TrackGrid to a WAV file, no audio device needed. The file is written at
+// the grid's render_config sample rate
+#[allow(dead_code)]
+pub(crate) fn bounce_track_grid<SequenceType>(track_grid: TrackGrid<SequenceType>, file_path: &str)
+    -> Result<(), String>
+where
+    SequenceType: NextNotes + Iterator + SetCurPosition,
+{
+    let render_config = track_grid.render_config;
+    let (frames, clip_report) = render_track_grid_with_report(track_grid);
+    report_clipping(&clip_report);
+    write_audio_file(file_path, frames, &render_config)
+}
+
+#[cfg(test)]
+mod test_comp_utils {
+    use crate::audio_gen::master_bus::{default_master_bus, MasterBusBuilder};
+    use crate::audio_gen::oscillator::Waveform;
+    use crate::common::constants::DEFAULT_SAMPLE_RATE;
+    use crate::common::render_config::{RenderConfig, RenderConfigBuilder};
+    use crate::common::stereo::{StereoFrame, PAN_CENTER, PAN_LEFT};
+    use ringbuf::HeapRb;
+    use ringbuf::traits::{Consumer, Observer, Split};
+
+    use crate::composition::comp_utils::{bounce_stems, bounce_track_grid, render_stems,
+        render_track_grid, render_track_grid_to_ring_buffer, render_track_grid_with_report};
+    use crate::note::note::NoteBuilder;
+    use crate::note::playback_note::PlaybackNoteBuilder;
+    use crate::sequence::time_note_sequence::{TimeNoteSequence, TimeNoteSequenceBuilder};
+    use crate::track::track::TrackBuilder;
+    use crate::track::track_grid::{TrackGrid, TrackGridBuilder};
+
+    #[test]
+    fn test_render_track_grid() {
+        let frames = render_track_grid(setup_track_grid(PAN_CENTER));
+
+        assert_eq!(frames.len(), (DEFAULT_SAMPLE_RATE / 2.0) as usize);
+        assert!(frames.iter().any(|frame| frame.left != 0.0 && frame.right != 0.0));
+    }
+
+    #[test]
+    fn test_render_track_grid_track_pan() {
+        let frames = render_track_grid(setup_track_grid(PAN_LEFT));
+
+        assert!(frames.iter().any(|frame| frame.left != 0.0));
+        assert!(frames.iter().all(|frame| frame.right.abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_bounce_track_grid() {
+        let file_path = std::env::temp_dir().join("osc_test_bounce_track_grid.wav");
+        bounce_track_grid(setup_track_grid(PAN_CENTER), file_path.to_str().unwrap()).unwrap();
+
+        let reader = hound::WavReader::open(&file_path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.duration(), (DEFAULT_SAMPLE_RATE / 2.0) as u32);
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_bounce_track_grid_sample_rate() {
+        let file_path = std::env::temp_dir().join("osc_test_bounce_track_grid_sample_rate.wav");
+        let mut track_grid = setup_track_grid(PAN_CENTER);
+        track_grid.render_config = RenderConfigBuilder::default()
+            .sample_rate(48000.0)
+            .build().unwrap();
+        bounce_track_grid(track_grid, file_path.to_str().unwrap()).unwrap();
+
+        let reader = hound::WavReader::open(&file_path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 48000);
+        assert_eq!(reader.duration(), 24000);
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_render_track_grid_master_bus() {
+        let mut track_grid = setup_track_grid(PAN_CENTER);
+        track_grid.master_bus = MasterBusBuilder::default().gain_db(40.0).build().unwrap();
+        let (frames, clip_report) = render_track_grid_with_report(track_grid);
+        assert!(clip_report.num_overs > 0);
+        assert_eq!(clip_report.num_clipped, 0);
+        assert!(frames.iter().all(|frame| frame.left.abs() < 1.0 && frame.right.abs() < 1.0));
+
+        let mut track_grid = setup_track_grid(PAN_CENTER);
+        track_grid.master_bus = MasterBusBuilder::default()
+            .gain_db(40.0)
+            .limiter(false)
+            .build().unwrap();
+        let (frames, clip_report) = render_track_grid_with_report(track_grid);
+        assert!(clip_report.num_clipped > 0);
+        assert!(frames.iter().any(|frame| frame.left == 1.0));
+    }
+
+    #[test]
+    fn test_render_stems() {
+        let mut track_grid = setup_noise_track_grid(Some(42));
+        track_grid.tracks[0].name = Some(String::from("Pink Noise"));
+        let stems = render_stems(track_grid);
+        assert_eq!(stems.iter().map(|stem| stem.name.as_str()).collect::<Vec<_>>(),
+                   vec!["Pink_Noise", "track_2"]);
+
+        // both run the length of the piece, and the second is silent until its note starts
+        let num_frames = RenderConfig::default().ms_to_samples(100.0);
+        assert!(stems.iter().all(|stem| stem.frames.len() == num_frames));
+        let second_start = RenderConfig::default().ms_to_samples(40.0);
+        assert!(stems[1].frames[..second_start].iter()
+            .all(|frame| *frame == StereoFrame::default()));
+        assert!(stems[1].frames[second_start..].iter().any(|frame| frame.left != 0.0));
+
+        // together they are the mix without the master bus
+        let mut track_grid = setup_noise_track_grid(Some(42));
+        track_grid.master_bus = MasterBusBuilder::default().limiter(false).build().unwrap();
+        let mix = render_track_grid(track_grid);
+        for (i, frame) in mix.iter().enumerate() {
+            let stems_frame = stems[0].frames[i] + stems[1].frames[i];
+            assert!((frame.left - stems_frame.left).abs() < 1e-6);
+            assert!((frame.right - stems_frame.right).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_bounce_stems() {
+        let dir_path = std::env::temp_dir().join("osc_test_bounce_stems");
+        std::fs::create_dir_all(&dir_path).unwrap();
+        let file_paths = bounce_stems(setup_noise_track_grid(Some(42)),
+                                      dir_path.to_str().unwrap()).unwrap();
+        assert_eq!(file_paths.len(), 2);
+        assert!(file_paths[0].ends_with("track_1.wav"));
+        for file_path in file_paths.iter() {
+            let reader = hound::WavReader::open(file_path).unwrap();
+            assert_eq!(reader.duration(), 4410);
+        }
+        std::fs::remove_dir_all(&dir_path).unwrap();
+
+        let mut track_grid = setup_noise_track_grid(Some(42));
+        for track in track_grid.tracks.iter_mut() {
+            track.name = Some(String::from("noise"));
+        }
+        assert!(bounce_stems(track_grid, std::env::temp_dir().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_render_track_grid_to_ring_buffer() {
+        let ring_buffer = HeapRb::<StereoFrame>::new(1024);
+        let (producer, mut consumer) = ring_buffer.split();
+        let render_thread = std::thread::spawn(move || {
+            render_track_grid_to_ring_buffer(setup_track_grid(PAN_CENTER), producer);
+        });
+
+        let mut frames = Vec::new();
+        while !render_thread.is_finished() || !consumer.is_empty() {
+            match consumer.try_pop() {
+                Some(frame) => frames.push(frame),
+                None => std::thread::yield_now(),
             }
         }
-    });
+        render_thread.join().unwrap();
 
-    for playback_notes in rx.iter() {
-        gen_notes_stream(playback_notes, OscillatorTables::new());
+        // the stream is the render, delayed by the master bus's lookahead
+        let latency = default_master_bus().latency();
+        assert!(frames[..latency].iter().all(|frame| *frame == StereoFrame::default()));
+        assert_eq!(frames[latency..], render_track_grid(setup_track_grid(PAN_CENTER)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_render_track_grid_seeded_noise() {
+        let seeded = render_track_grid(setup_noise_track_grid(Some(42)));
+        assert!(seeded.iter().any(|frame| frame.left != 0.0));
+        assert_eq!(seeded, render_track_grid(setup_noise_track_grid(Some(42))));
+        assert_ne!(seeded, render_track_grid(setup_noise_track_grid(Some(43))));
+        assert_ne!(render_track_grid(setup_noise_track_grid(None)),
+                   render_track_grid(setup_noise_track_grid(None)));
+    }
+
+    // A held pink noise note with a shorter white noise note on another track, so the held
+    // note spans several windows. Quiet enough that the two together don't clip
+    fn setup_noise_track_grid(seed: Option<u64>) -> TrackGrid<TimeNoteSequence> {
+        let tracks = [(Waveform::PinkNoise, 0.0, 100.0), (Waveform::WhiteNoise, 40.0, 60.0)]
+            .into_iter()
+            .map(|(waveform, start_time_ms, end_time_ms)| {
+                let mut sequence = TimeNoteSequenceBuilder::default().build().unwrap();
+                sequence.append_note(
+                    PlaybackNoteBuilder::default()
+                        .note(
+                            NoteBuilder::default()
+                                .start_time_ms(start_time_ms)
+                                .end_time_ms(end_time_ms)
+                                .volume(0.5)
+                                .waveforms(vec![waveform])
+                                .build().unwrap()
+                        )
+                        .playback_start_time_ms(start_time_ms)
+                        .playback_end_time_ms(end_time_ms)
+                        .build().unwrap()
+                );
+                TrackBuilder::default().sequence(sequence).build().unwrap()
+            })
+            .collect();
+
+        TrackGridBuilder::default()
+            .tracks(tracks)
+            .render_config(RenderConfigBuilder::default().seed(seed).build().unwrap())
+            .build().unwrap()
+    }
+
+    fn setup_track_grid(track_pan: f32) -> TrackGrid<TimeNoteSequence> {
+        let mut sequence = TimeNoteSequenceBuilder::default().build().unwrap();
+        sequence.append_note(
+            PlaybackNoteBuilder::default()
+                .note(
+                    NoteBuilder::default()
+                        .start_time_ms(0.0)
+                        .end_time_ms(500.0)
+                        .waveforms(vec![Waveform::Sine])
+                        .build().unwrap()
+                )
+                .playback_start_time_ms(0.0)
+                .playback_end_time_ms(500.0)
+                .build().unwrap()
+        );
+
+        TrackGridBuilder::default()
+            .tracks(vec![TrackBuilder::default().sequence(sequence).pan(track_pan).build().unwrap()])
+            .build().unwrap()
+    }
+}
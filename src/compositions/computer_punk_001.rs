@@ -1,4 +1,4 @@
-use crate::audio_gen::{audio_gen, oscillator};
+use crate::audio_gen::audio_gen::default_output_render_config;
 use crate::audio_gen::oscillator::Waveform;
 use crate::composition::comp_utils;
 use crate::effect::delay::DelayBuilder;
@@ -21,20 +21,18 @@ pub(crate) fn play() {
     // Init
     println!("playing 'computer punk {}'\n", self::COMPUTER_PUNK_VERSION);
 
-    let sampled_note_volume = 0.0009;
-    let sampled_note_rev_volume = 0.0042 * 0.3;
+    // built at the device's rate, so the samples and effects play at the right pitch and speed
+    let render_config = default_output_render_config();
+    let sampled_note_volume = 0.8;
+    let sampled_note_rev_volume = 0.8 * 0.3;
 
     // Track Effect
     #[allow(unused_variables)]
     let delay = DelayBuilder::default()
-        .id(0)
-        .decay(0.5)
+        .delay_time_ms(170.0)
+        .feedback(0.5)
         .mix(0.95)
-        .interval_ms(70.0)
-        .duration_ms(100.0)
-        .num_repeats(4)
-        .num_predelay_samples(2)
-        .num_concurrent_sample_managers(4)
+        .render_config(render_config)
         .build().unwrap();
     // Envelopes
     let short_envelope = EnvelopeBuilder::default()
@@ -46,16 +44,19 @@ pub(crate) fn play() {
     let flanger = FlangerBuilder::default()
         .window_size(12)
         .mix(0.15)
+        .render_config(render_config)
         .build().unwrap();
     let flanger_2 = FlangerBuilder::default()
         .window_size(6)
         .mix(0.5)
+        .render_config(render_config)
         .build().unwrap();
     // LFOs
     let lfo = LFOBuilder::default()
         .waveforms(vec![Waveform::Sine])
         .frequency(110.0)
         .amplitude(0.5)
+        .render_config(render_config)
         .build().unwrap();
     // /Track Effects
     
@@ -77,6 +78,7 @@ pub(crate) fn play() {
         vec![flanger.clone()],
         vec![delay.clone()],
         vec![lfo.clone()],
+        &render_config,
     );
 
     let mut piano_note_1_rev = piano_note_1.clone();
@@ -172,25 +174,20 @@ pub(crate) fn play() {
     //     adjust_note_start_end_time(
     //         &mut guitar_note_1_rev, 3.0 * note_dur, note_dur));
 
-    let mut tracks = Vec::new();
-    tracks.push(piano_track_1);
-    tracks.push(piano_track_2);
-    // tracks.push(guitar_track_1);
+    let tracks = vec![
+        piano_track_1,
+        piano_track_2,
+        // guitar_track_1,
+    ];
 
     // Load and play Track Grid
     let track_grid =
         TrackGridBuilder::<TimeNoteSequence>::default()
         .tracks(tracks)
+        .render_config(render_config)
         .build().unwrap();
 
-    let (tx, rx) = std::sync::mpsc::channel();
-    std::thread::spawn(move || {
-        for playback_notes in track_grid {
-            tx.send(playback_notes).unwrap();
-        }
-    });
-
-    for playback_notes in rx.iter() {
-        audio_gen::gen_notes_stream(playback_notes, oscillator::OscillatorTables::new());
+    if let Err(e) = comp_utils::play_track_grid(track_grid) {
+        eprintln!("{}", e);
     }
 }
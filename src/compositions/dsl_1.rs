@@ -1,4 +1,5 @@
-use crate::dsl::parser::parse_dsl;
+use crate::audio_gen::audio_gen::default_output_render_config;
+use crate::dsl::parser::parse_dsl_with_config;
 use crate::composition::comp_utils::play_track_grid;
 
 pub(crate) fn play() {
@@ -7,11 +8,11 @@ pub(crate) fn play() {
     let input = r#"
 let env1 = a 0.3,0.4 d 0.5,0.6 s 0.6,0.5 r 1.0,0.0
 let env2 = a 0.1,0.9 d 0.2,0.6 s 0.8,0.6 r 1.0,0.0
-let delay1 = delay mix 0.5 decay 1.0 interval_ms 30.0 duration_ms 60.0 num_repeats 3 num_predelay_samples 30 num_concurrent_delays 2
+let delay1 = delay time_ms 90.0 feedback 0.9 mix 0.5
 let flanger1 = flanger window_size 35 mix 0.75
-let samp1 = samp:/Users/markweiss/Downloads/punk_computer/003/piano_note_1_clipped.wav:0.0006:{step}
-let C5 = osc:sine,sine,sawtooth,sawtooth,sine,sine:5,C:0.1:{step}
-let G5 = osc:sine,sine,sawtooth,sawtooth,sine,sine:5,G:0.1:{step} 
+let samp1 = samp:/Users/markweiss/Downloads/punk_computer/003/piano_note_1_clipped.wav:0.6:{step}
+let C5 = osc:sine/lvl=4.0,sawtooth/lvl=2.0:5,C:0.1:{step}
+let G5 = osc:sine/lvl=4.0,sawtooth/lvl=2.0:5,G:0.1:{step}
 
 FixedTimeNoteSequence dur Quarter tempo 12 num_steps 16
 $env1
@@ -49,5 +50,9 @@ $delay1
 apply step:(range 3,31,4) $G5
 "#;
 
-    play_track_grid( parse_dsl(input).unwrap());
+    // parse at the device's rate, so the effects and samples are built for the rate it plays at
+    let track_grid = parse_dsl_with_config(input, default_output_render_config()).unwrap();
+    if let Err(e) = play_track_grid(track_grid) {
+        eprintln!("{}", e);
+    }
 }
\ No newline at end of file
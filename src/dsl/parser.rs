@@ -2,23 +2,39 @@ use std::str::FromStr;
 use std::collections::HashMap;
 use regex;
 
-use crate::audio_gen::oscillator::Waveform;
+use crate::audio_gen::band_limit::BandLimit;
+use crate::audio_gen::oscillator::{Waveform, DEFAULT_PULSE_WIDTH};
+use crate::audio_gen::wavetable::{register_wavetable, wavetable_id, Wavetable, WavetableId};
+use crate::common::render_config::RenderConfig;
 use crate::effect::delay::{DelayBuilder};
-use crate::effect::flanger::{FlangerBuilder};
+use crate::effect::filter::{FilterBuilder, FilterMode};
+use crate::effect::reverb::ReverbBuilder;
+use crate::effect::flanger::{FlangerBuilder, FlangerMode};
 use crate::effect::lfo::{LFOBuilder};
-use crate::envelope::envelope::{EnvelopeBuilder};
+use crate::envelope::envelope::{Envelope, EnvelopeBuilder};
 use crate::envelope::envelope_pair::EnvelopePair;
+use crate::envelope::modulation_envelope::{ModulationEnvelope, ModulationEnvelopeBuilder};
 use crate::meter::durations::DurationType as MeterDurationType;
+use crate::note::additive_note::{AdditiveNoteBuilder, PartialBuilder};
+use crate::note::fm_note::{FMAlgorithm, FMNoteBuilder, FMOperatorBuilder};
 use crate::note::note::{NoteBuilder};
+use crate::note::oscillator_layer::{OscillatorLayer, OscillatorLayerBuilder};
 use crate::note::playback_note::{NoteType, PlaybackNote, PlaybackNoteBuilder};
 use crate::note::sampled_note::{SampledNoteBuilder};
 use crate::note::scales::WesternPitch;
+use crate::note::unison::{Unison, UnisonBuilder};
 use crate::sequence::fixed_time_note_sequence::{FixedTimeNoteSequence, FixedTimeNoteSequenceBuilder};
 use crate::sequence::note_sequence_trait::AppendNote;
 use crate::track::track::{Track, TrackBuilder};
 use crate::track::track_effects::{TrackEffects, TrackEffectsBuilder};
 use crate::track::track_grid::{TrackGrid, TrackGridBuilder};
 
+// Where FM operator and additive partial envelopes start their release, as a fraction of the note
+static SINE_RELEASE_POSITION: f32 = 0.98;
+
+// An `apply` line parsed into its `key:value,...` arguments and the identifier at the end
+type ApplyDef = (HashMap<String, Vec<String>>, String);
+
 #[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub enum DslDurationType {
@@ -85,6 +101,13 @@ pub enum WaveformType {
     Saw,
     GaussianNoise,
     Noise,
+    WhiteNoise,
+    PinkNoise,
+    BrownNoise,
+    // duty cycle from 0.0 to 1.0
+    Pulse(f32),
+    // registered wavetable and the position from 0.0 to 1.0 across its frames
+    Wavetable(WavetableId, f32),
 }
 
 impl FromStr for WaveformType {
@@ -97,7 +120,37 @@ impl FromStr for WaveformType {
             "triangle" | "tri" => Ok(WaveformType::Triangle),
             "sawtooth" | "saw" => Ok(WaveformType::Sawtooth),
             "gaussiannoise" | "noise" => Ok(WaveformType::GaussianNoise),
-            _ => Err(format!("Unknown waveform: {}", s)),
+            "whitenoise" | "white" => Ok(WaveformType::WhiteNoise),
+            "pinknoise" | "pink" => Ok(WaveformType::PinkNoise),
+            "brownnoise" | "brown" => Ok(WaveformType::BrownNoise),
+            "pulse" | "pls" => Ok(WaveformType::Pulse(DEFAULT_PULSE_WIDTH)),
+            // split the original string, since wavetable names are case sensitive
+            _ => match s.split_once('=') {
+                Some((key, value)) => match key.to_lowercase().as_str() {
+                    "pulse" | "pls" => {
+                        let width = value.parse::<f32>()
+                            .map_err(|_| format!("Invalid pulse width: {}", value))?;
+                        if width <= 0.0 || width >= 1.0 {
+                            return Err(format!("Pulse width must be between 0.0 and 1.0: {}",
+                                               width));
+                        }
+                        Ok(WaveformType::Pulse(width))
+                    }
+                    "wavetable" | "wt" => {
+                        let (name, position) = match value.split_once('@') {
+                            Some((name, position)) => (name, position.parse::<f32>()
+                                .map_err(|_| format!("Invalid wavetable position: {}",
+                                                     position))?),
+                            None => (value, 0.0),
+                        };
+                        let id = wavetable_id(name)
+                            .ok_or_else(|| format!("Unknown wavetable: {}", name))?;
+                        Ok(WaveformType::Wavetable(id, position))
+                    }
+                    _ => Err(format!("Unknown waveform: {}", s)),
+                },
+                None => Err(format!("Unknown waveform: {}", s)),
+            },
         }
     }
 }
@@ -106,11 +159,75 @@ impl WaveformType {
     fn to_waveform(&self) -> Waveform {
         match self {
             WaveformType::Sine | WaveformType::Sin => Waveform::Sine,
-            WaveformType::Square | WaveformType::Sqr => Waveform::Square,
-            WaveformType::Triangle | WaveformType::Tri => Waveform::Triangle,
-            WaveformType::Sawtooth | WaveformType::Saw => Waveform::Saw,
+            WaveformType::Square | WaveformType::Sqr => Waveform::Square(BandLimit::default()),
+            WaveformType::Triangle | WaveformType::Tri => Waveform::Triangle(BandLimit::default()),
+            WaveformType::Sawtooth | WaveformType::Saw => Waveform::Saw(BandLimit::default()),
             WaveformType::GaussianNoise | WaveformType::Noise => Waveform::GaussianNoise,
+            WaveformType::WhiteNoise => Waveform::WhiteNoise,
+            WaveformType::PinkNoise => Waveform::PinkNoise,
+            WaveformType::BrownNoise => Waveform::BrownNoise,
+            WaveformType::Pulse(width) => Waveform::Pulse(BandLimit::default(), *width),
+            WaveformType::Wavetable(id, position) => Waveform::Wavetable(*id, *position),
+        }
+    }
+}
+
+// A waveform of an osc note and its level, pitch offset from the note and start phase, written
+// as the waveform followed by any of /lvl=F, /oct=N, /semi=N, /cent=F and /phase=F
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub struct LayerDef {
+    pub waveform: WaveformType,
+    pub level: f32,
+    pub octaves: i32,
+    pub semitones: i32,
+    pub cents: f32,
+    pub phase: f32,
+}
+
+impl FromStr for LayerDef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        let mut layer_def = LayerDef {
+            waveform: WaveformType::from_str(parts.next().unwrap_or_default())?,
+            level: 1.0,
+            octaves: 0,
+            semitones: 0,
+            cents: 0.0,
+            phase: 0.0,
+        };
+        for part in parts {
+            let (key, value) = part.split_once('=')
+                .ok_or_else(|| format!("Invalid layer setting: {}", part))?;
+            let invalid = || format!("Invalid layer {}: {}", key, value);
+            let parse_f32 = || value.parse::<f32>().map_err(|_| invalid());
+            let parse_i32 = || value.parse::<i32>().map_err(|_| invalid());
+            match key.to_lowercase().as_str() {
+                "lvl" | "level" => layer_def.level = parse_f32()?,
+                "oct" | "octave" => layer_def.octaves = parse_i32()?,
+                "semi" => layer_def.semitones = parse_i32()?,
+                "cent" | "cents" => layer_def.cents = parse_f32()?,
+                "phase" => layer_def.phase = parse_f32()?,
+                _ => return Err(format!("Unknown layer setting: {}", key)),
+            }
         }
+        Ok(layer_def)
+    }
+}
+
+impl LayerDef {
+    fn to_layer(&self) -> Result<OscillatorLayer, String> {
+        OscillatorLayerBuilder::default()
+            .waveform(self.waveform.to_waveform())
+            .level(self.level)
+            .octaves(self.octaves)
+            .semitones(self.semitones)
+            .cents(self.cents)
+            .phase(self.phase)
+            .build()
+            .map_err(|e| format!("Failed to build OscillatorLayer: {:?}", e))
     }
 }
 
@@ -188,23 +305,44 @@ impl WesternPitchType {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct DelayDef {
+    pub time_ms: f32,
+    pub feedback: f32,
     pub mix: f32,
-    pub decay: f32,
-    pub interval_ms: f32,
-    pub duration_ms: f32,
-    pub num_repeats: usize,
-    pub num_predelay_samples: usize,
-    pub num_concurrent_delays: usize,
+    // cutoff of a low-pass in the feedback path, None for unfiltered echoes
+    pub cutoff: Option<f32>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct FlangerDef {
     pub window_size: usize,
     pub mix: f32,
+    pub rate: f32,
+    pub depth: f32,
+    pub feedback: f32,
+    // voices of a chorus, None for a flanger
+    pub chorus_voices: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub struct FilterDef {
+    pub mode: FilterMode,
+    pub cutoff: f32,
+    pub q: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub struct ReverbDef {
+    pub room_size: f32,
+    pub damping: f32,
+    pub predelay_ms: f32,
+    pub width: f32,
+    pub mix: f32,
 }
 
 #[derive(Debug, Clone)]
@@ -221,6 +359,30 @@ pub enum EffectDef {
     Delay(DelayDef),
     Flanger(FlangerDef),
     LFO(LFODef),
+    Filter(FilterDef),
+    Reverb(ReverbDef),
+    PitchEnvelope(ModulationEnvelopeDef),
+    CutoffEnvelope(ModulationEnvelopeDef),
+    // scales the wavetable position of every osc note in the block over the note
+    WavetablePositionEnvelope(EnvelopeDef),
+}
+
+// An envelope that modulates pitch, in semitones, or filter cutoff, in octaves, by depth
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct ModulationEnvelopeDef {
+    pub depth: f32,
+    pub envelope_def: EnvelopeDef,
+}
+
+// Unison applied to every osc note in the block
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub struct UnisonDef {
+    pub num_voices: usize,
+    pub detune_cents: f32,
+    pub stereo_spread: f32,
+    pub random_phase: f32,
 }
 
 #[derive(Debug, Clone)]
@@ -240,11 +402,22 @@ pub struct SequenceDef {
     pub num_steps: usize,
 }
 
+// A sine of an FM or additive note, an FM operator or an additive partial. Its frequency ratio to
+// the note and level, and optionally its envelope as the position its attack ends at full level,
+// the position its decay ends and the level it then sustains at
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub struct SineDef {
+    pub ratio: f32,
+    pub level: f32,
+    pub envelope: Option<(f32, f32, f32)>,
+}
+
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub enum NoteDeclaration {
     Oscillator {
-        waveforms: Vec<WaveformType>,
+        layers: Vec<LayerDef>,
         note_freq: f32,
         volume: f32,
         step_index: usize,
@@ -254,6 +427,19 @@ pub enum NoteDeclaration {
         volume: f32,
         step_index: usize,
     },
+    FM {
+        algorithm: FMAlgorithm,
+        operators: Vec<SineDef>,
+        note_freq: f32,
+        volume: f32,
+        step_index: usize,
+    },
+    Additive {
+        partials: Vec<SineDef>,
+        note_freq: f32,
+        volume: f32,
+        step_index: usize,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -262,9 +448,27 @@ pub struct OuterBlock {
     pub sequence_def: SequenceDef,
     pub envelope_defs: Vec<EnvelopeDef>,
     pub effect_defs: Vec<EffectDef>,
+    pub unison_def: Option<UnisonDef>,
     pub note_declarations: Vec<NoteDeclaration>,
 }
 
+// Where a declared wavetable's frames come from
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub enum WavetableSource {
+    // a WAV file of num_frames consecutive single cycles
+    File { file_path: String, num_frames: usize },
+    // a list of harmonic amplitudes per frame
+    Harmonics(Vec<Vec<f32>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub struct WavetableDef {
+    pub name: String,
+    pub source: WavetableSource,
+}
+
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct MacroDef {
@@ -276,6 +480,7 @@ pub struct MacroDef {
 #[allow(dead_code)]
 pub struct Script {
     pub macro_defs: HashMap<String, String>,
+    pub wavetable_defs: Vec<WavetableDef>,
     pub outer_blocks: Vec<OuterBlock>,
 }
 
@@ -283,6 +488,7 @@ pub struct Script {
 pub struct Parser {
     tokens: Vec<String>,
     current: usize,
+    render_config: RenderConfig,
 }
 
 impl Parser {
@@ -308,6 +514,7 @@ impl Parser {
         Self {
             tokens,
             current: 0,
+            render_config: RenderConfig::default(),
         }
     }
 
@@ -381,16 +588,12 @@ impl Parser {
 
         let mut lines: Vec<String> = input.lines().map(|s| s.to_string()).collect();
 
-        let mut i = 0;
-        let lines_len = lines.len();
-        while i < lines_len {
-            let mut line_content = lines[i].trim();
-            let mut chars = line_content.chars().peekable();
+        for line in lines.iter_mut() {
+            let line_content = line.trim();
             let mut in_generator = false;
             let mut j: usize= 0;
             let mut lbound: usize= 0;
-            let mut rbound: usize = 0;
-            while let Some(ch) = chars.next() {
+            for ch in line_content.chars() {
                 if ch == '\n' {
                     break;
                 }
@@ -405,19 +608,17 @@ impl Parser {
                     continue;
 
                 } else if ch == ')' {
-                    rbound = j;
+                    let rbound = j;
                     let generated =
                         Self::call_generator_with_args(&line_content[lbound..rbound + 1])
                             .unwrap_or("parse of generator failed".to_string());
-                    lines[i] = line_content.replace(&line_content[lbound..rbound + 1], &generated);
-                    in_generator = false;
+                    *line = line_content.replace(&line_content[lbound..rbound + 1], &generated);
                     break;
                 }
                 j += 1;
             }
-            i += 1;
         }
-        return Ok(lines.join("\n"));
+        Ok(lines.join("\n"))
     }
 
     fn call_generator_with_args(generator_substring: &str) -> Result<String, String> {
@@ -489,7 +690,7 @@ impl Parser {
     }
 
     #[allow(dead_code)]
-    fn parse_apply_def(line: &str) -> Result<Option<(HashMap<String, Vec<String>>, String)>, String> {
+    fn parse_apply_def(line: &str) -> Result<Option<ApplyDef>, String> {
         let mut parts: Vec<&str> = line.split_whitespace().collect();
         if parts.len() < 3 || parts[0] != "apply" {
             return Ok(None);
@@ -499,7 +700,7 @@ impl Parser {
         for token in parts.iter_mut() {
             if token.contains(":") {
                 let key = token.split(":").next().unwrap().to_string();
-                if key == "osc" || key == "samp" {
+                if key == "osc" || key == "samp" || key == "fm" || key == "add" {
                     continue;
                 }  
                 let value =
@@ -610,6 +811,7 @@ impl Parser {
 
     fn parse_script(&mut self) -> Result<Script, String> {
         let mut macro_defs = HashMap::new();
+        let mut wavetable_defs = Vec::new();
         let mut outer_blocks = Vec::new();
         
         // Parse macro definitions first
@@ -617,6 +819,13 @@ impl Parser {
             let (name, expression) = self.parse_assignment()?;
             macro_defs.insert(name, expression);
         }
+
+        // Parse wavetable declarations, registering each before the notes that play it are parsed
+        while self.current < self.tokens.len() && self.peek() == "wavetable" {
+            let wavetable_def = self.parse_wavetable_def()?;
+            Self::register_wavetable_def(&wavetable_def)?;
+            wavetable_defs.push(wavetable_def);
+        }
         
         // Parse outer blocks
         while self.current < self.tokens.len() && !self.is_comment_start() {
@@ -626,10 +835,44 @@ impl Parser {
 
         Ok(Script { 
             macro_defs,
+            wavetable_defs,
             outer_blocks 
         })
     }
 
+    fn parse_wavetable_def(&mut self) -> Result<WavetableDef, String> {
+        self.skip_comment_lines();
+
+        self.expect("wavetable")?;
+        let name = self.advance();
+        let source = match self.advance().as_str() {
+            "file" => {
+                // the path is split into tokens at any ':' or ',' in it, so join them back up
+                let mut file_path = String::new();
+                while self.current < self.tokens.len() && self.peek() != "frames" {
+                    file_path.push_str(&self.advance());
+                }
+                self.expect("frames")?;
+                let num_frames = self.parse_usize()?;
+                WavetableSource::File { file_path, num_frames }
+            }
+            "harmonics" => {
+                let mut frames = vec![self.parse_f32_list()?];
+                while self.peek() == "harmonics" {
+                    self.advance();
+                    frames.push(self.parse_f32_list()?);
+                }
+                WavetableSource::Harmonics(frames)
+            }
+            token => return Err(format!("Unknown wavetable source: {}", token)),
+        };
+
+        Ok(WavetableDef {
+            name,
+            source,
+        })
+    }
+
     fn parse_outer_block(&mut self) -> Result<OuterBlock, String> {
         let sequence_def = self.parse_sequence_def()?;
         let mut envelope_defs = Vec::new();
@@ -648,6 +891,12 @@ impl Parser {
             effect_defs.push(effect_def);
         }
 
+        // Parse optional unison definition
+        let mut unison_def = None;
+        if self.current < self.tokens.len() && self.peek() == "unison" {
+            unison_def = Some(self.parse_unison_def()?);
+        }
+
         // Parse note declarations
         while self.current < self.tokens.len() && self.is_note_declaration_start() {
             let note_declaration = self.parse_note_declaration()?;
@@ -658,6 +907,7 @@ impl Parser {
             sequence_def,
             envelope_defs,
             effect_defs,
+            unison_def,
             note_declarations,
         })
     }
@@ -721,6 +971,19 @@ impl Parser {
             self.parse_flanger_def()
         } else if self.peek() == "lfo" {
             self.parse_lfo_def()
+        } else if self.peek() == "filter" {
+            self.parse_filter_def()
+        } else if self.peek() == "reverb" {
+            self.parse_reverb_def()
+        } else if self.peek() == "pitch_env" {
+            self.advance();
+            Ok(EffectDef::PitchEnvelope(self.parse_modulation_envelope_def()?))
+        } else if self.peek() == "cutoff_env" {
+            self.advance();
+            Ok(EffectDef::CutoffEnvelope(self.parse_modulation_envelope_def()?))
+        } else if self.peek() == "wt_env" {
+            self.advance();
+            Ok(EffectDef::WavetablePositionEnvelope(self.parse_envelope_def()?))
         } else {
             Err(format!("Unknown effect type: {}", self.peek()))
         }
@@ -730,29 +993,24 @@ impl Parser {
         self.skip_comment_lines();
 
         self.expect("delay")?;
+        self.expect("time_ms")?;
+        let time_ms = self.parse_f32()?;
+        self.expect("feedback")?;
+        let feedback = self.parse_f32()?;
         self.expect("mix")?;
         let mix = self.parse_f32()?;
-        self.expect("decay")?;
-        let decay = self.parse_f32()?;
-        self.expect("interval_ms")?;
-        let interval_ms = self.parse_f32()?;
-        self.expect("duration_ms")?;
-        let duration_ms = self.parse_f32()?;
-        self.expect("num_repeats")?;
-        let num_repeats = self.parse_usize()?;
-        self.expect("num_predelay_samples")?;
-        let num_predelay_samples = self.parse_usize()?;
-        self.expect("num_concurrent_delays")?;
-        let num_concurrent_delays = self.parse_usize()?;
+        // the feedback filter is optional
+        let mut cutoff = None;
+        if self.peek() == "cutoff" {
+            self.advance();
+            cutoff = Some(self.parse_f32()?);
+        }
 
         Ok(EffectDef::Delay(DelayDef {
+            time_ms,
+            feedback,
             mix,
-            decay,
-            interval_ms,
-            duration_ms,
-            num_repeats,
-            num_predelay_samples,
-            num_concurrent_delays,
+            cutoff,
         }))
     }
 
@@ -764,11 +1022,39 @@ impl Parser {
         let window_size = self.parse_usize()?;
         self.expect("mix")?;
         let mix = self.parse_f32()?;
-
-        Ok(EffectDef::Flanger(FlangerDef {
+        let mut flanger_def = FlangerDef {
             window_size,
             mix,
-        }))
+            rate: 0.25,
+            depth: 0.0,
+            feedback: 0.0,
+            chorus_voices: None,
+        };
+
+        // the sweep, feedback and chorus are optional, in any order
+        loop {
+            match self.peek() {
+                "rate" => {
+                    self.advance();
+                    flanger_def.rate = self.parse_f32()?;
+                }
+                "depth" => {
+                    self.advance();
+                    flanger_def.depth = self.parse_f32()?;
+                }
+                "feedback" => {
+                    self.advance();
+                    flanger_def.feedback = self.parse_f32()?;
+                }
+                "chorus" => {
+                    self.advance();
+                    flanger_def.chorus_voices = Some(self.parse_usize()?);
+                }
+                _ => break,
+            }
+        }
+
+        Ok(EffectDef::Flanger(flanger_def))
     }
 
     fn parse_lfo_def(&mut self) -> Result<EffectDef, String> {
@@ -789,6 +1075,101 @@ impl Parser {
         }))
     }
 
+    fn parse_filter_def(&mut self) -> Result<EffectDef, String> {
+        self.skip_comment_lines();
+
+        self.expect("filter")?;
+        let mode = self.parse_filter_mode()?;
+        self.expect("cutoff")?;
+        let cutoff = self.parse_f32()?;
+        self.expect("q")?;
+        let q = self.parse_f32()?;
+
+        Ok(EffectDef::Filter(FilterDef {
+            mode,
+            cutoff,
+            q,
+        }))
+    }
+
+    fn parse_reverb_def(&mut self) -> Result<EffectDef, String> {
+        self.skip_comment_lines();
+
+        self.expect("reverb")?;
+        self.expect("room_size")?;
+        let room_size = self.parse_f32()?;
+        self.expect("damping")?;
+        let damping = self.parse_f32()?;
+        self.expect("predelay_ms")?;
+        let predelay_ms = self.parse_f32()?;
+        self.expect("width")?;
+        let width = self.parse_f32()?;
+        self.expect("mix")?;
+        let mix = self.parse_f32()?;
+
+        Ok(EffectDef::Reverb(ReverbDef {
+            room_size,
+            damping,
+            predelay_ms,
+            width,
+            mix,
+        }))
+    }
+
+    fn parse_modulation_envelope_def(&mut self) -> Result<ModulationEnvelopeDef, String> {
+        self.expect("depth")?;
+        let depth = self.parse_f32()?;
+        let envelope_def = self.parse_envelope_def()?;
+
+        Ok(ModulationEnvelopeDef {
+            depth,
+            envelope_def,
+        })
+    }
+
+    fn parse_filter_mode(&mut self) -> Result<FilterMode, String> {
+        let token = self.advance();
+        match token.to_lowercase().as_str() {
+            "lp" | "lowpass" => Ok(FilterMode::LowPass),
+            "hp" | "highpass" => Ok(FilterMode::HighPass),
+            "bp" | "bandpass" => Ok(FilterMode::BandPass),
+            "notch" => Ok(FilterMode::Notch),
+            _ => Err(format!("Unknown filter mode: {}", token)),
+        }
+    }
+
+    fn parse_unison_def(&mut self) -> Result<UnisonDef, String> {
+        self.skip_comment_lines();
+
+        self.expect("unison")?;
+        let num_voices = self.parse_usize()?;
+        self.expect("detune")?;
+        let detune_cents = self.parse_f32()?;
+        let mut unison_def = UnisonDef {
+            num_voices,
+            detune_cents,
+            stereo_spread: 0.0,
+            random_phase: 0.0,
+        };
+
+        // spread and phase are optional, in either order
+        loop {
+            match self.peek() {
+                "spread" => {
+                    self.advance();
+                    unison_def.stereo_spread = self.parse_f32()?;
+                }
+                "phase" => {
+                    self.advance();
+                    unison_def.random_phase = self.parse_f32()?;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(unison_def)
+    }
+
     fn parse_waveforms(&mut self) -> Result<Vec<WaveformType>, String> {
         let mut waveforms = Vec::new();
         
@@ -806,6 +1187,23 @@ impl Parser {
         Ok(waveforms)
     }
 
+    fn parse_layer_defs(&mut self) -> Result<Vec<LayerDef>, String> {
+        let mut layer_defs = Vec::new();
+
+        loop {
+            let token = self.advance();
+            layer_defs.push(LayerDef::from_str(&token)?);
+
+            if self.peek() == "," {
+                self.advance(); // consume comma
+            } else {
+                break;
+            }
+        }
+
+        Ok(layer_defs)
+    }
+
     fn parse_waveform(&mut self) -> Result<WaveformType, String> {
         let token = self.advance();
         WaveformType::from_str(&token)
@@ -816,6 +1214,10 @@ impl Parser {
             self.parse_osc_note()
         } else if self.peek() == "samp" {
             self.parse_samp_note()
+        } else if self.peek() == "fm" {
+            self.parse_fm_note()
+        } else if self.peek() == "add" {
+            self.parse_additive_note()
         } else {
             Err(format!("Unknown note type: {}", self.peek()))
         }
@@ -826,7 +1228,7 @@ impl Parser {
 
         self.expect("osc")?;
         self.expect(":")?;
-        let waveforms = self.parse_waveforms()?;
+        let layers = self.parse_layer_defs()?;
         self.expect(":")?;
         let note_freq = self.parse_note_freq()?;
         self.expect(":")?;
@@ -835,7 +1237,7 @@ impl Parser {
         let step_index = self.parse_usize()?;
 
         Ok(NoteDeclaration::Oscillator {
-            waveforms,
+            layers,
             note_freq,
             volume,
             step_index,
@@ -860,6 +1262,101 @@ impl Parser {
         })
     }
 
+    fn parse_fm_note(&mut self) -> Result<NoteDeclaration, String> {
+        self.skip_comment_lines();
+
+        self.expect("fm")?;
+        self.expect(":")?;
+        let algorithm = self.parse_fm_algorithm()?;
+        self.expect(":")?;
+        let operators = self.parse_sine_defs()?;
+        self.expect(":")?;
+        let note_freq = self.parse_note_freq()?;
+        self.expect(":")?;
+        let volume = self.parse_f32()?;
+        self.expect(":")?;
+        let step_index = self.parse_usize()?;
+
+        Ok(NoteDeclaration::FM {
+            algorithm,
+            operators,
+            note_freq,
+            volume,
+            step_index,
+        })
+    }
+
+    fn parse_fm_algorithm(&mut self) -> Result<FMAlgorithm, String> {
+        let token = self.advance();
+        match token.to_lowercase().as_str() {
+            "stack" => Ok(FMAlgorithm::Stack),
+            "parallel" => Ok(FMAlgorithm::Parallel),
+            "pairs" => Ok(FMAlgorithm::Pairs),
+            "branch" => Ok(FMAlgorithm::Branch),
+            _ => Err(format!("Unknown FM algorithm: {}", token)),
+        }
+    }
+
+    fn parse_additive_note(&mut self) -> Result<NoteDeclaration, String> {
+        self.skip_comment_lines();
+
+        self.expect("add")?;
+        self.expect(":")?;
+        let partials = self.parse_sine_defs()?;
+        self.expect(":")?;
+        let note_freq = self.parse_note_freq()?;
+        self.expect(":")?;
+        let volume = self.parse_f32()?;
+        self.expect(":")?;
+        let step_index = self.parse_usize()?;
+
+        Ok(NoteDeclaration::Additive {
+            partials,
+            note_freq,
+            volume,
+            step_index,
+        })
+    }
+
+    fn parse_sine_defs(&mut self) -> Result<Vec<SineDef>, String> {
+        let mut sine_defs = Vec::new();
+
+        loop {
+            let sine_def = Self::parse_sine_def(&self.advance())?;
+            sine_defs.push(sine_def);
+
+            if self.peek() == "," {
+                self.advance(); // consume comma
+            } else {
+                break;
+            }
+        }
+
+        Ok(sine_defs)
+    }
+
+    // RATIO/LEVEL or RATIO/LEVEL/ATTACK/DECAY/SUSTAIN
+    fn parse_sine_def(token: &str) -> Result<SineDef, String> {
+        let values = token.split('/')
+            .map(|value| value.parse::<f32>()
+                .map_err(|_| format!("Invalid operator or partial value: {}", value)))
+            .collect::<Result<Vec<f32>, String>>()?;
+        match values.as_slice() {
+            [ratio, level] => Ok(SineDef {
+                ratio: *ratio,
+                level: *level,
+                envelope: None,
+            }),
+            [ratio, level, attack, decay, sustain] => Ok(SineDef {
+                ratio: *ratio,
+                level: *level,
+                envelope: Some((*attack, *decay, *sustain)),
+            }),
+            _ => Err(format!("Operators and partials must be RATIO/LEVEL or \
+                             RATIO/LEVEL/ATTACK/DECAY/SUSTAIN: {}", token)),
+        }
+    }
+
     fn parse_note_freq(&mut self) -> Result<f32, String> {
         let token = self.advance();
         
@@ -915,11 +1412,14 @@ impl Parser {
     }
 
     fn is_effect_start(&self) -> bool {
-        self.peek() == "delay" || self.peek() == "flanger" || self.peek() == "lfo"
+        self.peek() == "delay" || self.peek() == "flanger" || self.peek() == "lfo" ||
+            self.peek() == "filter" || self.peek() == "reverb" || self.peek() == "pitch_env" ||
+            self.peek() == "cutoff_env" || self.peek() == "wt_env"
     }
 
     fn is_note_declaration_start(&self) -> bool {
-        self.peek() == "osc" || self.peek() == "samp"
+        self.peek() == "osc" || self.peek() == "samp" || self.peek() == "fm" ||
+            self.peek() == "add"
     }
 
     fn is_comment_start(&self) -> bool {
@@ -958,6 +1458,15 @@ impl Parser {
         token.parse::<f32>().map_err(|_| format!("Invalid float: {}", token))
     }
 
+    fn parse_f32_list(&mut self) -> Result<Vec<f32>, String> {
+        let mut values = vec![self.parse_f32()?];
+        while self.peek() == "," {
+            self.advance(); // consume comma
+            values.push(self.parse_f32()?);
+        }
+        Ok(values)
+    }
+
     fn parse_u8(&mut self) -> Result<u8, String> {
         let token = self.advance();
         token.parse::<u8>().map_err(|_| format!("Invalid u8: {}", token))
@@ -978,6 +1487,7 @@ impl Parser {
 
         TrackGridBuilder::default()
             .tracks(tracks)
+            .render_config(self.render_config)
             .build()
             .map_err(|e| format!("Failed to build TrackGrid: {:?}", e))
     }
@@ -990,9 +1500,25 @@ impl Parser {
         let track_effects = self.build_track_effects(&block.envelope_defs, &block.effect_defs)?;
         
         // Add notes to sequence
+        let unison = block.unison_def.as_ref().map(Self::build_unison).transpose()?;
+        let mut wavetable_position_envelope = None;
+        for effect_def in &block.effect_defs {
+            if let EffectDef::WavetablePositionEnvelope(env_def) = effect_def {
+                if wavetable_position_envelope.is_some() {
+                    return Err(String::from("Only one wt_env is allowed per block"));
+                }
+                wavetable_position_envelope = Some(Self::build_envelope(env_def)?);
+            }
+        }
         let mut sequence_with_notes = sequence;
         for note_decl in &block.note_declarations {
-            let playback_note = self.build_playback_note(note_decl, &block.sequence_def)?;
+            let mut playback_note = self.build_playback_note(note_decl, &block.sequence_def)?;
+            if let (Some(unison), NoteType::Oscillator) = (unison, playback_note.note_type) {
+                playback_note.note.unison = unison;
+            }
+            if playback_note.note_type == NoteType::Oscillator {
+                playback_note.wavetable_position_envelope = wavetable_position_envelope;
+            }
             sequence_with_notes.append_note(playback_note);
         }
 
@@ -1029,40 +1555,51 @@ impl Parser {
         let mut delays = Vec::new();
         let mut flangers = Vec::new();
         let mut lfos = Vec::new();
+        let mut filters = Vec::new();
+        let mut reverbs = Vec::new();
+        let mut pitch_envelope = None;
+        let mut cutoff_envelope = None;
 
         // Build envelopes
         for env_def in envelope_defs {
-            let envelope = EnvelopeBuilder::default()
-                .attack(EnvelopePair(env_def.attack.0, env_def.attack.1))
-                .decay(EnvelopePair(env_def.decay.0, env_def.decay.1))
-                .sustain(EnvelopePair(env_def.sustain.0, env_def.sustain.1))
-                .release(EnvelopePair(env_def.release.0, env_def.release.1))
-                .build()
-                .map_err(|e| format!("Failed to build Envelope: {:?}", e))?;
-            envelopes.push(envelope);
+            envelopes.push(Self::build_envelope(env_def)?);
         }
 
         // Build effects
         for effect_def in effect_defs {
             match effect_def {
                 EffectDef::Delay(delay_def) => {
+                    let feedback_filter = match delay_def.cutoff {
+                        Some(cutoff) => Some(FilterBuilder::default()
+                            .cutoff_hz(cutoff)
+                            .render_config(self.render_config)
+                            .build()
+                            .map_err(|e| format!("Failed to build Delay: {:?}", e))?),
+                        None => None,
+                    };
                     let delay = DelayBuilder::default()
-                        .id(0) // Default ID
+                        .delay_time_ms(delay_def.time_ms)
+                        .feedback(delay_def.feedback)
                         .mix(delay_def.mix)
-                        .decay(delay_def.decay)
-                        .interval_ms(delay_def.interval_ms)
-                        .duration_ms(delay_def.duration_ms)
-                        .num_repeats(delay_def.num_repeats)
-                        .num_predelay_samples(delay_def.num_predelay_samples)
-                        .num_concurrent_sample_managers(delay_def.num_concurrent_delays)
+                        .feedback_filter(feedback_filter)
+                        .render_config(self.render_config)
                         .build()
                         .map_err(|e| format!("Failed to build Delay: {:?}", e))?;
                     delays.push(delay);
                 }
                 EffectDef::Flanger(flanger_def) => {
-                    let flanger = FlangerBuilder::default()
+                    let mut flanger_builder = FlangerBuilder::default();
+                    flanger_builder
                         .window_size(flanger_def.window_size)
                         .mix(flanger_def.mix)
+                        .rate_hz(flanger_def.rate)
+                        .depth(flanger_def.depth)
+                        .feedback(flanger_def.feedback)
+                        .render_config(self.render_config);
+                    if let Some(voices) = flanger_def.chorus_voices {
+                        flanger_builder.mode(FlangerMode::Chorus).voices(voices);
+                    }
+                    let flanger = flanger_builder
                         .build()
                         .map_err(|e| format!("Failed to build Flanger: {:?}", e))?;
                     flangers.push(flanger);
@@ -1075,10 +1612,47 @@ impl Parser {
                         .frequency(lfo_def.freq)
                         .amplitude(lfo_def.amp)
                         .waveforms(waveforms)
+                        .render_config(self.render_config)
                         .build()
                         .map_err(|e| format!("Failed to build LFO: {:?}", e))?;
                     lfos.push(lfo);
                 }
+                EffectDef::Filter(filter_def) => {
+                    let filter = FilterBuilder::default()
+                        .mode(filter_def.mode)
+                        .cutoff_hz(filter_def.cutoff)
+                        .q(filter_def.q)
+                        .render_config(self.render_config)
+                        .build()
+                        .map_err(|e| format!("Failed to build Filter: {:?}", e))?;
+                    filters.push(filter);
+                }
+                EffectDef::Reverb(reverb_def) => {
+                    let reverb = ReverbBuilder::default()
+                        .room_size(reverb_def.room_size)
+                        .damping(reverb_def.damping)
+                        .predelay_ms(reverb_def.predelay_ms)
+                        .width(reverb_def.width)
+                        .mix(reverb_def.mix)
+                        .render_config(self.render_config)
+                        .build()
+                        .map_err(|e| format!("Failed to build Reverb: {:?}", e))?;
+                    reverbs.push(reverb);
+                }
+                EffectDef::PitchEnvelope(modulation_def) => {
+                    if pitch_envelope.is_some() {
+                        return Err(String::from("Only one pitch_env is allowed per block"));
+                    }
+                    pitch_envelope = Some(Self::build_modulation_envelope(modulation_def)?);
+                }
+                EffectDef::CutoffEnvelope(modulation_def) => {
+                    if cutoff_envelope.is_some() {
+                        return Err(String::from("Only one cutoff_env is allowed per block"));
+                    }
+                    cutoff_envelope = Some(Self::build_modulation_envelope(modulation_def)?);
+                }
+                // set on the block's osc notes rather than the track
+                EffectDef::WavetablePositionEnvelope(_) => {}
             }
         }
 
@@ -1087,27 +1661,60 @@ impl Parser {
             .delays(delays)
             .flangers(flangers)
             .lfos(lfos)
+            .filters(filters)
+            .reverbs(reverbs)
+            .pitch_envelope(pitch_envelope)
+            .cutoff_envelope(cutoff_envelope)
             .build()
             .map_err(|e| format!("Failed to build TrackEffects: {:?}", e))
     }
 
+    fn register_wavetable_def(wavetable_def: &WavetableDef) -> Result<(), String> {
+        let wavetable = match &wavetable_def.source {
+            WavetableSource::File { file_path, num_frames } =>
+                Wavetable::from_wav_file(file_path, *num_frames)?,
+            WavetableSource::Harmonics(frames) => Wavetable::from_harmonics(frames)?,
+        };
+        register_wavetable(&wavetable_def.name, wavetable);
+        Ok(())
+    }
+
+    fn build_envelope(env_def: &EnvelopeDef) -> Result<Envelope, String> {
+        EnvelopeBuilder::default()
+            .attack(EnvelopePair(env_def.attack.0, env_def.attack.1))
+            .decay(EnvelopePair(env_def.decay.0, env_def.decay.1))
+            .sustain(EnvelopePair(env_def.sustain.0, env_def.sustain.1))
+            .release(EnvelopePair(env_def.release.0, env_def.release.1))
+            .build()
+            .map_err(|e| format!("Failed to build Envelope: {:?}", e))
+    }
+
+    fn build_modulation_envelope(modulation_def: &ModulationEnvelopeDef)
+                                 -> Result<ModulationEnvelope, String> {
+        ModulationEnvelopeBuilder::default()
+            .envelope(Self::build_envelope(&modulation_def.envelope_def)?)
+            .depth(modulation_def.depth)
+            .build()
+            .map_err(|e| format!("Failed to build ModulationEnvelope: {:?}", e))
+    }
+
     fn build_playback_note(&self, note_decl: &NoteDeclaration, sequence_def: &SequenceDef) -> Result<PlaybackNote, String> {
         let step_duration_ms = (60000.0 / sequence_def.tempo as f32) * sequence_def.dur.to_factor();
         let start_time_ms = note_decl.get_step_index() as f32 * step_duration_ms;
         let end_time_ms = start_time_ms + step_duration_ms;
 
         match note_decl {
-            NoteDeclaration::Oscillator { waveforms, note_freq, volume, .. } => {
-                let waveforms: Vec<Waveform> = waveforms.iter()
-                    .map(|w| w.to_waveform())
-                    .collect();
+            NoteDeclaration::Oscillator { layers, note_freq, volume, .. } => {
+                let layers = layers.iter()
+                    .map(|layer_def| layer_def.to_layer())
+                    .collect::<Result<Vec<OscillatorLayer>, String>>()?;
 
                 let note = NoteBuilder::default()
                     .frequency(*note_freq)
                     .volume(*volume)
                     .start_time_ms(start_time_ms)
                     .end_time_ms(end_time_ms)
-                    .waveforms(waveforms)
+                    .layers(layers)
                     .build()
                     .map_err(|e| format!("Failed to build Note: {:?}", e))?;
 
@@ -1122,6 +1729,7 @@ impl Parser {
             NoteDeclaration::Sample { file_path, volume, .. } => {
                 let sampled_note = SampledNoteBuilder::default()
                     .file_path(file_path.clone())
+                    .sample_rate(self.render_config.sample_rate)
                     .volume(*volume)
                     .start_time_ms(start_time_ms)
                     .end_time_ms(end_time_ms)
@@ -1136,6 +1744,88 @@ impl Parser {
                     .build()
                     .map_err(|e| format!("Failed to build PlaybackNote: {:?}", e))
             }
+            NoteDeclaration::FM { algorithm, operators, note_freq, volume, .. } => {
+                let mut fm_operators = Vec::new();
+                for operator in operators {
+                    fm_operators.push(FMOperatorBuilder::default()
+                        .ratio(operator.ratio)
+                        .level(operator.level)
+                        .envelope(Self::build_sine_envelope(operator)?)
+                        .build()
+                        .map_err(|e| format!("Failed to build FMOperator: {:?}", e))?);
+                }
+
+                let fm_note = FMNoteBuilder::default()
+                    .frequency(*note_freq)
+                    .volume(*volume)
+                    .start_time_ms(start_time_ms)
+                    .end_time_ms(end_time_ms)
+                    .operators(fm_operators)
+                    .algorithm(*algorithm)
+                    .build()
+                    .map_err(|e| format!("Failed to build FMNote: {:?}", e))?;
+
+                PlaybackNoteBuilder::default()
+                    .note_type(NoteType::FM)
+                    .fm_note(fm_note)
+                    .playback_start_time_ms(start_time_ms)
+                    .playback_end_time_ms(end_time_ms)
+                    .build()
+                    .map_err(|e| format!("Failed to build PlaybackNote: {:?}", e))
+            }
+            NoteDeclaration::Additive { partials, note_freq, volume, .. } => {
+                let mut additive_partials = Vec::new();
+                for partial in partials {
+                    additive_partials.push(PartialBuilder::default()
+                        .ratio(partial.ratio)
+                        .amplitude(partial.level)
+                        .envelope(Self::build_sine_envelope(partial)?)
+                        .build()
+                        .map_err(|e| format!("Failed to build Partial: {:?}", e))?);
+                }
+
+                let additive_note = AdditiveNoteBuilder::default()
+                    .frequency(*note_freq)
+                    .volume(*volume)
+                    .start_time_ms(start_time_ms)
+                    .end_time_ms(end_time_ms)
+                    .partials(additive_partials)
+                    .build()
+                    .map_err(|e| format!("Failed to build AdditiveNote: {:?}", e))?;
+
+                PlaybackNoteBuilder::default()
+                    .note_type(NoteType::Additive)
+                    .additive_note(additive_note)
+                    .playback_start_time_ms(start_time_ms)
+                    .playback_end_time_ms(end_time_ms)
+                    .build()
+                    .map_err(|e| format!("Failed to build PlaybackNote: {:?}", e))
+            }
+        }
+    }
+
+    // The sine holds its sustain level until the note's release, where it fades out like the
+    // notes of the block envelopes
+    fn build_unison(unison_def: &UnisonDef) -> Result<Unison, String> {
+        UnisonBuilder::default()
+            .num_voices(unison_def.num_voices)
+            .detune_cents(unison_def.detune_cents)
+            .stereo_spread(unison_def.stereo_spread)
+            .random_phase(unison_def.random_phase)
+            .build()
+            .map_err(|e| format!("Failed to build Unison: {:?}", e))
+    }
+
+    fn build_sine_envelope(sine_def: &SineDef) -> Result<Option<Envelope>, String> {
+        match sine_def.envelope {
+            Some((attack, decay, sustain)) => EnvelopeBuilder::default()
+                .attack(EnvelopePair(attack, 1.0))
+                .decay(EnvelopePair(decay, sustain))
+                .sustain(EnvelopePair(decay.max(SINE_RELEASE_POSITION), sustain))
+                .build()
+                .map(Some)
+                .map_err(|e| format!("Failed to build Envelope: {:?}", e)),
+            None => Ok(None),
         }
     }
 
@@ -1149,7 +1839,7 @@ impl Parser {
 
     fn parse_identifier(&mut self) -> Result<String, String> {
         let token = self.advance();
-        if token.chars().next().map_or(false, |c| c.is_alphabetic()) &&
+        if token.chars().next().is_some_and(|c| c.is_alphabetic()) &&
            token.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
             Ok(token)
         } else {
@@ -1203,25 +1893,40 @@ impl NoteDeclaration {
         match self {
             NoteDeclaration::Oscillator { step_index, .. } => *step_index,
             NoteDeclaration::Sample { step_index, .. } => *step_index,
+            NoteDeclaration::FM { step_index, .. } => *step_index,
+            NoteDeclaration::Additive { step_index, .. } => *step_index,
         }
     }
 }
 
+#[allow(dead_code)]
 pub fn parse_dsl(input: &str) -> Result<TrackGrid<FixedTimeNoteSequence>, String> {
+    parse_dsl_with_config(input, RenderConfig::default())
+}
+
+// Parses the script into a TrackGrid, and the effects in it, that render at render_config's rate
+pub fn parse_dsl_with_config(input: &str, render_config: RenderConfig)
+    -> Result<TrackGrid<FixedTimeNoteSequence>, String> {
     let mut parser = Parser::new(input);
+    parser.render_config = render_config;
     parser.parse()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::audio_gen::audio_gen::write_audio_file;
+    use crate::audio_gen::sample_format::SampleFormat;
+    use crate::audio_gen::wavetable::{get_wavetable, register_wavetable, Wavetable};
+    use crate::common::render_config::RenderConfigBuilder;
+    use crate::common::stereo::StereoFrame;
 
     #[test]
     fn test_parse_simple_script() {
         let input = r#"
             FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
             a 0.1,0.8 d 0.3,0.6 s 0.8,0.4 r 1.0,0.0
-            delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2
+            delay time_ms 150.0 feedback 0.7 mix 0.5
             osc:sine:440.0:0.5:0
             osc:square:880.0:0.3:4
             samp:/Users/markweiss/RustroverProjects/osc_bak/src/dsl/test_data/test_sample.wav:0.005:2
@@ -1290,7 +1995,7 @@ mod tests {
         let input = r#"
             FixedTimeNoteSequence dur Half tempo 100 num_steps 32
             a 0.1,0.9 d 0.4,0.6 s 0.8,0.3 r 1.0,0.0
-            delay mix 0.8 decay 0.6 interval_ms 80.0 duration_ms 40.0 num_repeats 5 num_predelay_samples 15 num_concurrent_delays 3
+            delay time_ms 120.0 feedback 0.6 mix 0.8 cutoff 2000.0
             flanger window_size 12 mix 0.4
             lfo freq 2.5 amp 0.3 waveforms sine,triangle
             osc:sine,square:440.0:0.7:0
@@ -1307,11 +2012,413 @@ mod tests {
         assert_eq!(track.effects.lfos.len(), 1);
     }
 
+    #[test]
+    fn test_parse_pulse_waveform() {
+        assert_eq!(WaveformType::from_str("pulse"), Ok(WaveformType::Pulse(0.5)));
+        assert_eq!(WaveformType::from_str("pls=0.25"), Ok(WaveformType::Pulse(0.25)));
+        assert!(WaveformType::from_str("pulse=1.5").is_err());
+        assert!(WaveformType::from_str("pulse=wide").is_err());
+        assert_eq!(WaveformType::Pulse(0.25).to_waveform(),
+                   Waveform::Pulse(BandLimit::default(), 0.25));
+
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            osc:pulse=0.125,saw:440.0:0.5:0
+        "#;
+        assert!(parse_dsl(input).is_ok());
+    }
+
+    #[test]
+    fn test_parse_wavetable_waveform() {
+        let wavetable = Wavetable::from_harmonics(&[vec![1.0], vec![1.0, 0.5, 0.25]]).unwrap();
+        let id = register_wavetable("DslTestTable", wavetable);
+        assert_eq!(WaveformType::from_str("wt=DslTestTable"),
+                   Ok(WaveformType::Wavetable(id, 0.0)));
+        assert_eq!(WaveformType::from_str("wavetable=DslTestTable@0.5"),
+                   Ok(WaveformType::Wavetable(id, 0.5)));
+        assert!(WaveformType::from_str("wt=dsltesttable").is_err());
+        assert!(WaveformType::from_str("wt=DslTestTable@end").is_err());
+
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            osc:wt=DslTestTable@0.25,sine:440.0:0.5:0
+        "#;
+        assert!(parse_dsl(input).is_ok());
+    }
+
+    #[test]
+    fn test_parse_wavetable_defs() {
+        // two cycles of a square wave, the second at half the level of the first
+        let frames: Vec<StereoFrame> = (0..200)
+            .map(|i| StereoFrame::from_mono(if i % 100 < 50 { 1.0 } else { -1.0 } /
+                (i / 100 + 1) as f32))
+            .collect();
+        let file_path = std::env::temp_dir().join("test_parse_wavetable_defs.wav");
+        let file_path = file_path.to_str().unwrap();
+        let render_config = RenderConfigBuilder::default()
+            .sample_format(SampleFormat::Float32)
+            .build().unwrap();
+        write_audio_file(file_path, frames, &render_config).unwrap();
+
+        let input = format!(r#"
+            wavetable DslFileTable file {} frames 2
+            wavetable DslHarmonicsTable harmonics 1.0 harmonics 1.0,0.5,0.25
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            wt_env a 0.25,0.25 d 0.5,0.5 s 0.75,0.75 r 1.0,1.0
+            osc:wt=DslHarmonicsTable@1.0,sine,wt=DslFileTable:440.0:0.5:0
+        "#, file_path);
+        let mut parser = Parser::new(&input);
+        let script = parser.parse_script().unwrap();
+        std::fs::remove_file(file_path).unwrap();
+        assert_eq!(script.wavetable_defs[0].source, WavetableSource::File {
+            file_path: file_path.to_string(),
+            num_frames: 2,
+        });
+        assert_eq!(script.wavetable_defs[1], WavetableDef {
+            name: String::from("DslHarmonicsTable"),
+            source: WavetableSource::Harmonics(vec![vec![1.0], vec![1.0, 0.5, 0.25]]),
+        });
+
+        // the notes hold the registered wavetables and morph from the first frame to the last
+        let file_id = wavetable_id("DslFileTable").unwrap();
+        let harmonics_id = wavetable_id("DslHarmonicsTable").unwrap();
+        assert_eq!(get_wavetable(file_id).frames.len(), 2);
+        let mut track_grid = parser.build_track_grid(script).unwrap();
+        let playback_note = &track_grid.next().unwrap()[0];
+        assert_eq!(playback_note.wavetables, vec![
+            Some((harmonics_id, get_wavetable(harmonics_id))),
+            None,
+            Some((file_id, get_wavetable(file_id))),
+        ]);
+        let envelope = playback_note.wavetable_position_envelope.unwrap();
+        assert_eq!(envelope.volume_factor(0.1), 0.1);
+        assert_eq!(envelope.volume_factor(0.9), 0.9);
+
+        let unknown_source = r#"
+            wavetable DslUnknownTable frames 2
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            osc:sine:440.0:0.5:0
+        "#;
+        assert!(parse_dsl(unknown_source).is_err());
+        let missing_file = r#"
+            wavetable DslMissingTable file /no/such/wavetable.wav frames 2
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            osc:wt=DslMissingTable:440.0:0.5:0
+        "#;
+        assert!(parse_dsl(missing_file).is_err());
+    }
+
+    #[test]
+    fn test_parse_fm_note() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            fm:pairs:1.0/1.0,14.0/0.8/0.0/0.2/0.0,1.0/0.6,1.0/1.5:4,C:0.5:2
+        "#;
+        let mut parser = Parser::new(input);
+        let script = parser.parse_script().unwrap();
+        match &script.outer_blocks[0].note_declarations[0] {
+            NoteDeclaration::FM { algorithm, operators, volume, step_index, .. } => {
+                assert_eq!(*algorithm, FMAlgorithm::Pairs);
+                assert_eq!(operators.len(), 4);
+                assert_eq!(operators[1], SineDef {
+                    ratio: 14.0,
+                    level: 0.8,
+                    envelope: Some((0.0, 0.2, 0.0)),
+                });
+                assert_eq!(*volume, 0.5);
+                assert_eq!(*step_index, 2);
+            }
+            note_declaration => panic!("expected an FM note, got {:?}", note_declaration),
+        }
+
+        let outer_block = &script.outer_blocks[0];
+        let playback_note = parser.build_playback_note(&outer_block.note_declarations[0],
+                                                       &outer_block.sequence_def).unwrap();
+        assert_eq!(playback_note.note_type, NoteType::FM);
+        assert_eq!(playback_note.fm_note.operators.len(), 4);
+        assert!(playback_note.fm_note.operators[1].envelope.is_some());
+
+        assert!(Parser::parse_sine_def("1.0/1.0/0.5").is_err());
+        assert!(Parser::parse_sine_def("1.0/loud").is_err());
+    }
+
+    #[test]
+    fn test_parse_additive_note() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            add:1.0/0.5,2.0/0.25/0.1/0.5/0.3,2.76/0.1:220.0:0.8:1
+        "#;
+        let mut parser = Parser::new(input);
+        let script = parser.parse_script().unwrap();
+        let outer_block = &script.outer_blocks[0];
+        match &outer_block.note_declarations[0] {
+            NoteDeclaration::Additive { partials, note_freq, step_index, .. } => {
+                assert_eq!(partials.len(), 3);
+                assert_eq!(partials[1], SineDef {
+                    ratio: 2.0,
+                    level: 0.25,
+                    envelope: Some((0.1, 0.5, 0.3)),
+                });
+                assert_eq!(*note_freq, 220.0);
+                assert_eq!(*step_index, 1);
+            }
+            note_declaration => panic!("expected an additive note, got {:?}", note_declaration),
+        }
+
+        let playback_note = parser.build_playback_note(&outer_block.note_declarations[0],
+                                                       &outer_block.sequence_def).unwrap();
+        assert_eq!(playback_note.note_type, NoteType::Additive);
+        let partial = playback_note.additive_note.partials[1];
+        assert_eq!(partial.amplitude, 0.25);
+        assert_eq!(partial.envelope.unwrap().decay, EnvelopePair(0.5, 0.3));
+    }
+
+    #[test]
+    fn test_parse_layers() {
+        assert_eq!(LayerDef::from_str("saw/lvl=0.5/oct=-1/semi=7/cent=-5.0/phase=0.25"),
+                   Ok(LayerDef {
+                       waveform: WaveformType::Sawtooth,
+                       level: 0.5,
+                       octaves: -1,
+                       semitones: 7,
+                       cents: -5.0,
+                       phase: 0.25,
+                   }));
+        assert_eq!(LayerDef::from_str("pulse=0.25").unwrap().level, 1.0);
+        assert!(LayerDef::from_str("saw/lvl=loud").is_err());
+        assert!(LayerDef::from_str("saw/oct=0.5").is_err());
+        assert!(LayerDef::from_str("saw/gain=0.5").is_err());
+
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            osc:sine/lvl=0.7,saw/lvl=0.3/oct=-1:4,C:0.5:0
+        "#;
+        let mut parser = Parser::new(input);
+        let script = parser.parse_script().unwrap();
+        let outer_block = &script.outer_blocks[0];
+        let playback_note = parser.build_playback_note(&outer_block.note_declarations[0],
+                                                       &outer_block.sequence_def).unwrap();
+        let layers = &playback_note.note.layers;
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].waveform, Waveform::Sine);
+        assert_eq!(layers[0].level, 0.7);
+        assert_eq!(layers[1].waveform, Waveform::Saw(BandLimit::default()));
+        assert_eq!(layers[1].frequency_ratio(), 0.5);
+    }
+
+    #[test]
+    fn test_parse_unison() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            unison 7 detune 12.0 phase 1.0
+            osc:saw:440.0:0.5:0
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            unison 3 detune 5.0 spread 0.8
+            osc:saw:440.0:0.5:0
+        "#;
+        let mut parser = Parser::new(input);
+        let script = parser.parse_script().unwrap();
+        assert_eq!(script.outer_blocks[0].unison_def, Some(UnisonDef {
+            num_voices: 7,
+            detune_cents: 12.0,
+            stereo_spread: 0.0,
+            random_phase: 1.0,
+        }));
+        let unison_def = script.outer_blocks[1].unison_def.clone().unwrap();
+        assert_eq!(unison_def.stereo_spread, 0.8);
+        let unison = Parser::build_unison(&unison_def).unwrap();
+        assert_eq!(unison.num_voices, 3);
+        assert_eq!(unison.detune_cents, 5.0);
+        assert!(parser.build_track_grid(script).is_ok());
+
+        let invalid = UnisonDef { num_voices: 0, ..unison_def };
+        assert!(Parser::build_unison(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            filter lp cutoff 800.0 q 0.7
+            filter notch cutoff 3000.0 q 2.0
+            osc:saw:440.0:0.5:0
+        "#;
+        let mut parser = Parser::new(input);
+        let script = parser.parse_script().unwrap();
+        match &script.outer_blocks[0].effect_defs[0] {
+            EffectDef::Filter(filter_def) => assert_eq!(*filter_def, FilterDef {
+                mode: FilterMode::LowPass,
+                cutoff: 800.0,
+                q: 0.7,
+            }),
+            _ => panic!("Expected a filter"),
+        }
+        let track_grid = parser.build_track_grid(script).unwrap();
+        let filters = &track_grid.tracks[0].effects.filters;
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[1].mode, FilterMode::Notch);
+        assert_eq!(filters[1].cutoff_hz, 3000.0);
+
+        let mut parser = Parser::new("filter lp cutoff 0.0 q 0.7");
+        let effect_def = parser.parse_effect_def().unwrap();
+        assert!(parser.build_track_effects(&[], &[effect_def]).is_err());
+        assert!(Parser::new("filter ladder cutoff 800.0 q 0.7").parse_effect_def().is_err());
+    }
+
+    #[test]
+    fn test_parse_flanger() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            flanger window_size 12 mix 0.4
+            flanger window_size 200 mix 0.5 depth 0.3 chorus 4 rate 0.8
+            flanger window_size 40 mix 0.5 rate 0.2 depth 1.0 feedback -0.7
+            osc:saw:440.0:0.5:0
+        "#;
+        let mut parser = Parser::new(input);
+        let script = parser.parse_script().unwrap();
+        match &script.outer_blocks[0].effect_defs[1] {
+            EffectDef::Flanger(flanger_def) => assert_eq!(*flanger_def, FlangerDef {
+                window_size: 200,
+                mix: 0.5,
+                rate: 0.8,
+                depth: 0.3,
+                feedback: 0.0,
+                chorus_voices: Some(4),
+            }),
+            _ => panic!("Expected a flanger"),
+        }
+        let track_grid = parser.build_track_grid(script).unwrap();
+        let flangers = &track_grid.tracks[0].effects.flangers;
+        assert_eq!(flangers[0].mode, FlangerMode::Flanger);
+        assert_eq!(flangers[0].depth, 0.0);
+        assert_eq!(flangers[1].mode, FlangerMode::Chorus);
+        assert_eq!(flangers[1].voices, 4);
+        assert_eq!(flangers[2].feedback, -0.7);
+
+        let mut parser = Parser::new("flanger window_size 12 mix 0.4 feedback 1.0");
+        let effect_def = parser.parse_effect_def().unwrap();
+        assert!(parser.build_track_effects(&[], &[effect_def]).is_err());
+    }
+
+    #[test]
+    fn test_parse_delay() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            delay time_ms 125.5 feedback 0.6 mix 0.4
+            delay time_ms 300.0 feedback 0.8 mix 0.3 cutoff 1800.0
+            osc:saw:440.0:0.5:0
+        "#;
+        let mut parser = Parser::new(input);
+        let script = parser.parse_script().unwrap();
+        match &script.outer_blocks[0].effect_defs[1] {
+            EffectDef::Delay(delay_def) => assert_eq!(*delay_def, DelayDef {
+                time_ms: 300.0,
+                feedback: 0.8,
+                mix: 0.3,
+                cutoff: Some(1800.0),
+            }),
+            _ => panic!("Expected a delay"),
+        }
+        let track_grid = parser.build_track_grid(script).unwrap();
+        let delays = &track_grid.tracks[0].effects.delays;
+        assert_eq!(delays[0].delay_time_ms, 125.5);
+        assert!(delays[0].feedback_filter.is_none());
+        assert_eq!(delays[1].feedback_filter.as_ref().unwrap().cutoff_hz, 1800.0);
+
+        let mut parser = Parser::new("delay time_ms 100.0 feedback 1.0 mix 0.4");
+        let effect_def = parser.parse_effect_def().unwrap();
+        assert!(parser.build_track_effects(&[], &[effect_def]).is_err());
+    }
+
+    #[test]
+    fn test_parse_reverb() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            reverb room_size 0.8 damping 0.3 predelay_ms 20.0 width 0.9 mix 0.25
+            osc:saw:440.0:0.5:0
+        "#;
+        let mut parser = Parser::new(input);
+        let script = parser.parse_script().unwrap();
+        match &script.outer_blocks[0].effect_defs[0] {
+            EffectDef::Reverb(reverb_def) => assert_eq!(*reverb_def, ReverbDef {
+                room_size: 0.8,
+                damping: 0.3,
+                predelay_ms: 20.0,
+                width: 0.9,
+                mix: 0.25,
+            }),
+            _ => panic!("Expected a reverb"),
+        }
+        let track_grid = parser.build_track_grid(script).unwrap();
+        let reverbs = &track_grid.tracks[0].effects.reverbs;
+        assert_eq!(reverbs.len(), 1);
+        assert_eq!(reverbs[0].room_size, 0.8);
+        assert_eq!(reverbs[0].mix, 0.25);
+
+        let mut parser = Parser::new(
+            "reverb room_size 1.2 damping 0.3 predelay_ms 0.0 width 1.0 mix 0.3");
+        let effect_def = parser.parse_effect_def().unwrap();
+        assert!(parser.build_track_effects(&[], &[effect_def]).is_err());
+        assert!(Parser::new("reverb room_size 0.5 mix 0.3").parse_effect_def().is_err());
+    }
+
+    #[test]
+    fn test_parse_modulation_envelopes() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            filter lp cutoff 400.0 q 2.0
+            pitch_env depth 24.0 a 0.0,1.0 d 0.1,0.0 s 0.9,0.0 r 1.0,0.0
+            cutoff_env depth 3.0 a 0.05,1.0 d 0.3,0.2 s 0.9,0.2 r 1.0,0.0
+            osc:sine:1,C:0.9:0
+        "#;
+        let track_grid = parse_dsl(input).unwrap();
+        let effects = &track_grid.tracks[0].effects;
+        let pitch_envelope = effects.pitch_envelope.unwrap();
+        assert_eq!(pitch_envelope.depth, 24.0);
+        assert_eq!(pitch_envelope.modulation(0.0), 24.0);
+        assert_eq!(pitch_envelope.modulation(0.5), 0.0);
+        let cutoff_envelope = effects.cutoff_envelope.unwrap();
+        assert_eq!(cutoff_envelope.envelope.decay, EnvelopePair(0.3, 0.2));
+        assert!((cutoff_envelope.modulation(0.5) - 0.6).abs() < 1e-6);
+
+        let twice = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            pitch_env depth 24.0 a 0.0,1.0 d 0.1,0.0 s 0.9,0.0 r 1.0,0.0
+            pitch_env depth 12.0 a 0.0,1.0 d 0.1,0.0 s 0.9,0.0 r 1.0,0.0
+            osc:sine:1,C:0.9:0
+        "#;
+        assert!(parse_dsl(twice).is_err());
+    }
+
+    #[test]
+    fn test_parse_with_config() {
+        let input = r#"
+            FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
+            a 0.1,0.8 d 0.3,0.6 s 0.8,0.4 r 1.0,0.0
+            delay time_ms 150.0 feedback 0.7 mix 0.5
+            lfo freq 2.5 amp 0.3 waveforms sine
+            filter hp cutoff 200.0 q 0.7
+            reverb room_size 0.5 damping 0.5 predelay_ms 10.0 width 1.0 mix 0.3
+            flanger window_size 12 mix 0.4 rate 0.5 depth 0.5
+            osc:sine:440.0:0.5:0
+        "#;
+        let render_config = RenderConfigBuilder::default().sample_rate(96000.0).build().unwrap();
+
+        let track_grid = parse_dsl_with_config(input, render_config).unwrap();
+        assert_eq!(track_grid.render_config, render_config);
+        let track = &track_grid.tracks[0];
+        assert_eq!(track.effects.delays[0].render_config, render_config);
+        assert_eq!(track.effects.lfos[0].render_config, render_config);
+        assert_eq!(track.effects.filters[0].render_config, render_config);
+        assert_eq!(track.effects.reverbs[0].render_config, render_config);
+        assert_eq!(track.effects.flangers[0].render_config, render_config);
+    }
+
     #[test]
     fn test_parse_macro_definitions() {
         let input = r#"
             let env1 = a 0.2,0.8 d 0.3,0.6 s 0.8,0.5 r 1.0,0.0
-            let delay1 = delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2
+            let delay1 = delay time_ms 150.0 feedback 0.7 mix 0.5
             let flanger1 = flanger window_size 8 mix 0.3
             FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
             osc:sine:440.0:0.5:0
@@ -1327,7 +2434,7 @@ mod tests {
         assert_eq!(script.macro_defs.get("env1").unwrap(), "a 0.2,0.8 d 0.3,0.6 s 0.8,0.5 r 1.0,0.0");
         
         // Check that delay1 contains the delay definition
-        assert_eq!(script.macro_defs.get("delay1").unwrap(), "delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2");
+        assert_eq!(script.macro_defs.get("delay1").unwrap(), "delay time_ms 150.0 feedback 0.7 mix 0.5");
         
         // Check that flanger1 contains the flanger definition
         assert_eq!(script.macro_defs.get("flanger1").unwrap(), "flanger window_size 8 mix 0.3");
@@ -1340,7 +2447,7 @@ mod tests {
     fn test_parse_macro_definitions_with_whitespace() {
         let input = r#"
             let env1 =   a 0.2,0.8 d 0.3,0.6 s 0.8,0.5 r 1.0,0.0   
-            let delay1 = delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2
+            let delay1 = delay time_ms 150.0 feedback 0.7 mix 0.5
             FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
             osc:sine:440.0:0.5:0
         "#;
@@ -1350,7 +2457,7 @@ mod tests {
         
         // Verify that whitespace is trimmed from expressions
         assert_eq!(script.macro_defs.get("env1").unwrap(), "a 0.2,0.8 d 0.3,0.6 s 0.8,0.5 r 1.0,0.0");
-        assert_eq!(script.macro_defs.get("delay1").unwrap(), "delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2");
+        assert_eq!(script.macro_defs.get("delay1").unwrap(), "delay time_ms 150.0 feedback 0.7 mix 0.5");
     }
 
     #[test]
@@ -1358,7 +2465,7 @@ mod tests {
         let input = r#"
             let env1 = a 0.2,0.8 d 0.3,0.6 s 0.8,0.5 r 1.0,0.0
             let env2 = a 0.1,0.9 d 0.2,0.7 s 0.9,0.4 r 0.8,0.1
-            let delay1 = delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2
+            let delay1 = delay time_ms 150.0 feedback 0.7 mix 0.5
             let lfo1 = lfo freq 2.0 amp 0.3 waveforms sine,triangle
             let note1 = osc:sine:440.0:0.5:0
             FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
@@ -1374,7 +2481,7 @@ mod tests {
         // Check each macro definition
         assert_eq!(script.macro_defs.get("env1").unwrap(), "a 0.2,0.8 d 0.3,0.6 s 0.8,0.5 r 1.0,0.0");
         assert_eq!(script.macro_defs.get("env2").unwrap(), "a 0.1,0.9 d 0.2,0.7 s 0.9,0.4 r 0.8,0.1");
-        assert_eq!(script.macro_defs.get("delay1").unwrap(), "delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2");
+        assert_eq!(script.macro_defs.get("delay1").unwrap(), "delay time_ms 150.0 feedback 0.7 mix 0.5");
         assert_eq!(script.macro_defs.get("lfo1").unwrap(), "lfo freq 2.0 amp 0.3 waveforms sine,triangle");
         assert_eq!(script.macro_defs.get("note1").unwrap(), "osc:sine:440.0:0.5:0");
         
@@ -1386,7 +2493,7 @@ mod tests {
     fn test_parse_identifier_validation() {
         let input = r#"
             let valid-name = a 0.2,0.8 d 0.3,0.6 s 0.8,0.5 r 1.0,0.0
-            let valid_name = delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2
+            let valid_name = delay time_ms 150.0 feedback 0.7 mix 0.5
             let validName123 = flanger window_size 8 mix 0.3
             FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
             osc:sine:440.0:0.5:0
@@ -1406,7 +2513,7 @@ mod tests {
     fn test_macro_expansion_basic() {
         let input = r#"
             let env1 = a 0.2,0.8 d 0.3,0.6 s 0.8,0.5 r 1.0,0.0
-            let delay1 = delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2
+            let delay1 = delay time_ms 150.0 feedback 0.7 mix 0.5
             FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
             $env1
             $delay1
@@ -1517,7 +2624,7 @@ mod tests {
             let sustain_params = 0.8,0.5
             let release_params = 1.0,0.0
             let env1 = a $attack_params d $decay_params s $sustain_params r $release_params
-            let delay1 = delay mix 0.5 decay 0.7 interval_ms 100.0 duration_ms 50.0 num_repeats 3 num_predelay_samples 10 num_concurrent_delays 2
+            let delay1 = delay time_ms 150.0 feedback 0.7 mix 0.5
             FixedTimeNoteSequence dur Quarter tempo 120 num_steps 16
             $env1
             $delay1
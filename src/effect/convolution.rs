@@ -0,0 +1,409 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+
+use derive_builder::Builder;
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+use crate::common::render_config::RenderConfig;
+use crate::common::stereo::StereoFrame;
+use crate::note::sampled_note::{load_sample_bufs, ChannelMode};
+
+static DEFAULT_MIX: f32 = 0.3;
+static DEFAULT_PREDELAY_MS: f32 = 0.0;
+// Samples of the impulse response convolved directly, and the length of the partitions the rest
+// is split into. Larger blocks make long impulse responses cheaper and the direct part dearer
+static DEFAULT_BLOCK_SIZE: usize = 256;
+
+// An impulse response at the render rate. right is empty for a mono impulse response, which is
+// then used for both channels
+#[allow(dead_code)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ImpulseResponse {
+    pub(crate) left: Vec<f32>,
+    pub(crate) right: Vec<f32>,
+}
+
+// Loads the impulse response of a space or cabinet from a WAV file, resampled to the render rate.
+// Stereo files keep their left and right channels
+#[allow(dead_code)]
+pub(crate) fn load_impulse_response(file_path: &str, render_config: &RenderConfig)
+    -> Result<ImpulseResponse, String> {
+    let (left, right) = load_sample_bufs(file_path, ChannelMode::Keep,
+                                         render_config.sample_rate)?;
+    if left.is_empty() {
+        return Err(format!("Impulse response {} has no samples", file_path));
+    }
+    Ok(ImpulseResponse { left, right })
+}
+
+// The impulse response of one channel, ready to convolve with. The first block of it is kept as
+// taps, convolved directly so the effect adds no latency, and the rest is split into blocks whose
+// spectra are multiplied with the input's. Shared by every copy of the effect
+struct Kernel {
+    block_size: usize,
+    head: Vec<f32>,
+    // spectra of the blocks after the head, each zero padded to twice the block size
+    tail_spectra: Vec<Vec<Complex<f32>>>,
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+}
+
+impl Kernel {
+    fn new(impulse_response: &[f32], block_size: usize, planner: &mut RealFftPlanner<f32>)
+        -> Self {
+        let forward = planner.plan_fft_forward(2 * block_size);
+        let inverse = planner.plan_fft_inverse(2 * block_size);
+        let head = impulse_response[..block_size.min(impulse_response.len())].to_vec();
+        let tail_spectra = impulse_response.chunks(block_size)
+            .skip(1)
+            .map(|partition| {
+                let mut padded = forward.make_input_vec();
+                padded[..partition.len()].copy_from_slice(partition);
+                let mut spectrum = forward.make_output_vec();
+                forward.process(&mut padded, &mut spectrum).unwrap();
+                spectrum
+            })
+            .collect();
+        Kernel { block_size, head, tail_spectra, forward, inverse }
+    }
+}
+
+impl fmt::Debug for Kernel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Kernel")
+            .field("block_size", &self.block_size)
+            .field("head_len", &self.head.len())
+            .field("num_tail_partitions", &self.tail_spectra.len())
+            .finish()
+    }
+}
+
+// The FFT plans follow from the block size, so they are left out
+impl PartialEq for Kernel {
+    fn eq(&self, other: &Self) -> bool {
+        self.block_size == other.block_size && self.head == other.head &&
+            self.tail_spectra == other.tail_spectra
+    }
+}
+
+// Convolution of one channel of input, uniformly partitioned overlap-save for the tail
+#[derive(Clone, Debug, PartialEq)]
+struct ConvolutionChannel {
+    kernel: Arc<Kernel>,
+    // the last head length of input, most recent at history_index, for the direct convolution
+    history: Vec<f32>,
+    history_index: usize,
+    // the previous block of input and the one filling up, which are transformed together
+    input_blocks: Vec<f32>,
+    position: usize,
+    // spectra of past input blocks, the most recent first, one for each tail partition
+    input_spectra: VecDeque<Vec<Complex<f32>>>,
+    // the tail's output for the block filling up, worked out when the previous block filled
+    tail_output: Vec<f32>,
+}
+
+impl ConvolutionChannel {
+    fn new(kernel: Arc<Kernel>) -> Self {
+        let block_size = kernel.block_size;
+        ConvolutionChannel {
+            history: vec![0.0; kernel.head.len()],
+            history_index: 0,
+            input_blocks: vec![0.0; 2 * block_size],
+            position: 0,
+            input_spectra: VecDeque::with_capacity(kernel.tail_spectra.len()),
+            tail_output: vec![0.0; block_size],
+            kernel,
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let kernel = self.kernel.clone();
+        let block_size = kernel.block_size;
+
+        self.history[self.history_index] = sample;
+        let mut output = self.tail_output[self.position];
+        for (i, tap) in kernel.head.iter().enumerate() {
+            let history_len = self.history.len();
+            output += tap * self.history[(self.history_index + history_len - i) % history_len];
+        }
+        self.history_index = (self.history_index + 1) % self.history.len();
+
+        self.input_blocks[block_size + self.position] = sample;
+        self.position += 1;
+        if self.position == block_size {
+            self.position = 0;
+            if !kernel.tail_spectra.is_empty() {
+                self.convolve_tail(&kernel);
+            }
+            self.input_blocks.copy_within(block_size.., 0);
+        }
+        output
+    }
+
+    // Adds the block that just filled to the spectra of past input and works out what the tail
+    // contributes to the next block. Partition j of the tail meets the input from j blocks ago,
+    // so the next block's tail only needs input that has already arrived
+    fn convolve_tail(&mut self, kernel: &Kernel) {
+        let mut input = self.input_blocks.clone();
+        let mut input_spectrum = kernel.forward.make_output_vec();
+        kernel.forward.process(&mut input, &mut input_spectrum).unwrap();
+        if self.input_spectra.len() == kernel.tail_spectra.len() {
+            self.input_spectra.pop_back();
+        }
+        self.input_spectra.push_front(input_spectrum);
+
+        let mut output_spectrum = kernel.inverse.make_input_vec();
+        for (input_spectrum, tail_spectrum) in self.input_spectra.iter()
+                .zip(kernel.tail_spectra.iter()) {
+            for ((output, input), tail) in output_spectrum.iter_mut()
+                    .zip(input_spectrum.iter())
+                    .zip(tail_spectrum.iter()) {
+                *output += input * tail;
+            }
+        }
+        // the first and last bins of a real signal's spectrum are real
+        let num_bins = output_spectrum.len();
+        output_spectrum[0].im = 0.0;
+        output_spectrum[num_bins - 1].im = 0.0;
+
+        let mut output = kernel.inverse.make_output_vec();
+        kernel.inverse.process(&mut output_spectrum, &mut output).unwrap();
+        // overlap-save keeps the second half, the first is wrapped around by the circular
+        // convolution. realfft leaves the inverse unscaled
+        let scale = 1.0 / output.len() as f32;
+        let block_size = kernel.block_size;
+        for (tail_output, sample) in self.tail_output.iter_mut().zip(&output[block_size..]) {
+            *tail_output = sample * scale;
+        }
+    }
+}
+
+// Convolves the input with a recorded impulse response, which puts it in the space, or through
+// the cabinet, the impulse response was recorded from. Each channel is convolved with its own
+// channel of a stereo impulse response. Partitioned FFT convolution keeps long impulse responses
+// fast, with the first block convolved directly so there is no added latency
+#[allow(dead_code)]
+#[derive(Builder, Clone, Debug, PartialEq)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub(crate) struct Convolution {
+    pub(crate) impulse_response: ImpulseResponse,
+
+    // from 0.0, all dry, to 1.0, all convolved
+    #[builder(default = "DEFAULT_MIX")]
+    pub(crate) mix: f32,
+
+    // gap between the dry signal and the convolved one
+    #[builder(default = "DEFAULT_PREDELAY_MS")]
+    pub(crate) predelay_ms: f32,
+
+    // scales the impulse response to unit energy, so the convolved signal is about as loud as
+    // the dry one whatever level the impulse response was recorded at
+    #[builder(default = "true")]
+    pub(crate) normalize: bool,
+
+    #[builder(default = "DEFAULT_BLOCK_SIZE")]
+    pub(crate) block_size: usize,
+
+    #[builder(default = "RenderConfig::default()")]
+    pub(crate) render_config: RenderConfig,
+
+    #[builder(default = "self.predelay_line()", setter(skip))]
+    predelay_line: Vec<StereoFrame>,
+    #[builder(default = "0", setter(skip))]
+    predelay_index: usize,
+
+    #[builder(default = "self.channels()", setter(skip))]
+    channels: Vec<ConvolutionChannel>,
+}
+
+#[allow(dead_code)]
+impl ConvolutionBuilder {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(impulse_response) = &self.impulse_response {
+            if impulse_response.left.is_empty() {
+                return Err(String::from("Convolution: impulse_response must have samples"));
+            }
+        }
+        if let Some(mix) = self.mix {
+            if !(0.0..=1.0).contains(&mix) {
+                return Err(String::from("Convolution: mix must be between 0.0 and 1.0"));
+            }
+        }
+        if let Some(predelay_ms) = self.predelay_ms {
+            if predelay_ms < 0.0 {
+                return Err(String::from("Convolution: predelay_ms must not be negative"));
+            }
+        }
+        if self.block_size == Some(0) {
+            return Err(String::from("Convolution: block_size must be greater than 0"));
+        }
+        Ok(())
+    }
+
+    fn predelay_line(&self) -> Vec<StereoFrame> {
+        let render_config = self.render_config.unwrap_or_default();
+        let predelay_ms = self.predelay_ms.unwrap_or(DEFAULT_PREDELAY_MS);
+        vec![StereoFrame::default(); render_config.ms_to_samples(predelay_ms)]
+    }
+
+    // A left and a right channel, sharing a kernel if the impulse response is mono
+    fn channels(&self) -> Vec<ConvolutionChannel> {
+        let impulse_response = self.impulse_response.clone().unwrap_or_default();
+        let block_size = self.block_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+        let mut irs = vec![impulse_response.left];
+        if !impulse_response.right.is_empty() {
+            irs.push(impulse_response.right);
+        }
+        if self.normalize.unwrap_or(true) {
+            let energy = irs.iter()
+                .map(|ir| ir.iter().map(|sample| sample * sample).sum::<f32>())
+                .fold(0.0, f32::max);
+            if energy > 0.0 {
+                let gain = 1.0 / energy.sqrt();
+                for ir in irs.iter_mut() {
+                    ir.iter_mut().for_each(|sample| *sample *= gain);
+                }
+            }
+        }
+
+        let mut planner = RealFftPlanner::new();
+        let kernels: Vec<Arc<Kernel>> = irs.iter()
+            .map(|ir| Arc::new(Kernel::new(ir, block_size, &mut planner)))
+            .collect();
+        vec![ConvolutionChannel::new(kernels[0].clone()),
+             ConvolutionChannel::new(kernels[kernels.len() - 1].clone())]
+    }
+}
+
+#[allow(dead_code)]
+impl Convolution {
+    pub(crate) fn apply_effect(&mut self, frame: StereoFrame) -> StereoFrame {
+        let mut input = frame;
+        if !self.predelay_line.is_empty() {
+            input = std::mem::replace(&mut self.predelay_line[self.predelay_index], input);
+            self.predelay_index = (self.predelay_index + 1) % self.predelay_line.len();
+        }
+        let wet = StereoFrame::new(self.channels[0].process(input.left),
+                                   self.channels[1].process(input.right));
+        frame * (1.0 - self.mix) + wet * self.mix
+    }
+
+    // Samples the convolved signal runs on for after the input stops, the pre-delay and the rest
+    // of the impulse response
+    pub(crate) fn tail_samples(&self) -> usize {
+        if self.mix == 0.0 {
+            return 0;
+        }
+        let impulse_response_len =
+            self.impulse_response.left.len().max(self.impulse_response.right.len());
+        self.predelay_line.len() + impulse_response_len - 1
+    }
+}
+
+#[cfg(test)]
+mod test_convolution {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::audio_gen::audio_gen::write_audio_file;
+    use crate::common::render_config::RenderConfigBuilder;
+    use crate::common::stereo::StereoFrame;
+    use crate::effect::convolution::{load_impulse_response, ConvolutionBuilder, ImpulseResponse};
+
+    fn direct_convolution(input: &[f32], impulse_response: &[f32]) -> Vec<f32> {
+        (0..input.len())
+            .map(|n| (0..impulse_response.len().min(n + 1))
+                .map(|i| impulse_response[i] * input[n - i])
+                .sum())
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_direct_convolution() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let input: Vec<f32> = (0..3000).map(|_| rng.random_range(-1.0..1.0)).collect();
+        // several partitions, the last of them partly filled
+        let left: Vec<f32> = (0..1000)
+            .map(|i| rng.random_range(-1.0..1.0) * (-(i as f32) / 200.0).exp())
+            .collect();
+        let right: Vec<f32> = left.iter().rev().copied().collect();
+        let mut convolution = ConvolutionBuilder::default()
+            .impulse_response(ImpulseResponse { left: left.clone(), right: right.clone() })
+            .mix(1.0)
+            .normalize(false)
+            .block_size(64)
+            .build().unwrap();
+        let output: Vec<StereoFrame> = input.iter()
+            .map(|sample| convolution.apply_effect(StereoFrame::new(*sample, -sample)))
+            .collect();
+
+        let expected_left = direct_convolution(&input, &left);
+        let negated_input: Vec<f32> = input.iter().map(|sample| -sample).collect();
+        let expected_right = direct_convolution(&negated_input, &right);
+        for (i, frame) in output.iter().enumerate() {
+            assert!((frame.left - expected_left[i]).abs() < 1e-3, "{}", i);
+            assert!((frame.right - expected_right[i]).abs() < 1e-3, "{}", i);
+        }
+    }
+
+    #[test]
+    fn test_mix_predelay_and_normalize() {
+        let impulse_response = ImpulseResponse { left: vec![0.0, 2.0], right: Vec::new() };
+        let mut convolution = ConvolutionBuilder::default()
+            .impulse_response(impulse_response)
+            .mix(0.5)
+            .predelay_ms(1.0)
+            .build().unwrap();
+        let output: Vec<StereoFrame> = (0..100)
+            .map(|i| if i == 0 { 1.0 } else { 0.0 })
+            .map(|sample| convolution.apply_effect(StereoFrame::from_mono(sample)))
+            .collect();
+        // the dry click at half level, then the click delayed by the pre-delay and the impulse
+        // response, normalized to 1.0 and mixed in at half level, in both channels
+        assert_eq!(output[0], StereoFrame::new(0.5, 0.5));
+        let predelay_samples = 44;
+        for (i, frame) in output.iter().enumerate().skip(1) {
+            let expected = if i == predelay_samples + 1 { 0.5 } else { 0.0 };
+            assert!((frame.left - expected).abs() < 1e-6, "{}", i);
+            assert_eq!(frame.left, frame.right);
+        }
+        // the last of it is the click at the end of the impulse response
+        assert_eq!(convolution.tail_samples(), predelay_samples + 1);
+    }
+
+    #[test]
+    fn test_load_impulse_response() {
+        let file_path = std::env::temp_dir().join("osc_test_load_impulse_response.wav");
+        let frames: Vec<StereoFrame> = (0..480)
+            .map(|i| StereoFrame::new(0.5 * (-(i as f32) / 50.0).exp(), 0.25))
+            .collect();
+        let render_config = RenderConfigBuilder::default().sample_rate(48000.0).build().unwrap();
+        write_audio_file(file_path.to_str().unwrap(), frames, &render_config).unwrap();
+
+        let impulse_response = load_impulse_response(
+            file_path.to_str().unwrap(),
+            &RenderConfigBuilder::default().sample_rate(24000.0).build().unwrap()).unwrap();
+        assert_eq!(impulse_response.left.len(), 240);
+        assert_eq!(impulse_response.right.len(), 240);
+        assert!(load_impulse_response("no_such_impulse_response.wav", &render_config).is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let impulse_response = ImpulseResponse { left: vec![1.0], right: Vec::new() };
+        assert!(ConvolutionBuilder::default().build().is_err());
+        assert!(ConvolutionBuilder::default()
+            .impulse_response(ImpulseResponse::default())
+            .build().is_err());
+        assert!(ConvolutionBuilder::default()
+            .impulse_response(impulse_response.clone())
+            .mix(1.5)
+            .build().is_err());
+        assert!(ConvolutionBuilder::default()
+            .impulse_response(impulse_response)
+            .block_size(0)
+            .build().is_err());
+    }
+}
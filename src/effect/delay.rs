@@ -1,435 +1,123 @@
 use derive_builder::Builder;
-use std::collections::VecDeque;
-use std::sync::{Arc, RwLock};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::Mutex;
-use std::collections::HashMap;
-use std::sync::LazyLock;
-
-use crate::common::constants::SAMPLES_PER_MS;
-
-pub(crate) const PREDELAY_BUFFER_SIZE: usize = 20;
-
-static DEFAULT_DELAY_ID: usize = 0;
-static DEFAULT_DELAY_MIX: f32 = 1.0;
-static DEFAULT_DELAY_DECAY: f32 = 0.5;
-static DEFAULT_INTERVAL_DURATION_MS: f32 = 100.0;
-static DEFAULT_DELAY_DURATION_MS: f32 = 20.0;
-static DEFAULT_NUM_REPEATS: usize = 4;
-static ACTIVE_SAMPLE_MANAGERS: LazyLock<Mutex<HashMap<usize, Vec<SampleManager>>>> = 
-    LazyLock::new(|| Mutex::new(HashMap::new()));
-static SAMPLE_MANAGER_ID_COUNTER: LazyLock<Mutex<usize>> = LazyLock::new(|| Mutex::new(0));
-static MAX_NUM_ACTIVE_SAMPLE_MANAGERS: usize = 4;
-
-fn add_sample_manager(id: usize, sm_id: usize, sample_buffer_size: usize,
-        delay_windows: Vec<bool>, num_delay_windows: usize,
-        num_predelay_samples: usize, sample_buffer_read_index: usize,
-        sample_buffer_write_index: usize, init_buffer_index: usize, cur_delay_window:
-        usize, delay_windows_index: usize) {
-    
-    let mut map = ACTIVE_SAMPLE_MANAGERS.lock().unwrap();
-    let sample_managers = map.entry(id).or_insert_with(Vec::new);
-    sample_managers.push(
-        SampleManager {
-            id: sm_id,
-            sample_buffer_size,
-            sample_buffer: Arc::new(RwLock::new(VecDeque::with_capacity(sample_buffer_size))),
-            delay_windows: delay_windows.clone(),
-            num_delay_windows,
-            num_predelay_samples,
-            sample_buffer_read_index: AtomicUsize::new(sample_buffer_read_index),
-            sample_buffer_write_index: AtomicUsize::new(sample_buffer_write_index),
-            init_buffer_index: AtomicUsize::new(init_buffer_index),
-            cur_delay_window: AtomicUsize::new(cur_delay_window),
-            delay_windows_index: AtomicUsize::new(delay_windows_index),
-            is_full: AtomicBool::new(false),
-            is_active: AtomicBool::new(true),
-            is_pre_delay: AtomicBool::new(true),
-            is_in_delay_window: AtomicBool::new(true),
-            is_in_interval: AtomicBool::new(false),
-            has_spawned: AtomicBool::new(false),
-        }
-    );
-}
-
-fn next_sample_manager_id() -> usize {
-    let mut counter = SAMPLE_MANAGER_ID_COUNTER.lock().unwrap();
-    let id = *counter;
-    *counter += 1;
-    id
-}
-
-// delay_buf: [************************************************************************* ...]
-//             | duration_ms | interval_ms | duration_ms | interval_ms | duration_ms | ...
-// there are num_repeats number of duration_ms sections
-// duration_ms sections are width in samples of the delay window, i.e. length of each delay event
-// interval_ms sections are width in samples of the silence between delay events
-// as each sample comes in, insert_index updates the delay buffer rolling forward modulo
-// as each sample comes in, the current delay_index is checked to see if it is in a delay window
-// once the index gets to the end of the delay window, num_repeats increments. If the window
-//  has repeated num_repeats times, it's put back in the pool. If it has not, a new window is
-//  is pulled from the pool and it starts recording samples
-
-#[allow(dead_code)]
-#[derive(Debug)]
-pub(crate) struct SampleManager {
-
-    // the id of the sample manager
-    id: usize,
-    // the size of the delay sample buffer
-    sample_buffer_size: usize,
-    sample_buffer: Arc<RwLock<VecDeque<f32>>>,
-    // boundaries of sample indexes in delay windows or in intervals between delay windows
-    // true if in delay window, false if in interval
-    delay_windows: Vec<bool>,
-    num_delay_windows: usize,
-    num_predelay_samples: usize,
-    // the current index for reading the next delay sample from the buffer
-    sample_buffer_read_index: AtomicUsize,
-    // the current index for writing the next delay sample from the buffer
-    sample_buffer_write_index: AtomicUsize,
-    // leader buffer, we don't start reading and incrementing other buffers until we have written
-    // this many initializing samples
-    init_buffer_index: AtomicUsize,
-    // which delay window we are in, used to calculate decay factor
-    cur_delay_window: AtomicUsize,
-    // position in bit vector of entire length of all delay windows
-    delay_windows_index: AtomicUsize,
-    // false if the sample manager can still write more samples
-    is_full: AtomicBool,
-    // true if the sample manager hasn't finished going through its delay windows
-    is_active: AtomicBool,
-    // true if the sample manager is in the pre-delay buffer
-    is_pre_delay: AtomicBool,
-    // true if the sample manager is in a delay window
-    is_in_delay_window: AtomicBool,
-    // true if the sample manager is in an interval
-    is_in_interval: AtomicBool,
-    // true if the sample manager has spawned a new sample manager; this will happend once
-    // for each Manager when it gets full, up to the global limit of MAX_NUM_ACTIVE_SAMPLE_MANAGERS
-    has_spawned: AtomicBool,
-}
-
-#[allow(dead_code)]
-impl SampleManager {
- 
-    pub(crate) fn next_sample(&mut self, sample: f32) -> f32 {
-        let mut delay_sample = 0.0f32;
-        
-        // if we are in the pre-delay buffer, increment the write index, add the sample to the
-        // buffer and return 0
-        if self.is_pre_delay.load(Ordering::SeqCst) {
-            let mut buffer = self.sample_buffer.write().unwrap();
-            buffer.push_back(sample);
-            self.sample_buffer_write_index.fetch_add(1, Ordering::SeqCst);
-            if self.sample_buffer_write_index.load(Ordering::SeqCst) == PREDELAY_BUFFER_SIZE {
-                self.is_pre_delay.store(false, Ordering::SeqCst);
-            }
-            return 0f32;
-        }
-        
-        // if the buffer holding the samples being repeated in each delay window is not full,
-        // add the sample to the buffer
-        if !self.is_full.load(Ordering::SeqCst) {
-            let mut buffer = self.sample_buffer.write().unwrap();
-            buffer.push_back(sample);
-            self.sample_buffer_write_index.fetch_add(1, Ordering::SeqCst);
-            if self.sample_buffer_write_index.load(Ordering::SeqCst) ==
-                    self.sample_buffer_size - self.num_predelay_samples {
-                self.is_full.store(true, Ordering::SeqCst);
-            }
-        }
-        
-        // check if we are in a delay window or an interval by checking current delay window value
-        if self.delay_windows[self.delay_windows_index.load(Ordering::SeqCst)] {
-            {
-                let buffer = self.sample_buffer.read().unwrap();
-                delay_sample =
-                    *buffer.get(self.sample_buffer_read_index.load(Ordering::SeqCst) %
-                                self.sample_buffer_size).unwrap_or(&0.0);
-            }
-            // If this is the first sample in the delay window, increment the delay window index
-            if self.sample_buffer_read_index.load(Ordering::SeqCst) == 0 {
-                self.cur_delay_window.fetch_add(1, Ordering::SeqCst);
-            }
-            self.sample_buffer_read_index.fetch_add(1, Ordering::SeqCst);
-        }
-        
-        // check for reaching the end of the delay windows
-        if !self.is_pre_delay.load(Ordering::SeqCst) {
-            self.delay_windows_index.fetch_add(1, Ordering::SeqCst);
-        }
-        if self.delay_windows_index.load(Ordering::SeqCst) ==
-                self.delay_windows.len() - self.num_predelay_samples {
-            self.reset();
-        }
-
-        delay_sample 
-    }
-    
-    // DO NOT reset has_spawned, it is used to track if the sample manager has spawned a new
-    // sample manager; this will happen once for each Manager when it gets full, up to the
-    // global limit of MAX_NUM_ACTIVE_SAMPLE_MANAGERS
-    pub(crate) fn reset(&mut self) {
-        self.sample_buffer_read_index.store(0, Ordering::SeqCst);
-        self.sample_buffer_write_index.store(0, Ordering::SeqCst);
-        self.init_buffer_index.store(0, Ordering::SeqCst);
-        self.cur_delay_window.store(0, Ordering::SeqCst);
-        self.delay_windows_index.store(0, Ordering::SeqCst);
-        self.is_full.store(false, Ordering::SeqCst);
-        self.is_active.store(true, Ordering::SeqCst);
-        self.is_pre_delay.store(true, Ordering::SeqCst);
-        self.is_in_delay_window.store(true, Ordering::SeqCst);
-        self.is_in_interval.store(false, Ordering::SeqCst);
-
-        let mut buffer =
-            self.sample_buffer.write().unwrap();
-        buffer.clear();
-    }
-
-    pub(crate) fn dump_print(&self) {
-        if self.is_active.load(Ordering::SeqCst) {
-            println!("--------------------------------");
-            println!("id: {}", self.id);
-            println!("sample_buffer_size: {}", self.sample_buffer_size);
-            println!("sample_buffer_read_index: {}", self.sample_buffer_read_index.load(Ordering::SeqCst));
-            println!("sample_buffer_write_index: {}", self.sample_buffer_write_index.load(Ordering::SeqCst));
-            println!("num_delay_windows: {}", self.num_delay_windows);
-            println!("num_predelay_samples: {}", self.num_predelay_samples);
-            println!("init_buffer_index: {}", self.init_buffer_index.load(Ordering::SeqCst));
-            println!("cur_delay_window: {}", self.cur_delay_window.load(Ordering::SeqCst));
-            println!("delay_windows_index: {}", self.delay_windows_index.load(Ordering::SeqCst));
-            println!("is_full: {}", self.is_full.load(Ordering::SeqCst));
-            println!("is_active: {}", self.is_active.load(Ordering::SeqCst));
-            println!("is_pre_delay: {}", self.is_pre_delay.load(Ordering::SeqCst));
-            println!("is_in_delay_window: {}", self.is_in_delay_window.load(Ordering::SeqCst));
-            println!("is_in_interval: {}", self.is_in_interval.load(Ordering::SeqCst));
-            println!("has_spawned: {}", self.has_spawned.load(Ordering::SeqCst));
-            println!("--------------------------------");
-        }
-    }
-}
-
-impl Clone for SampleManager {
-    fn clone(&self) -> Self {
-        SampleManager {
-            id: self.id,
-            sample_buffer_size: self.sample_buffer_size,
-            sample_buffer: Arc::new(RwLock::new(VecDeque::with_capacity(self.sample_buffer_size))),
-            delay_windows: self.delay_windows.clone(),
-            num_delay_windows: self.num_delay_windows,
-            num_predelay_samples: self.num_predelay_samples,
-            sample_buffer_read_index: AtomicUsize::new(self.sample_buffer_read_index.load(Ordering::SeqCst)),
-            sample_buffer_write_index: AtomicUsize::new(self.sample_buffer_write_index.load(Ordering::SeqCst)),
-            init_buffer_index: AtomicUsize::new(self.init_buffer_index.load(Ordering::SeqCst)),
-            cur_delay_window: AtomicUsize::new(self.cur_delay_window.load(Ordering::SeqCst)),
-            delay_windows_index: AtomicUsize::new(self.delay_windows_index.load(Ordering::SeqCst)),
-            is_full: AtomicBool::new(self.is_full.load(Ordering::SeqCst)),
-            is_active: AtomicBool::new(self.is_active.load(Ordering::SeqCst)),
-            is_pre_delay: AtomicBool::new(self.is_pre_delay.load(Ordering::SeqCst)),
-            is_in_delay_window: AtomicBool::new(self.is_in_delay_window.load(Ordering::SeqCst)),
-            is_in_interval: AtomicBool::new(self.is_in_interval.load(Ordering::SeqCst)),
-            has_spawned: AtomicBool::new(self.has_spawned.load(Ordering::SeqCst)),
-        }
-    }
-}
 
+use crate::common::constants::TAIL_LEVEL;
+use crate::common::render_config::RenderConfig;
+use crate::effect::filter::Filter;
+
+static DEFAULT_DELAY_TIME_MS: f32 = 250.0;
+static DEFAULT_DELAY_FEEDBACK: f32 = 0.5;
+static DEFAULT_DELAY_MIX: f32 = 0.5;
+// Shortest delay, so the feedback always reads a sample already written
+static MIN_DELAY_SAMPLES: f32 = 1.0;
+
+// Feedback delay. Each sample is written to a circular buffer this delay owns, and read back
+// delay_time_ms later, between samples when the time doesn't land on one. What is read back is
+// scaled by feedback, optionally filtered, and written back in with the input, so every echo
+// returns quieter than the last. All the state is in the instance, so a clone carries on exactly
+// where the original is and never shares its buffer
 #[allow(dead_code)]
 #[derive(Builder, Clone, Debug, PartialEq)]
-#[builder(build_fn(skip))]
+#[builder(build_fn(validate = "Self::validate"))]
 pub(crate) struct Delay {
-     
-    id: usize,
-
-    // master level at which sample events are mixed into final output
-    pub(crate) mix: f32,
-
-    // factor for how much each sample event decays in magnitude from the previous one
-    pub(crate) decay: f32,
+    // time from the input to its first echo, and between echoes
+    #[builder(default = "DEFAULT_DELAY_TIME_MS")]
+    pub(crate) delay_time_ms: f32,
 
-    // duration of the silence between sample events
-    pub(crate) interval_ms: f32,
+    // from 0.0, a single echo, up to 1.0, exclusive, how much of each echo returns in the next
+    #[builder(default = "DEFAULT_DELAY_FEEDBACK")]
+    pub(crate) feedback: f32,
 
-    // duration of each sample event
-    pub(crate) duration_ms: f32,
-
-    // the number of sample events
-    pub(crate) num_repeats: usize,
+    // from 0.0, all dry, to 1.0, all echoes
+    #[builder(default = "DEFAULT_DELAY_MIX")]
+    pub(crate) mix: f32,
 
-    // number of samples in the pre-delay buffer
-    pub(crate) num_predelay_samples: usize,
+    // filter in the feedback path, e.g. a low-pass so each echo is darker than the one before.
+    // None leaves the echoes unfiltered
+    #[builder(default = "None")]
+    pub(crate) feedback_filter: Option<Filter>,
 
-    // the number of concurrent sample managers allowed
-    pub(crate) num_concurrent_sample_managers: usize,  
+    // sample rate the delay time is measured in
+    #[builder(default = "RenderConfig::default()")]
+    pub(crate) render_config: RenderConfig,
 
-    #[builder(field(private))]
-    sample_manager_id_counter: usize,
-    
-    #[builder(field(private))]
-    sample_manager_is_full_counter: usize,
+    // delay_time_ms in samples, computed at build time because it's constant
+    #[builder(default = "self.delay_samples()", setter(skip))]
+    delay_samples: f32,
 
-    // complement of mix, private compute at build time because it's constant
-    #[builder(field(private))]
-    mix_complement: f32,
-    
-    // boundaries of sample indexes in delay windows or in intervals between delay windows
-    #[builder(field(private))]
-    delay_windows: Vec<bool>,
-    
-    #[builder(field(private))]
-    duration_num_samples: usize,
+    // The delay line, with room past the delay for the sample read between
+    #[builder(default = "vec![0.0; self.delay_samples() as usize + 2]", setter(skip))]
+    sample_buffer: Vec<f32>,
 
-    #[builder(field(private))]
-    interval_num_samples: usize,
+    // The current index for inserting samples into the buffer
+    #[builder(default = "0", setter(skip))]
+    insert_index: usize,
 }
 
-// build the delay windows vectors, just the length of the sequence of indexes in each delay
-// window and the interval between delay windows, set to true or false. To allow fast lookup
-// of whether we add a delay sample as we iterate through samples
-fn build_delay_windows(duration_num_samples: usize, interval_num_samples: usize,
-                       num_repeats: usize) -> Vec<bool> {
-
-    let mut delay_windows = Vec::new();
-    let samples_total = (duration_num_samples * num_repeats) +
-        (interval_num_samples * num_repeats - 1);
-    
-    let mut in_window = true;
-    let mut in_window_index: usize = 0;
-    for _ in 0..samples_total {
-        if in_window {
-            delay_windows.push(true);
-        } else {
-            delay_windows.push(false);
+#[allow(dead_code)]
+impl DelayBuilder {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(delay_time_ms) = self.delay_time_ms {
+            if delay_time_ms <= 0.0 {
+                return Err(String::from("Delay: delay_time_ms must be greater than 0.0"));
+            }
         }
-        
-        in_window_index += 1;
-        if in_window && in_window_index == duration_num_samples {
-            in_window = false;
-            in_window_index = 0;
-        } else if !in_window && in_window_index == interval_num_samples {
-            in_window = true;
-            in_window_index = 0;
+        if let Some(feedback) = self.feedback {
+            if !(0.0..1.0).contains(&feedback) {
+                return Err(String::from("Delay: feedback must be from 0.0 up to 1.0"));
+            }
         }
+        if let Some(mix) = self.mix {
+            if !(0.0..=1.0).contains(&mix) {
+                return Err(String::from("Delay: mix must be between 0.0 and 1.0"));
+            }
+        }
+        Ok(())
     }
-    
-    delay_windows
-}
 
-#[allow(dead_code)]
-impl DelayBuilder {
-    
-    pub(crate) fn build(&mut self) -> Result<Delay, String> {
-        let id = self.id.unwrap_or(DEFAULT_DELAY_ID);
-        let mix = self.mix.unwrap_or(DEFAULT_DELAY_MIX);
-        let decay = self.decay.unwrap_or(DEFAULT_DELAY_DECAY);
-        let interval_ms = self.interval_ms.unwrap_or(DEFAULT_INTERVAL_DURATION_MS);
-        let duration_ms = self.duration_ms.unwrap_or(DEFAULT_DELAY_DURATION_MS);
-        let num_repeats = self.num_repeats.unwrap_or(DEFAULT_NUM_REPEATS);
-        let num_predelay_samples =
-            self.num_predelay_samples.unwrap_or(PREDELAY_BUFFER_SIZE);
-        let num_concurrent_sample_managers =
-            self.num_concurrent_sample_managers.unwrap_or(MAX_NUM_ACTIVE_SAMPLE_MANAGERS);
-
-        let sample_manager_id_counter = 0;
-        let sample_manager_is_full_counter = 0;
-        let duration_num_samples = duration_ms as usize * SAMPLES_PER_MS as usize;
-        let interval_num_samples = interval_ms as usize * SAMPLES_PER_MS as usize;
-        
-        // initialize the delay with one active SampleManager
-        add_sample_manager(
-            id, next_sample_manager_id(), duration_num_samples,
-            build_delay_windows(duration_num_samples, interval_num_samples, num_repeats),
-            num_repeats, num_predelay_samples,
-            0, 0, 0, 0, 0
-        );
-
-        let mix_complement = 1.0 - mix;
-        
-        Ok(
-            Delay {
-                // public
-                id,
-                mix,
-                decay,
-                interval_ms,
-                duration_ms,
-                num_repeats,
-                num_predelay_samples,
-                num_concurrent_sample_managers,
-                // private
-                sample_manager_id_counter,
-                sample_manager_is_full_counter,
-                mix_complement,
-                // window_size,
-                delay_windows: build_delay_windows(duration_num_samples, interval_num_samples,
-                                                   num_repeats),
-                duration_num_samples,
-                interval_num_samples,
-            }
-        )
+    fn delay_samples(&self) -> f32 {
+        let render_config = self.render_config.unwrap_or_default();
+        let delay_time_ms = self.delay_time_ms.unwrap_or(DEFAULT_DELAY_TIME_MS);
+        (delay_time_ms * render_config.samples_per_ms()).max(MIN_DELAY_SAMPLES)
     }
 }
 
 #[allow(dead_code)]
 impl Delay {
-    
-    pub(crate) fn apply_effect(&mut self, sample: f32, _sample_clock: f32) -> f32 {
-        let delay_sample = Arc::new(Mutex::new(0.0f32));
-        let num_delay_samples = AtomicUsize::new(0);
-        let push = AtomicBool::new(false);
-        
-        // Process all samples under one lock
-        {
-            let mut managers = ACTIVE_SAMPLE_MANAGERS.lock().unwrap();
-            if let Some(sample_managers) = managers.get_mut(&self.id) {
-                for sample_manager in sample_managers.iter_mut() {
-                    let next_sample = sample_manager.next_sample(sample) *
-                        self.decay.powi(sample_manager.cur_delay_window.load(Ordering::SeqCst) as i32);
-                    
-                    // Update delay sample under one lock
-                    {
-                        let mut current = delay_sample.lock().unwrap();
-                        *current += next_sample;
-                    }
-                    
-                    num_delay_samples.fetch_add(1, Ordering::SeqCst);
-                    
-                    if !sample_manager.has_spawned.load(Ordering::SeqCst) &&
-                            sample_manager.is_full.load(Ordering::SeqCst) {
-                        sample_manager.has_spawned.store(true, Ordering::SeqCst);
-                        push.store(true, Ordering::SeqCst);
-                    }
-                }
-            }
-        }
+    pub(crate) fn apply_effect(&mut self, sample: f32) -> f32 {
+        let delay_sample = self.read_delayed(self.delay_samples);
 
-        // Get final value under one lock
-        let final_value = {
-            let mut value = delay_sample.lock().unwrap();
-            if num_delay_samples.load(Ordering::SeqCst) > 0 {
-                *value /= num_delay_samples.load(Ordering::SeqCst) as f32;
-            }
-            *value
-        };
-
-        // Add new manager outside the lock
-        // enforce global limit on number of active sample managers
-        if push.load(Ordering::SeqCst) &&
-                *SAMPLE_MANAGER_ID_COUNTER.lock().unwrap() < self.num_concurrent_sample_managers {
-            add_sample_manager(
-                self.id,
-                next_sample_manager_id(),
-                self.duration_num_samples,
-                self.delay_windows.clone(),
-                self.num_repeats,
-                self.num_predelay_samples,
-                0, 0, 0, 0, 0
-            );
+        let mut feedback_sample = delay_sample * self.feedback;
+        if let Some(filter) = self.feedback_filter.as_mut() {
+            feedback_sample = filter.apply_effect(feedback_sample);
         }
-        push.store(false, Ordering::SeqCst);
+        self.sample_buffer[self.insert_index] = sample + feedback_sample;
+        self.insert_index = (self.insert_index + 1) % self.sample_buffer.len();
 
-        self.mix_complement * sample + (self.mix * final_value)
+        sample * (1.0 - self.mix) + delay_sample * self.mix
+    }
+
+    // Samples the echoes run on for after the input stops, until they die away to TAIL_LEVEL.
+    // Each echo is the last scaled by the feedback, and a feedback filter only shortens it
+    pub(crate) fn tail_samples(&self) -> usize {
+        if self.mix == 0.0 {
+            return 0;
+        }
+        let mut num_echoes = 1.0;
+        if self.feedback > 0.0 {
+            num_echoes += (TAIL_LEVEL.ln() / self.feedback.ln()).ceil();
+        }
+        (num_echoes * self.delay_samples).ceil() as usize + 1
+    }
 
+    // The input from delay samples ago, interpolated linearly between the samples either side.
+    // A delay of 1.0 is the last sample written
+    fn read_delayed(&self, delay: f32) -> f32 {
+        let buffer_len = self.sample_buffer.len();
+        let whole = delay.floor() as usize;
+        let fraction = delay - whole as f32;
+        let newer = self.sample_buffer[(self.insert_index + buffer_len - whole) % buffer_len];
+        let older = self.sample_buffer[(self.insert_index + buffer_len - whole - 1) % buffer_len];
+        newer + (older - newer) * fraction
     }
 }
 
@@ -442,6 +130,118 @@ pub(crate) fn default_delay() -> Delay {
 #[allow(dead_code)]
 pub(crate) fn no_op_delay() -> Delay {
     DelayBuilder::default()
-        .num_repeats(0)
+        .mix(0.0)
         .build().unwrap()
 }
+
+#[cfg(test)]
+mod test_delay {
+    use crate::common::render_config::RenderConfigBuilder;
+    use crate::common::constants::TAIL_LEVEL;
+    use crate::effect::delay::{no_op_delay, Delay, DelayBuilder};
+    use crate::effect::filter::FilterBuilder;
+
+    // a 1 kHz render, so a delay of n ms is n samples
+    fn delay_builder(delay_time_ms: f32, feedback: f32) -> DelayBuilder {
+        let render_config = RenderConfigBuilder::default().sample_rate(1000.0).build().unwrap();
+        DelayBuilder::default()
+            .delay_time_ms(delay_time_ms)
+            .feedback(feedback)
+            .mix(1.0)
+            .render_config(render_config)
+            .clone()
+    }
+
+    fn impulse_response(delay: &mut Delay, num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|i| delay.apply_effect(if i == 0 { 1.0 } else { 0.0 }))
+            .collect()
+    }
+
+    #[test]
+    fn test_echoes() {
+        let mut delay = delay_builder(10.0, 0.5).build().unwrap();
+        let output = impulse_response(&mut delay, 41);
+        assert_eq!(output[10], 1.0);
+        assert_eq!(output[20], 0.5);
+        assert_eq!(output[30], 0.25);
+        assert_eq!(output[40], 0.125);
+        assert!(output.iter().enumerate().all(|(i, sample)| i % 10 == 0 || *sample == 0.0));
+        assert_eq!(output[0], 0.0);
+
+        // no feedback is a single echo
+        let mut delay = delay_builder(10.0, 0.0).build().unwrap();
+        let output = impulse_response(&mut delay, 30);
+        assert_eq!(output.iter().sum::<f32>(), 1.0);
+
+        assert_eq!(no_op_delay().apply_effect(0.7), 0.7);
+    }
+
+    #[test]
+    fn test_fractional_delay_time() {
+        // an echo between two samples is split across them
+        let mut delay = delay_builder(10.25, 0.0).build().unwrap();
+        let output = impulse_response(&mut delay, 20);
+        assert_eq!(output[10], 0.75);
+        assert_eq!(output[11], 0.25);
+    }
+
+    #[test]
+    fn test_filtered_feedback() {
+        let render_config = RenderConfigBuilder::default().sample_rate(1000.0).build().unwrap();
+        let low_pass = FilterBuilder::default()
+            .cutoff_hz(50.0)
+            .render_config(render_config)
+            .build().unwrap();
+        let mut delay = delay_builder(10.0, 0.9)
+            .feedback_filter(Some(low_pass))
+            .build().unwrap();
+        let output = impulse_response(&mut delay, 100);
+
+        // the first echo doesn't go through the filter, the later ones are smeared by it
+        assert_eq!(output[10], 1.0);
+        assert!(output[20] < 0.9);
+        assert!(output[21] > 0.0);
+    }
+
+    #[test]
+    fn test_clone_is_independent() {
+        let mut delay = delay_builder(10.0, 0.5).build().unwrap();
+        impulse_response(&mut delay, 5);
+
+        // a clone carries on where the original is, and then goes its own way
+        let mut clone = delay.clone();
+        let output: Vec<f32> = (0..30).map(|_| delay.apply_effect(0.0)).collect();
+        let clone_output: Vec<f32> = (0..30).map(|_| clone.apply_effect(0.0)).collect();
+        assert_eq!(output, clone_output);
+        assert_eq!(output[5], 1.0);
+
+        // two delays built the same way don't hear each other's input
+        let mut first = delay_builder(10.0, 0.5).build().unwrap();
+        let mut second = delay_builder(10.0, 0.5).build().unwrap();
+        impulse_response(&mut first, 5);
+        assert!((0..30).all(|_| second.apply_effect(0.0) == 0.0));
+    }
+
+    #[test]
+    fn test_tail_samples() {
+        // the echoes have died away by the time the delay says they have, but not long before
+        let mut delay = delay_builder(10.0, 0.5).build().unwrap();
+        let tail_samples = delay.tail_samples();
+        let output = impulse_response(&mut delay, tail_samples + 50);
+        assert!(output[tail_samples..].iter().all(|sample| sample.abs() < TAIL_LEVEL));
+        assert!(output[tail_samples - 20..].iter().any(|sample| sample.abs() > TAIL_LEVEL));
+
+        // a single echo, and none from a dry delay
+        assert_eq!(delay_builder(10.0, 0.0).build().unwrap().tail_samples(), 11);
+        assert_eq!(no_op_delay().tail_samples(), 0);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(DelayBuilder::default().delay_time_ms(0.0).build().is_err());
+        assert!(DelayBuilder::default().feedback(1.0).build().is_err());
+        assert!(DelayBuilder::default().feedback(-0.1).build().is_err());
+        assert!(DelayBuilder::default().mix(1.5).build().is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use derive_builder::Builder;
+
+use crate::common::render_config::RenderConfig;
+
+static DEFAULT_CUTOFF_HZ: f32 = 1000.0;
+// Butterworth, the highest Q with no peak at the cutoff
+static DEFAULT_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+// Cutoffs are kept just below Nyquist, where the filter's tan() prewarp goes to infinity
+static MAX_CUTOFF_NYQUIST_RATIO: f32 = 0.99;
+
+// Which band the filter passes
+// LowPass: below the cutoff, the staple of subtractive patches
+// HighPass: above the cutoff
+// BandPass: around the cutoff, narrower as Q rises
+// Notch: everything but a band around the cutoff
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum FilterMode {
+    #[default]
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+// Two-pole state variable filter, in the trapezoidal integrator form, which stays stable and
+// keeps its tuning when the cutoff is swept, unlike a biquad whose coefficients are recomputed
+#[allow(dead_code)]
+#[derive(Builder, Clone, Debug, PartialEq)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub(crate) struct Filter {
+    #[builder(default = "FilterMode::default()")]
+    pub(crate) mode: FilterMode,
+
+    #[builder(default = "DEFAULT_CUTOFF_HZ")]
+    pub(crate) cutoff_hz: f32,
+
+    // resonance, how sharply the filter peaks at the cutoff. 0.5 and below doesn't peak at all
+    #[builder(default = "DEFAULT_Q")]
+    pub(crate) q: f32,
+
+    #[builder(default = "RenderConfig::default()")]
+    pub(crate) render_config: RenderConfig,
+
+    // integrator gain for the cutoff, and damping for the Q
+    #[builder(default = "self.coefficients().0", setter(skip))]
+    g: f32,
+    #[builder(default = "self.coefficients().1", setter(skip))]
+    k: f32,
+
+    // state of the two integrators
+    #[builder(default = "0.0", setter(skip))]
+    ic1eq: f32,
+    #[builder(default = "0.0", setter(skip))]
+    ic2eq: f32,
+}
+
+#[allow(dead_code)]
+impl FilterBuilder {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(cutoff_hz) = self.cutoff_hz {
+            if cutoff_hz <= 0.0 {
+                return Err(String::from("Filter: cutoff_hz must be greater than 0.0"));
+            }
+        }
+        if let Some(q) = self.q {
+            if q <= 0.0 {
+                return Err(String::from("Filter: q must be greater than 0.0"));
+            }
+        }
+        Ok(())
+    }
+
+    fn coefficients(&self) -> (f32, f32) {
+        coefficients(self.cutoff_hz.unwrap_or(DEFAULT_CUTOFF_HZ), self.q.unwrap_or(DEFAULT_Q),
+                     &self.render_config.unwrap_or_default())
+    }
+}
+
+#[allow(dead_code)]
+impl Filter {
+    // Moves the cutoff without resetting the filter's state, so it can be swept while it plays
+    pub(crate) fn set_cutoff_hz(&mut self, cutoff_hz: f32) {
+        self.cutoff_hz = cutoff_hz;
+        (self.g, self.k) = coefficients(self.cutoff_hz, self.q, &self.render_config);
+    }
+
+    // Moves the cutoff this many octaves from cutoff_hz for the next samples, leaving cutoff_hz
+    // in place so a modulation source can keep sweeping around it
+    pub(crate) fn modulate_cutoff(&mut self, octaves: f32) {
+        (self.g, self.k) = coefficients(self.cutoff_hz * 2.0f32.powf(octaves), self.q,
+                                        &self.render_config);
+    }
+
+    pub(crate) fn apply_effect(&mut self, sample: f32) -> f32 {
+        let (g, k) = (self.g, self.k);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = sample - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        // v2 is the low-pass output and v1 the band-pass output, scaled by k to unity gain
+        match self.mode {
+            FilterMode::LowPass => v2,
+            FilterMode::HighPass => sample - k * v1 - v2,
+            FilterMode::BandPass => k * v1,
+            FilterMode::Notch => sample - k * v1,
+        }
+    }
+}
+
+// Integrator gain g, prewarped so the cutoff lands where asked at any sample rate, and damping k
+fn coefficients(cutoff_hz: f32, q: f32, render_config: &RenderConfig) -> (f32, f32) {
+    let max_cutoff_hz = MAX_CUTOFF_NYQUIST_RATIO * render_config.nyquist_frequency();
+    let cutoff_hz = cutoff_hz.clamp(1.0, max_cutoff_hz);
+    ((std::f32::consts::PI * cutoff_hz / render_config.sample_rate).tan(), 1.0 / q)
+}
+
+#[allow(dead_code)]
+pub(crate) fn default_filter() -> Filter {
+    FilterBuilder::default().build().unwrap()
+}
+
+#[cfg(test)]
+mod test_filter {
+    use crate::effect::filter::{FilterBuilder, FilterMode};
+
+    // Peak amplitude of the filter's output for a sine at frequency, once the filter settles
+    fn sine_gain(mode: FilterMode, q: f32, frequency: f32) -> f32 {
+        let mut filter = FilterBuilder::default()
+            .mode(mode)
+            .cutoff_hz(1000.0)
+            .q(q)
+            .build().unwrap();
+        let sample_rate = filter.render_config.sample_rate;
+        (0..8820)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate;
+                filter.apply_effect(phase.sin())
+            })
+            .skip(4410)
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn test_modes() {
+        let q = std::f32::consts::FRAC_1_SQRT_2;
+        // two octaves past the cutoff a two-pole filter is down about 24 dB
+        assert!(sine_gain(FilterMode::LowPass, q, 100.0) > 0.99);
+        assert!(sine_gain(FilterMode::LowPass, q, 4000.0) < 0.07);
+        assert!(sine_gain(FilterMode::HighPass, q, 250.0) < 0.07);
+        assert!(sine_gain(FilterMode::HighPass, q, 10000.0) > 0.99);
+        assert!((sine_gain(FilterMode::BandPass, q, 1000.0) - 1.0).abs() < 0.01);
+        assert!(sine_gain(FilterMode::BandPass, q, 100.0) < 0.15);
+        assert!(sine_gain(FilterMode::Notch, q, 1000.0) < 0.01);
+        assert!(sine_gain(FilterMode::Notch, q, 100.0) > 0.95);
+    }
+
+    #[test]
+    fn test_resonance() {
+        // at the cutoff a low-pass filter's gain is its Q
+        assert!((sine_gain(FilterMode::LowPass, 4.0, 1000.0) - 4.0).abs() < 0.1);
+        assert!((sine_gain(FilterMode::LowPass, 0.5, 1000.0) - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_modulate_cutoff() {
+        let mut modulated = FilterBuilder::default().cutoff_hz(500.0).build().unwrap();
+        modulated.modulate_cutoff(1.0);
+        assert_eq!(modulated.cutoff_hz, 500.0);
+        let mut filter = FilterBuilder::default().cutoff_hz(1000.0).build().unwrap();
+        for i in 0..100 {
+            let sample = (i as f32 * 0.3).sin();
+            assert_eq!(modulated.apply_effect(sample), filter.apply_effect(sample));
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(FilterBuilder::default().cutoff_hz(0.0).build().is_err());
+        assert!(FilterBuilder::default().q(-1.0).build().is_err());
+    }
+}
@@ -1,104 +1,162 @@
-use std::collections::VecDeque;
-use std::sync::{Arc, RwLock};
-use std::sync::atomic::{AtomicUsize, Ordering};
 use derive_builder::Builder;
 
+use crate::common::render_config::RenderConfig;
+
 static SAMPLE_BUFFER_SIZE: usize = 20;
-static DEFAULT_WINDOW_SIZE: usize = 12;
 static DEFAULT_MIX: f32 = 0.5;
+static DEFAULT_RATE_HZ: f32 = 0.25;
+static DEFAULT_CHORUS_VOICES: usize = 3;
+// Shortest delay the sweep reaches, so the feedback always reads a sample already written
+static MIN_DELAY_SAMPLES: f32 = 1.0;
+
+// Flanger: one delayed copy swept against the dry signal, for the jet-plane comb sweep
+// Chorus: several copies swept out of step with each other, which thickens the sound like a
+//  section of slightly detuned players
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum FlangerMode {
+    #[default]
+    Flanger,
+    Chorus,
+}
 
-#[derive(Builder, Debug)]
+// Mixes the input with copies of itself delayed by a time an internal sine LFO sweeps. The delay
+// moves from window_size samples down towards none and back, read between samples so the sweep
+// is smooth, and feedback runs the delayed signal back into the delay line to sharpen the peaks
+#[allow(dead_code)]
+#[derive(Builder, Clone, Debug, PartialEq)]
+#[builder(build_fn(validate = "Self::validate"))]
 pub(crate) struct Flanger {
-    // The size of the sample window
+    // The longest delay in samples, where the sweep starts. 0 bypasses the effect
     #[builder(default = "SAMPLE_BUFFER_SIZE")]
-    window_size: usize,
-
-    // The buffer holding samples for the flanger effect
-    #[builder(field(private),
-      default = "Arc::new(RwLock::new(VecDeque::with_capacity(self.window_size.unwrap_or(SAMPLE_BUFFER_SIZE))))",
-      setter(custom))]
-    sample_buffer: Arc<RwLock<VecDeque<f32>>>,
-
-    // The current index for inserting samples into the buffer
-    #[builder(default = "AtomicUsize::new(0)", setter(skip))]
-    insert_index: AtomicUsize,
+    pub(crate) window_size: usize,
 
-    // The mix level of the effect 
+    // The mix level of the effect
     #[builder(default = "DEFAULT_MIX")]
     pub(crate) mix: f32,
 
     // Complement of mix, computed at build time
     #[builder(field(private), default = "1.0 - self.mix.unwrap_or(DEFAULT_MIX)")]
     mix_complement: f32,
-}
 
-impl Clone for Flanger {
-    fn clone(&self) -> Self {
-        Flanger {
-            window_size: self.window_size,
-            sample_buffer: Arc::new(RwLock::new(VecDeque::with_capacity(self.window_size))),
-            insert_index: AtomicUsize::new(self.insert_index.load(Ordering::SeqCst)),
-            mix: self.mix,
-            mix_complement: self.mix_complement,
-        }
-    }
-}
+    #[builder(default = "FlangerMode::default()")]
+    pub(crate) mode: FlangerMode,
 
-impl PartialEq for Flanger {
-    fn eq(&self, other: &Self) -> bool {
-        self.window_size == other.window_size &&
-        self.insert_index.load(Ordering::SeqCst) ==
-            other.insert_index.load(Ordering::SeqCst) &&
-        self.mix == other.mix &&
-        self.mix_complement == other.mix_complement
-    }
+    // number of delayed copies in Chorus mode, their sweeps spread evenly through the LFO's cycle
+    #[builder(default = "DEFAULT_CHORUS_VOICES")]
+    pub(crate) voices: usize,
+
+    // frequency of the sweep
+    #[builder(default = "DEFAULT_RATE_HZ")]
+    pub(crate) rate_hz: f32,
+
+    // from 0.0, a fixed delay of window_size, to 1.0, sweeping all the way down to no delay
+    #[builder(default = "0.0")]
+    pub(crate) depth: f32,
+
+    // from -1.0 to 1.0, exclusive. Negative feedback flips the delayed signal, moving the peaks
+    // to where the notches were
+    #[builder(default = "0.0")]
+    pub(crate) feedback: f32,
+
+    #[builder(default = "RenderConfig::default()")]
+    pub(crate) render_config: RenderConfig,
+
+    // The delay line, with room past window_size for the sample read between
+    #[builder(default = "vec![0.0; self.window_size.unwrap_or(SAMPLE_BUFFER_SIZE) + 2]",
+              setter(skip))]
+    sample_buffer: Vec<f32>,
+
+    // The current index for inserting samples into the buffer
+    #[builder(default = "0", setter(skip))]
+    insert_index: usize,
+
+    // position of the LFO through its cycle, from 0.0 to 1.0
+    #[builder(default = "0.0", setter(skip))]
+    lfo_phase: f64,
 }
 
 #[allow(dead_code)]
 impl FlangerBuilder {
-    pub(crate) fn sample_buffer(&mut self, sample_buffer: Arc<RwLock<VecDeque<f32>>>) -> &mut Self {
-        let window_size = self.window_size.unwrap_or(DEFAULT_WINDOW_SIZE);
-        for _ in 0..window_size {
-            sample_buffer.write().unwrap().push_back(0.0);
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(mix) = self.mix {
+            if !(0.0..=1.0).contains(&mix) {
+                return Err(String::from("Flanger: mix must be between 0.0 and 1.0"));
+            }
+        }
+        if let Some(depth) = self.depth {
+            if !(0.0..=1.0).contains(&depth) {
+                return Err(String::from("Flanger: depth must be between 0.0 and 1.0"));
+            }
+        }
+        if let Some(feedback) = self.feedback {
+            if feedback.abs() >= 1.0 {
+                return Err(String::from("Flanger: feedback must be between -1.0 and 1.0"));
+            }
+        }
+        if let Some(rate_hz) = self.rate_hz {
+            if rate_hz < 0.0 {
+                return Err(String::from("Flanger: rate_hz must not be negative"));
+            }
         }
-        self.sample_buffer = Some(sample_buffer);
-        self
+        if self.voices == Some(0) {
+            return Err(String::from("Flanger: voices must be greater than 0"));
+        }
+        Ok(())
     }
 }
 
 #[allow(dead_code)]
 impl Flanger {
     pub(crate) fn apply_effect(&mut self, sample: f32, _sample_clock: f32) -> f32 {
-        let mut flanger_sample = 0.0;
-        
-        // Write new sample
-        {
-            let mut buffer = self.sample_buffer.write().unwrap();
-            if buffer.len() < self.window_size {
-                buffer.push_back(sample);
-            } else {
-                let idx = self.insert_index.load(Ordering::SeqCst) % self.window_size;
-                if let Some(old_sample) = buffer.get_mut(idx) {
-                    *old_sample = sample;
-                }
-            }
+        if self.window_size == 0 {
+            return sample;
         }
 
-        // Read delayed sample
-        {
-            let buffer = self.sample_buffer.read().unwrap();
-            if !buffer.is_empty() {
-                let read_idx = (self.insert_index.load(Ordering::SeqCst) + 1) % buffer.len();
-                flanger_sample = *buffer.get(read_idx).unwrap_or(&0.0);
-            }
-        }
+        let num_taps = match self.mode {
+            FlangerMode::Flanger => 1,
+            FlangerMode::Chorus => self.voices,
+        };
+        let flanger_sample = (0..num_taps)
+            .map(|tap| {
+                let phase = self.lfo_phase + tap as f64 / num_taps as f64;
+                self.read_delayed(self.delay_samples(phase))
+            })
+            .sum::<f32>() / num_taps as f32;
 
-        // Update insert index
-        self.insert_index.fetch_add(1, Ordering::SeqCst);
+        self.sample_buffer[self.insert_index] = sample + self.feedback * flanger_sample;
+        self.insert_index = (self.insert_index + 1) % self.sample_buffer.len();
+        self.lfo_phase = (self.lfo_phase +
+            self.rate_hz as f64 / self.render_config.sample_rate as f64).fract();
 
         // Mix original and flanged samples
         sample * self.mix_complement + flanger_sample * self.mix
     }
+
+    // Puts the LFO where it is at sample_index of the piece had it been running since the start,
+    // so the notes a flanger runs on all sweep in step wherever they start
+    pub(crate) fn set_lfo_phase_at(&mut self, sample_index: u64) {
+        self.lfo_phase = (self.rate_hz as f64 * sample_index as f64 /
+            self.render_config.sample_rate as f64).fract();
+    }
+
+    // Delay at this point in the LFO's cycle, window_size at the start of the cycle and shortest
+    // half way through
+    fn delay_samples(&self, phase: f64) -> f32 {
+        let sweep = 0.5 * (1.0 - (2.0 * std::f64::consts::PI * phase).cos()) as f32;
+        (self.window_size as f32 * (1.0 - self.depth * sweep)).max(MIN_DELAY_SAMPLES)
+    }
+
+    // The input from delay samples ago, interpolated linearly between the samples either side.
+    // A delay of 1.0 is the last sample written
+    fn read_delayed(&self, delay: f32) -> f32 {
+        let buffer_len = self.sample_buffer.len();
+        let whole = delay.floor() as usize;
+        let fraction = delay - whole as f32;
+        let newer = self.sample_buffer[(self.insert_index + buffer_len - whole) % buffer_len];
+        let older = self.sample_buffer[(self.insert_index + buffer_len - whole - 1) % buffer_len];
+        newer + (older - newer) * fraction
+    }
 }
 
 #[allow(dead_code)]
@@ -114,3 +172,96 @@ pub(crate) fn no_op_flanger() -> Flanger {
         .window_size(0)
         .build().unwrap()
 }
+
+#[cfg(test)]
+mod test_flanger {
+    use crate::effect::flanger::{no_op_flanger, Flanger, FlangerBuilder, FlangerMode};
+
+    fn impulse_response(flanger: &mut Flanger, num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|i| flanger.apply_effect(if i == 0 { 1.0 } else { 0.0 }, 0.0))
+            .collect()
+    }
+
+    #[test]
+    fn test_fixed_delay_and_feedback() {
+        let mut flanger = FlangerBuilder::default()
+            .window_size(10)
+            .mix(0.5)
+            .build().unwrap();
+        let output = impulse_response(&mut flanger, 30);
+        assert_eq!(output[0], 0.5);
+        assert_eq!(output[10], 0.5);
+        assert!(output.iter().enumerate().all(|(i, sample)| i == 0 || i == 10 || *sample == 0.0));
+
+        // each pass round the delay line comes back at the feedback level
+        let mut flanger = FlangerBuilder::default()
+            .window_size(10)
+            .mix(1.0)
+            .feedback(0.5)
+            .build().unwrap();
+        let output = impulse_response(&mut flanger, 31);
+        assert_eq!(output[10], 1.0);
+        assert_eq!(output[20], 0.5);
+        assert_eq!(output[30], 0.25);
+
+        assert_eq!(no_op_flanger().apply_effect(0.7, 0.0), 0.7);
+    }
+
+    #[test]
+    fn test_sweep() {
+        // a 10 Hz sweep of a 20 sample delay, at its shortest after 50 ms
+        let mut flanger = FlangerBuilder::default()
+            .window_size(20)
+            .mix(1.0)
+            .rate_hz(10.0)
+            .depth(0.5)
+            .build().unwrap();
+        assert_eq!(flanger.delay_samples(0.0), 20.0);
+        assert!((flanger.delay_samples(0.5) - 10.0).abs() < 1e-5);
+        assert!((flanger.delay_samples(0.25) - 15.0).abs() < 1e-5);
+
+        // a constant input comes through the interpolation unchanged, once the delay line fills
+        let output: Vec<f32> = (0..4410).map(|_| flanger.apply_effect(1.0, 0.0)).collect();
+        assert!(output[21..].iter().all(|sample| (sample - 1.0).abs() < 1e-5));
+        assert!((flanger.lfo_phase - 0.0).abs() < 1e-6 || (flanger.lfo_phase - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_lfo_phase_at() {
+        // 25 ms into a 10 Hz sweep is a quarter of the way through its cycle, and 125 ms in
+        // is the same point in the next one
+        let mut flanger = FlangerBuilder::default().rate_hz(10.0).build().unwrap();
+        flanger.set_lfo_phase_at(1102);
+        assert!((flanger.lfo_phase - 0.25).abs() < 1e-3);
+        flanger.set_lfo_phase_at(5512);
+        assert!((flanger.lfo_phase - 0.25).abs() < 1e-3);
+        flanger.set_lfo_phase_at(0);
+        assert_eq!(flanger.lfo_phase, 0.0);
+    }
+
+    #[test]
+    fn test_chorus() {
+        let mut flanger = FlangerBuilder::default()
+            .window_size(400)
+            .mix(1.0)
+            .mode(FlangerMode::Chorus)
+            .voices(2)
+            .depth(0.5)
+            .build().unwrap();
+        // the two voices start half a cycle apart, at the longest and shortest delays, so a click
+        // comes back twice at half level
+        let output = impulse_response(&mut flanger, 401);
+        assert!((output[200] - 0.5).abs() < 0.01);
+        assert!((output[400] - 0.5).abs() < 0.01);
+        assert!((output.iter().sum::<f32>() - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(FlangerBuilder::default().feedback(1.0).build().is_err());
+        assert!(FlangerBuilder::default().depth(1.5).build().is_err());
+        assert!(FlangerBuilder::default().voices(0).build().is_err());
+        assert!(FlangerBuilder::default().rate_hz(-1.0).build().is_err());
+    }
+}
@@ -1,13 +1,16 @@
 use derive_builder::Builder;
 
-use crate::audio_gen::oscillator::{get_gaussian_noise_sample, get_sample, OscillatorTables};
+use crate::audio_gen::noise::NoiseGenerator;
+use crate::audio_gen::oscillator::{OscillatorTables, PhaseAccumulator};
 use crate::audio_gen::oscillator::Waveform;
-use crate::common::constants::{DEFAULT_LFO_AMPLITUDE, SAMPLE_RATE};
+use crate::common::constants::{DEFAULT_LFO_AMPLITUDE, DEFAULT_SAMPLE_RATE};
+use crate::common::render_config::RenderConfig;
 
 #[allow(dead_code)]
 #[derive(Builder, Clone, Debug, PartialEq)]
+#[builder(build_fn(validate = "Self::validate"))]
 pub(crate) struct LFO {
-    #[builder(default = "SAMPLE_RATE / 10.0", setter(custom))]
+    #[builder(default = "DEFAULT_SAMPLE_RATE / 10.0", setter(custom))]
     pub(crate) frequency: f32,
 
     #[builder(default = "DEFAULT_LFO_AMPLITUDE")]
@@ -18,8 +21,19 @@ pub(crate) struct LFO {
     #[builder(default = "vec![Waveform::Sine]", setter(custom))]
     pub(crate) waveforms: Vec<Waveform>,
 
-    #[builder(default = "OscillatorTables::new()", setter(skip))]
+    #[builder(default = "RenderConfig::default()")]
+    pub(crate) render_config: RenderConfig,
+
+    #[builder(default = "OscillatorTables::with_render_config(self.render_config.unwrap_or_default())",
+              setter(skip))]
     oscillator_tables: OscillatorTables,
+
+    // for noise waveforms, seeded from the render seed, or 0 if it has none so LFOs built the
+    // same way are equal
+    #[builder(default = "NoiseGenerator::from_seed(
+                  self.render_config.unwrap_or_default().seed.unwrap_or_default())",
+              setter(skip))]
+    noise: NoiseGenerator,
 }
 
 #[allow(dead_code)]
@@ -28,40 +42,53 @@ impl LFOBuilder {
         if frequency <= 0.0 {
             panic!("LFO frequency must be greater than 0.0");
         }
-        if frequency > SAMPLE_RATE / 2.0 {
-            panic!("LFO frequency must be less than the Nyquist frequency");
-        }
         self.frequency = Some(frequency);
         self
     }
 
     pub(crate) fn waveforms(&mut self, waveforms: Vec<Waveform>) -> &mut Self {
-        if waveforms.contains(&Waveform::Square) {
-            panic!("LFO cannot contain square waveform");
+        if waveforms.iter().any(|waveform|
+            matches!(waveform, Waveform::Square(_) | Waveform::Pulse(..))) {
+            panic!("LFO cannot contain square or pulse waveform");
         }
         self.waveforms = Some(waveforms);
         self
     }
+
+    // The Nyquist frequency depends on the render sample rate, which may be set after frequency
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let render_config = self.render_config.unwrap_or_default();
+        if let Some(frequency) = self.frequency {
+            if frequency > render_config.nyquist_frequency() {
+                return Err(String::from("LFO frequency must be less than the Nyquist frequency"));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl LFO {
     #[allow(dead_code)]
-    pub(crate) fn apply_effect(&self, mut sample: f32, sample_count: u64) -> f32 {
-        for waveform in self.waveforms.clone() {
+    pub(crate) fn apply_effect(&mut self, mut sample: f32, sample_count: u64) -> f32 {
+        // frequency never changes, so the phase can be computed directly from the sample count
+        let phase = PhaseAccumulator::new(self.frequency as f64 * sample_count as f64 /
+            self.render_config.sample_rate as f64).phase();
+        for waveform in self.waveforms.iter() {
             sample += match waveform {
-                Waveform::GaussianNoise => get_gaussian_noise_sample(),
-                Waveform::Saw => get_sample(&self.oscillator_tables.saw_table,
-                                            self.frequency, sample_count),
-                Waveform::Sine => get_sample(&self.oscillator_tables.sine_table,
-                                             self.frequency, sample_count),
-                Waveform::Triangle => get_sample(&self.oscillator_tables.triangle_table,
-                                                 self.frequency, sample_count),
-                // LFO cannot contain square waveform
-                Waveform::Square => 0.0
+                // LFO cannot contain square or pulse waveform
+                Waveform::Square(_) | Waveform::Pulse(..) => 0.0,
+                _ => self.oscillator_tables.get_waveform_sample(*waveform, self.frequency, phase,
+                                                                &mut self.noise),
             }
         }
         self.amplitude * sample
     }
+
+    // LFO output on its own, for modulating a parameter rather than a sample
+    #[allow(dead_code)]
+    pub(crate) fn modulation(&mut self, sample_count: u64) -> f32 {
+        self.apply_effect(0.0, sample_count)
+    }
 }
 
 #[allow(dead_code)]
@@ -1,3 +1,6 @@
 pub mod flanger;
 pub mod lfo;
 pub mod delay;
+pub mod convolution;
+pub mod filter;
+pub mod reverb;
@@ -0,0 +1,333 @@
+use derive_builder::Builder;
+
+use crate::common::constants::TAIL_LEVEL;
+use crate::common::render_config::RenderConfig;
+use crate::common::stereo::StereoFrame;
+
+static DEFAULT_ROOM_SIZE: f32 = 0.5;
+static DEFAULT_DAMPING: f32 = 0.5;
+static DEFAULT_PREDELAY_MS: f32 = 0.0;
+static DEFAULT_WIDTH: f32 = 1.0;
+static DEFAULT_MIX: f32 = 0.3;
+
+// Freeverb's tunings, in samples at 44.1 kHz and scaled to the render rate. The comb and
+// all-pass lengths are mutually prime so their echoes don't pile up on the same samples, and the
+// right channel's are longer by STEREO_SPREAD, which decorrelates it from the left
+static TUNING_SAMPLE_RATE: f32 = 44100.0;
+static COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+static ALL_PASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+static STEREO_SPREAD: usize = 23;
+// Freeverb's gain staging: input is scaled down so eight combs summed don't overload, room size
+// maps onto feedback from 0.7 to 0.98, and the wet signal is scaled back up
+static INPUT_GAIN: f32 = 0.015;
+static ROOM_SCALE: f32 = 0.28;
+static ROOM_OFFSET: f32 = 0.7;
+static DAMPING_SCALE: f32 = 0.4;
+static WET_SCALE: f32 = 3.0;
+static ALL_PASS_FEEDBACK: f32 = 0.5;
+
+// Feedback comb filter with a one-pole low-pass in its loop, so high frequencies die away
+// faster than low ones, as they do in a real room
+#[derive(Clone, Debug, PartialEq)]
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filter_store: f32,
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Comb { buffer: vec![0.0; length.max(1)], index: 0, filter_store: 0.0 }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.filter_store = output * (1.0 - damping) + self.filter_store * damping;
+        self.buffer[self.index] = input + self.filter_store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+}
+
+// Schroeder all-pass, which smears the combs' echoes into a dense tail without coloring it
+#[derive(Clone, Debug, PartialEq)]
+struct AllPass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl AllPass {
+    fn new(length: usize) -> Self {
+        AllPass { buffer: vec![0.0; length.max(1)], index: 0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.index];
+        self.buffer[self.index] = input + buffered * ALL_PASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+        buffered - input
+    }
+}
+
+// One channel of the reverb, eight combs in parallel into four all-passes in series
+#[derive(Clone, Debug, PartialEq)]
+struct ReverbChannel {
+    combs: Vec<Comb>,
+    all_passes: Vec<AllPass>,
+}
+
+impl ReverbChannel {
+    fn new(spread: usize, render_config: &RenderConfig) -> Self {
+        let scale = |tuning: usize| {
+            ((tuning + spread) as f32 * render_config.sample_rate / TUNING_SAMPLE_RATE).round()
+                as usize
+        };
+        ReverbChannel {
+            combs: COMB_TUNINGS.iter().map(|tuning| Comb::new(scale(*tuning))).collect(),
+            all_passes: ALL_PASS_TUNINGS.iter()
+                .map(|tuning| AllPass::new(scale(*tuning)))
+                .collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let mut output = self.combs.iter_mut()
+            .map(|comb| comb.process(input, feedback, damping))
+            .sum();
+        for all_pass in self.all_passes.iter_mut() {
+            output = all_pass.process(output);
+        }
+        output
+    }
+}
+
+// Freeverb, Jezar's tuning of a Schroeder reverb. The stereo input is summed, delayed by the
+// pre-delay and fed to a left and right network of comb and all-pass filters, whose outputs are
+// crossed by width and mixed with the dry signal
+#[allow(dead_code)]
+#[derive(Builder, Clone, Debug, PartialEq)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub(crate) struct Reverb {
+    // from 0.0 to 1.0, how long the tail rings
+    #[builder(default = "DEFAULT_ROOM_SIZE")]
+    pub(crate) room_size: f32,
+
+    // from 0.0 to 1.0, how quickly the high frequencies in the tail die away
+    #[builder(default = "DEFAULT_DAMPING")]
+    pub(crate) damping: f32,
+
+    // gap between the dry signal and the start of the tail
+    #[builder(default = "DEFAULT_PREDELAY_MS")]
+    pub(crate) predelay_ms: f32,
+
+    // from 0.0 for a mono tail to 1.0 for a fully stereo one
+    #[builder(default = "DEFAULT_WIDTH")]
+    pub(crate) width: f32,
+
+    // from 0.0, all dry, to 1.0, all reverb
+    #[builder(default = "DEFAULT_MIX")]
+    pub(crate) mix: f32,
+
+    // sample rate the filter lengths and pre-delay are scaled to
+    #[builder(default = "RenderConfig::default()")]
+    pub(crate) render_config: RenderConfig,
+
+    #[builder(default = "self.predelay_buffer()", setter(skip))]
+    predelay_buffer: Vec<f32>,
+    #[builder(default = "0", setter(skip))]
+    predelay_index: usize,
+
+    #[builder(default = "self.channel(0)", setter(skip))]
+    left: ReverbChannel,
+    #[builder(default = "self.channel(STEREO_SPREAD)", setter(skip))]
+    right: ReverbChannel,
+}
+
+#[allow(dead_code)]
+impl ReverbBuilder {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        for (name, value) in [("room_size", self.room_size), ("damping", self.damping),
+                              ("width", self.width), ("mix", self.mix)] {
+            if let Some(value) = value {
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(format!("Reverb: {} must be between 0.0 and 1.0", name));
+                }
+            }
+        }
+        if let Some(predelay_ms) = self.predelay_ms {
+            if predelay_ms < 0.0 {
+                return Err(String::from("Reverb: predelay_ms must not be negative"));
+            }
+        }
+        Ok(())
+    }
+
+    fn predelay_buffer(&self) -> Vec<f32> {
+        let render_config = self.render_config.unwrap_or_default();
+        let predelay_ms = self.predelay_ms.unwrap_or(DEFAULT_PREDELAY_MS);
+        vec![0.0; render_config.ms_to_samples(predelay_ms)]
+    }
+
+    fn channel(&self, spread: usize) -> ReverbChannel {
+        ReverbChannel::new(spread, &self.render_config.unwrap_or_default())
+    }
+}
+
+#[allow(dead_code)]
+impl Reverb {
+    pub(crate) fn apply_effect(&mut self, frame: StereoFrame) -> StereoFrame {
+        let mut input = (frame.left + frame.right) * INPUT_GAIN;
+        if !self.predelay_buffer.is_empty() {
+            input = std::mem::replace(&mut self.predelay_buffer[self.predelay_index], input);
+            self.predelay_index = (self.predelay_index + 1) % self.predelay_buffer.len();
+        }
+
+        let feedback = self.feedback();
+        let damping = self.damping * DAMPING_SCALE;
+        let left = self.left.process(input, feedback, damping);
+        let right = self.right.process(input, feedback, damping);
+
+        // width crosses less of each channel's tail into the other
+        let wet = self.mix * WET_SCALE;
+        let wet_same = wet * (self.width / 2.0 + 0.5);
+        let wet_cross = wet * (1.0 - self.width) / 2.0;
+        let dry = 1.0 - self.mix;
+        StereoFrame::new(
+            left * wet_same + right * wet_cross + frame.left * dry,
+            right * wet_same + left * wet_cross + frame.right * dry,
+        )
+    }
+
+    // Samples the tail rings on for after the input stops, until it dies away to TAIL_LEVEL.
+    // Each pass round the longest comb scales it by the feedback, and damping only shortens it
+    pub(crate) fn tail_samples(&self) -> usize {
+        if self.mix == 0.0 {
+            return 0;
+        }
+        let num_passes = (TAIL_LEVEL.ln() / self.feedback().ln()).ceil() as usize;
+        let longest_comb = self.right.combs.iter()
+            .map(|comb| comb.buffer.len())
+            .max().unwrap_or(0);
+        let all_passes: usize = self.right.all_passes.iter()
+            .map(|all_pass| all_pass.buffer.len())
+            .sum();
+        self.predelay_buffer.len() + longest_comb * num_passes + all_passes
+    }
+
+    fn feedback(&self) -> f32 {
+        self.room_size * ROOM_SCALE + ROOM_OFFSET
+    }
+}
+
+#[allow(dead_code)]
+pub(crate) fn default_reverb() -> Reverb {
+    ReverbBuilder::default().build().unwrap()
+}
+
+#[cfg(test)]
+mod test_reverb {
+    use crate::common::constants::TAIL_LEVEL;
+    use crate::common::render_config::RenderConfigBuilder;
+    use crate::common::stereo::StereoFrame;
+    use crate::effect::reverb::{default_reverb, ReverbBuilder, COMB_TUNINGS};
+
+    // The reverb's response to a single click, one second long
+    fn impulse_response(room_size: f32, damping: f32, predelay_ms: f32, width: f32)
+        -> Vec<StereoFrame> {
+        let mut reverb = ReverbBuilder::default()
+            .room_size(room_size)
+            .damping(damping)
+            .predelay_ms(predelay_ms)
+            .width(width)
+            .mix(1.0)
+            .build().unwrap();
+        (0..44100)
+            .map(|i| reverb.apply_effect(StereoFrame::from_mono(if i == 0 { 1.0 } else { 0.0 })))
+            .collect()
+    }
+
+    fn energy(frames: &[StereoFrame]) -> f32 {
+        frames.iter().map(|frame| frame.left * frame.left + frame.right * frame.right).sum()
+    }
+
+    #[test]
+    fn test_impulse_response() {
+        let frames = impulse_response(0.5, 0.5, 0.0, 1.0);
+        // all wet, so silent until the shortest comb comes round
+        assert!(frames[..COMB_TUNINGS[0]].iter().all(|frame| *frame == StereoFrame::default()));
+        assert!(energy(&frames[COMB_TUNINGS[0]..4410]) > 0.0);
+        // the tail decays
+        assert!(energy(&frames[30870..]) < 0.01 * energy(&frames[..4410]));
+        assert!(frames.iter().all(|frame| frame.left.is_finite() && frame.left.abs() < 1.0));
+
+        // a bigger room rings for longer
+        let big_room = impulse_response(0.95, 0.5, 0.0, 1.0);
+        assert!(energy(&big_room[22050..]) > energy(&frames[22050..]));
+
+        // damping takes the edge off the tail, so it changes less from sample to sample
+        let roughness = |frames: &[StereoFrame]| -> f32 {
+            frames.windows(2).map(|pair| (pair[1].left - pair[0].left).abs()).sum::<f32>() /
+                frames.iter().map(|frame| frame.left.abs()).sum::<f32>()
+        };
+        let damped = impulse_response(0.5, 1.0, 0.0, 1.0);
+        assert!(roughness(&damped[4410..22050]) < roughness(&frames[4410..22050]));
+    }
+
+    #[test]
+    fn test_predelay_and_width() {
+        let frames = impulse_response(0.5, 0.5, 10.0, 1.0);
+        let predelay_samples = 441;
+        let first_sound = frames.iter().position(|frame| frame.left != 0.0).unwrap();
+        assert_eq!(first_sound, predelay_samples + COMB_TUNINGS[0]);
+
+        // the channels differ at full width and are the same with none
+        assert!(frames.iter().any(|frame| frame.left != frame.right));
+        let mono = impulse_response(0.5, 0.5, 10.0, 0.0);
+        assert!(mono.iter().all(|frame| (frame.left - frame.right).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_dry_and_sample_rate() {
+        let mut reverb = ReverbBuilder::default().mix(0.0).build().unwrap();
+        let frame = StereoFrame::new(0.5, -0.25);
+        assert_eq!(reverb.apply_effect(frame), frame);
+
+        // the filters are scaled so the reverb sounds the same at another rate
+        let render_config = RenderConfigBuilder::default().sample_rate(88200.0).build().unwrap();
+        let mut reverb = ReverbBuilder::default()
+            .mix(1.0)
+            .render_config(render_config)
+            .build().unwrap();
+        let first_sound = (0..88200)
+            .map(|i| reverb.apply_effect(StereoFrame::from_mono(if i == 0 { 1.0 } else { 0.0 })))
+            .position(|frame| frame.left != 0.0).unwrap();
+        assert_eq!(first_sound, 2 * COMB_TUNINGS[0]);
+    }
+
+    #[test]
+    fn test_tail_samples() {
+        // the tail has died away by the time the reverb says it has, but not long before
+        let mut reverb = ReverbBuilder::default().mix(1.0).build().unwrap();
+        let tail_samples = reverb.tail_samples();
+        let frames: Vec<StereoFrame> = (0..tail_samples + 4410)
+            .map(|i| reverb.apply_effect(StereoFrame::from_mono(if i == 0 { 1.0 } else { 0.0 })))
+            .collect();
+        let peak = |frames: &[StereoFrame]| frames.iter()
+            .map(|frame| frame.left.abs().max(frame.right.abs()))
+            .fold(0.0, f32::max);
+        assert!(peak(&frames[tail_samples..]) < TAIL_LEVEL * peak(&frames));
+        assert!(peak(&frames[tail_samples / 4..]) > TAIL_LEVEL * peak(&frames));
+
+        // a bigger room rings for longer, and a dry reverb has no tail
+        let big_room = ReverbBuilder::default().room_size(0.9).build().unwrap();
+        assert!(big_room.tail_samples() > default_reverb().tail_samples());
+        assert_eq!(ReverbBuilder::default().mix(0.0).build().unwrap().tail_samples(), 0);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(ReverbBuilder::default().room_size(1.5).build().is_err());
+        assert!(ReverbBuilder::default().mix(-0.1).build().is_err());
+        assert!(ReverbBuilder::default().predelay_ms(-1.0).build().is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use derive_builder::Builder;
+
+use crate::envelope::envelope::{default_envelope, Envelope};
+
+// An ADSR envelope used as a modulation source rather than to scale a note's volume. Its shape,
+// from 0.0 to 1.0 over the note, is scaled by depth, in the units of the parameter it modulates:
+// semitones for pitch and octaves for a filter's cutoff
+#[allow(dead_code)]
+#[derive(Builder, Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ModulationEnvelope {
+    #[builder(default = "default_envelope()")]
+    pub(crate) envelope: Envelope,
+
+    // offset at the envelope's peak, negative to sweep down
+    #[builder(default = "0.0")]
+    pub(crate) depth: f32,
+}
+
+#[allow(dead_code)]
+impl ModulationEnvelope {
+    // Offset for the parameter at position through the note
+    pub(crate) fn modulation(&self, position: f32) -> f32 {
+        self.depth * self.envelope.volume_factor(position)
+    }
+}
+
+// Sum of the offsets of whichever envelopes are set, 0.0 if none are
+#[allow(dead_code)]
+pub(crate) fn total_modulation(envelopes: &[Option<ModulationEnvelope>], position: f32) -> f32 {
+    envelopes.iter()
+        .flatten()
+        .map(|envelope| envelope.modulation(position))
+        .sum()
+}
+
+// Frequency ratio for an offset in semitones
+#[allow(dead_code)]
+pub(crate) fn semitones_to_ratio(semitones: f32) -> f32 {
+    2.0f32.powf(semitones / 12.0)
+}
+
+#[cfg(test)]
+mod test_modulation_envelope {
+    use crate::common::float_utils::assert_float_eq;
+    use crate::envelope::envelope::EnvelopeBuilder;
+    use crate::envelope::envelope_pair::EnvelopePair;
+    use crate::envelope::modulation_envelope::{semitones_to_ratio, total_modulation,
+                                               ModulationEnvelopeBuilder};
+
+    #[test]
+    fn test_modulation() {
+        // starts at full depth, falls to half by a fifth of the note and holds there
+        let envelope = EnvelopeBuilder::default()
+            .attack(EnvelopePair(0.0, 1.0))
+            .decay(EnvelopePair(0.2, 0.5))
+            .sustain(EnvelopePair(0.8, 0.5))
+            .build().unwrap();
+        let pitch_envelope = ModulationEnvelopeBuilder::default()
+            .envelope(envelope)
+            .depth(-24.0)
+            .build().unwrap();
+        assert_float_eq(pitch_envelope.modulation(0.0), -24.0);
+        assert_float_eq(pitch_envelope.modulation(0.1), -18.0);
+        assert_float_eq(pitch_envelope.modulation(0.2), -12.0);
+        assert_float_eq(pitch_envelope.modulation(0.5), -12.0);
+        assert_float_eq(pitch_envelope.modulation(0.9), -6.0);
+        assert_float_eq(pitch_envelope.modulation(1.0), 0.0);
+
+        assert_float_eq(total_modulation(&[Some(pitch_envelope), None, Some(pitch_envelope)],
+                                         0.2), -24.0);
+        assert_float_eq(total_modulation(&[None], 0.2), 0.0);
+        assert_float_eq(semitones_to_ratio(-12.0), 0.5);
+        assert_float_eq(semitones_to_ratio(24.0), 4.0);
+    }
+}
@@ -22,7 +22,7 @@ pub(crate) enum DurationType {
 }
 
 impl DurationType {
-    pub(crate) fn to_factor(&self) -> f32 {
+    pub(crate) fn to_factor(self) -> f32 {
         match self {
             DurationType::Whole => WHOLE,
             DurationType::Half => HALF,
@@ -3,7 +3,9 @@ use std::collections::HashMap;
 use nodi::midly;
 use nodi::midly::num::{u28, u4, u7, u15};
 
+use crate::note::additive_note::AdditiveNoteBuilder;
 use crate::note::constants;
+use crate::note::fm_note::FMNoteBuilder;
 use crate::note::note::NoteBuilder;
 use crate::note::playback_note::{NoteType, PlaybackNote, PlaybackNoteBuilder};
 use crate::note::sampled_note::SampledNoteBuilder;
@@ -48,104 +50,58 @@ pub(crate) fn midi_file_to_tracks<
     let mut ticks_since_start: u28 = u28::from(0);
     for track in midi.tracks.iter() {
         for event in track.iter() {
-            match event {
-                // delta is the number of ticks since the last Midi event
-                midly::TrackEvent { delta, kind} => {
-                    ticks_since_start += *delta;
-
-                    match kind {
-                        midly::TrackEventKind::Midi { channel, message } => {
-                            match message {
-                                // 'key' is midi pitch 1..127
-                                midly::MidiMessage::NoteOn { key, vel } => {
-                                    let note_key = NoteKey {channel: *channel, pitch: *key};
-
-                                    if *vel > u7::from(0) {
-                                        // If we have never seen the channel before, init the state
-                                        // of the map being used to collect events into sequences
-                                        if !track_sequence_map.contains_key(channel) {
-                                            track_sequence_map.insert(*channel,
-                                                                      SequenceBuilderType::new());
-                                        }
-                                        // Update the current note for this channel.
-                                        // - Capture the velocity from the NoteOn event, should
-                                        //  be a value > 0 if it's a note meant to be heard
-                                        // - Set the duration to 0 to start
-                                        // - Capture the start time in ticks converted to msecs
-
-                                        // TODO THIS SHOULD CLOSE THE OPEN NOTE AND OPEN A NEW ONE
-                                        // Handle case of existing open note with same key by
-                                        //  skipping this note if it is a duplicate
-                                        if !track_notes_map.contains_key(&note_key) {
-                                            let note_start_time_ms =
-                                                ticks_since_start.as_int() as f32 / ticks_per_ms;
-                                            match note_type {
-                                                NoteType::Oscillator => { 
-                                                    let note =
-                                                        NoteBuilder::default().
-                                                            frequency(
-                                                                constants::PITCH_TO_FREQ_HZ[key.as_int() as usize] as f32)
-                                                            .volume(vel.as_int() as f32 / 127.0f32)
-                                                            .start_time_ms(note_start_time_ms)
-                                                            .end_time_ms(note_start_time_ms)
-                                                            .build().unwrap();
-
-                                                    track_notes_map.insert(
-                                                       note_key,
-                                                       PlaybackNoteBuilder::default()
-                                                           .note_type(note_type)
-                                                           .note(note)
-                                                           .playback_start_time_ms(note_start_time_ms)
-                                                           .playback_end_time_ms(note_start_time_ms)
-                                                           .build().unwrap());
-                                                }
-                                                NoteType::Sample => {
-                                                    let sampled_note =
-                                                        SampledNoteBuilder::default()
-                                                            .volume(vel.as_int() as f32 / 127.0f32)
-                                                            .start_time_ms(note_start_time_ms)
-                                                            .end_time_ms(note_start_time_ms)
-                                                            .build().unwrap();
-                                                    track_notes_map.insert(
-                                                        note_key,
-                                                        PlaybackNoteBuilder::default()
-                                                            .note_type(note_type)
-                                                            .sampled_note(sampled_note)
-                                                            .playback_start_time_ms(note_start_time_ms)
-                                                            .playback_end_time_ms(note_start_time_ms)
-                                                            .build().unwrap());
-                                                    
-                                                }
-                                            }
-                                        }
-                                        // 0 volume for a note we got the start of previously
-                                    } else if track_sequence_map.contains_key(channel) &&
-                                        track_notes_map.contains_key(&note_key) {
-                                        let ms_since_start =
-                                            ticks_since_start.as_int() as f32 / ticks_per_ms;
-                                        handle_note_off(note_key,
-                                                        ms_since_start,
-                                                        &mut track_notes_map,
-                                                        &mut track_sequence_map);
-                                    }
-                                }
-
-                                #[allow(unused_variables)]
-                                midly::MidiMessage::NoteOff { key, vel } => {
-                                    let note_key = NoteKey {channel: *channel, pitch: *key};
-                                    let ms_since_start =
-                                        ticks_since_start.as_int() as f32 / ticks_per_ms;
-                                    handle_note_off(note_key,
-                                                    ms_since_start,
-                                                    &mut track_notes_map,
-                                                    &mut track_sequence_map);
-                                }
-
-                                _ => {}
-                            }
+            // delta is the number of ticks since the last Midi event
+            let midly::TrackEvent { delta, kind } = event;
+            ticks_since_start += *delta;
+
+            if let midly::TrackEventKind::Midi { channel, message } = kind {
+                match message {
+                    // 'key' is midi pitch 1..127
+                    midly::MidiMessage::NoteOn { key, vel } => {
+                        let note_key = NoteKey {channel: *channel, pitch: *key};
+
+                        if *vel > u7::from(0) {
+                            // If we have never seen the channel before, init the state
+                            // of the map being used to collect events into sequences
+                            track_sequence_map.entry(*channel).or_insert_with(SequenceBuilderType::new);
+                            // Update the current note for this channel.
+                            // - Capture the velocity from the NoteOn event, should
+                            //  be a value > 0 if it's a note meant to be heard
+                            // - Set the duration to 0 to start
+                            // - Capture the start time in ticks converted to msecs
+
+                            // TODO THIS SHOULD CLOSE THE OPEN NOTE AND OPEN A NEW ONE
+                            // Handle case of existing open note with same key by
+                            //  skipping this note if it is a duplicate
+                            track_notes_map.entry(note_key).or_insert_with(|| {
+                                let note_start_time_ms =
+                                    ticks_since_start.as_int() as f32 / ticks_per_ms;
+                                build_playback_note(note_type, *key, *vel, note_start_time_ms)
+                            });
+                            // 0 volume for a note we got the start of previously
+                        } else if track_sequence_map.contains_key(channel) &&
+                            track_notes_map.contains_key(&note_key) {
+                            let ms_since_start =
+                                ticks_since_start.as_int() as f32 / ticks_per_ms;
+                            handle_note_off(note_key,
+                                            ms_since_start,
+                                            &mut track_notes_map,
+                                            &mut track_sequence_map);
                         }
-                        _ => {}
                     }
+
+                    #[allow(unused_variables)]
+                    midly::MidiMessage::NoteOff { key, vel } => {
+                        let note_key = NoteKey {channel: *channel, pitch: *key};
+                        let ms_since_start =
+                            ticks_since_start.as_int() as f32 / ticks_per_ms;
+                        handle_note_off(note_key,
+                                        ms_since_start,
+                                        &mut track_notes_map,
+                                        &mut track_sequence_map);
+                    }
+
+                    _ => {}
                 }
             }
         }
@@ -164,27 +120,64 @@ pub(crate) fn midi_file_to_tracks<
     tracks
 }
 
+// Builds the open note for a NoteOn event, with the duration set to 0 until its NoteOff arrives
+#[allow(dead_code)]
+fn build_playback_note(note_type: NoteType, key: u7, vel: u7, note_start_time_ms: f32) -> PlaybackNote {
+    let frequency = constants::PITCH_TO_FREQ_HZ[key.as_int() as usize] as f32;
+    let volume = vel.as_int() as f32 / 127.0f32;
+    let mut playback_note_builder = PlaybackNoteBuilder::default();
+    playback_note_builder
+        .note_type(note_type)
+        .playback_start_time_ms(note_start_time_ms)
+        .playback_end_time_ms(note_start_time_ms);
+    match note_type {
+        NoteType::Oscillator => {
+            playback_note_builder.note(
+                NoteBuilder::default()
+                    .frequency(frequency)
+                    .volume(volume)
+                    .start_time_ms(note_start_time_ms)
+                    .end_time_ms(note_start_time_ms)
+                    .build().unwrap());
+        }
+        NoteType::Sample => {
+            playback_note_builder.sampled_note(
+                SampledNoteBuilder::default()
+                    .volume(volume)
+                    .start_time_ms(note_start_time_ms)
+                    .end_time_ms(note_start_time_ms)
+                    .build().unwrap());
+        }
+        NoteType::FM => {
+            playback_note_builder.fm_note(
+                FMNoteBuilder::default()
+                    .frequency(frequency)
+                    .volume(volume)
+                    .start_time_ms(note_start_time_ms)
+                    .end_time_ms(note_start_time_ms)
+                    .build().unwrap());
+        }
+        NoteType::Additive => {
+            playback_note_builder.additive_note(
+                AdditiveNoteBuilder::default()
+                    .frequency(frequency)
+                    .volume(volume)
+                    .start_time_ms(note_start_time_ms)
+                    .end_time_ms(note_start_time_ms)
+                    .build().unwrap());
+        }
+    }
+    playback_note_builder.build().unwrap()
+}
+
 #[allow(dead_code)]
 pub(crate) fn get_beats_per_minute(midi: &midly::Smf) -> u8 {
     for track in midi.tracks.iter() {
         for event in track.iter() {
-            match event {
-                #[allow(unused_variables)]
-                midly::TrackEvent { delta, kind } => {
-                    match kind {
-                        midly::TrackEventKind::Meta(meta) => {
-                            match meta {
-                                midly::MetaMessage::Tempo(tempo) => {
-                                    let normalized_tempo=
-                                        if tempo.as_int() > 0 {tempo.as_int()} else {1};
-                                    return (60000000 / normalized_tempo) as u8;
-                                }
-                                _ => {}
-                            }
-                        }
-                        _ => {}
-                    }
-                }
+            if let midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) = event.kind {
+                let normalized_tempo=
+                    if tempo.as_int() > 0 {tempo.as_int()} else {1};
+                return (60000000 / normalized_tempo) as u8;
             }
         }
     }
@@ -196,9 +189,7 @@ pub(crate) fn get_ticks_per_beat(midi: &midly::Smf) -> u15 {
     let header = midi.header;
 
     match header.timing {
-        midly::Timing::Metrical(ticks_per_beat) => {
-            return ticks_per_beat;
-        },
+        midly::Timing::Metrical(ticks_per_beat) => ticks_per_beat,
         _ => {
             panic!("Only Metrical timing is supported");
         }
@@ -0,0 +1,184 @@
+use derive_builder::Builder;
+
+use crate::audio_gen::oscillator::{OscillatorTables, PhaseAccumulator};
+use crate::envelope::envelope::Envelope;
+use crate::note::constants::{DEFAULT_FREQUENCY, DEFAULT_VOLUME, INIT_START_TIME};
+
+#[allow(dead_code)]
+#[derive(Builder, Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Partial {
+    // frequency as a multiple of the note frequency, which need not be a whole number
+    #[builder(default = "1.0")]
+    pub(crate) ratio: f32,
+
+    #[builder(default = "1.0")]
+    pub(crate) amplitude: f32,
+
+    // scales the amplitude over the course of the note
+    #[builder(default = "None")]
+    pub(crate) envelope: Option<Envelope>,
+}
+
+// A voice that is the sum of sine partials. Amplitudes aren't normalized, so that analyzed
+// spectra resynthesize at their original level
+#[allow(dead_code)]
+#[derive(Builder, Clone, Debug, PartialEq)]
+pub(crate) struct AdditiveNote {
+    #[builder(default = "DEFAULT_FREQUENCY")]
+    pub(crate) frequency: f32,
+
+    #[builder(default = "DEFAULT_VOLUME")]
+    pub(crate) volume: f32,
+
+    #[builder(default = "INIT_START_TIME")]
+    pub(crate) start_time_ms: f32,
+
+    #[builder(default = "INIT_START_TIME")]
+    pub(crate) end_time_ms: f32,
+
+    #[builder(default = "vec![default_partial()]")]
+    pub(crate) partials: Vec<Partial>,
+
+    // samples rendered since the start of the note, which positions the partial envelopes
+    #[builder(default = "0", setter(skip))]
+    pub(crate) sample_index: usize,
+
+    // one per partial, created on the first sample
+    #[builder(default = "Vec::new()", setter(skip))]
+    phases: Vec<PhaseAccumulator>,
+}
+
+#[allow(dead_code)]
+pub(crate) fn default_partial() -> Partial {
+    PartialBuilder::default().build().unwrap()
+}
+
+#[allow(dead_code)]
+pub(crate) fn default_additive_note() -> AdditiveNote {
+    AdditiveNoteBuilder::default().build().unwrap()
+}
+
+// Partials at whole number ratios 1, 2, 3, ... with the given amplitudes
+#[allow(dead_code)]
+pub(crate) fn harmonic_partials(amplitudes: &[f32]) -> Vec<Partial> {
+    amplitudes.iter().enumerate()
+        .map(|(i, amplitude)| PartialBuilder::default()
+            .ratio((i + 1) as f32)
+            .amplitude(*amplitude)
+            .build().unwrap())
+        .collect()
+}
+
+#[allow(dead_code)]
+impl AdditiveNote {
+    pub(crate) fn duration_ms(&self) -> f32 {
+        self.end_time_ms - self.start_time_ms
+    }
+
+    // Moves the voice elapsed_ms into the note, for a note that starts playing partway through
+    pub(crate) fn set_elapsed_ms(&mut self, elapsed_ms: f32, samples_per_ms: f32) {
+        self.sample_index = (elapsed_ms.max(0.0) * samples_per_ms) as usize;
+        self.phases = self.partials.iter()
+            .map(|partial| PhaseAccumulator::at_elapsed_ms(self.frequency * partial.ratio,
+                                                           elapsed_ms))
+            .collect();
+    }
+
+    // Next sample of the voice, before volume is applied. Partials at or above the Nyquist
+    // frequency are skipped, since they would alias
+    pub(crate) fn next_sample(&mut self, oscillator_tables: &OscillatorTables) -> f32 {
+        if self.phases.len() != self.partials.len() {
+            self.phases = vec![PhaseAccumulator::default(); self.partials.len()];
+        }
+
+        let sample_rate = oscillator_tables.sample_rate();
+        let nyquist_frequency = oscillator_tables.render_config.nyquist_frequency();
+        let position = self.sample_index as f32 / (self.duration_ms() * sample_rate / 1000.0);
+        let mut output = 0.0;
+        for (partial, phase) in self.partials.iter().zip(self.phases.iter_mut()) {
+            let frequency = self.frequency * partial.ratio;
+            let phase = phase.next_phase(frequency, sample_rate);
+            if frequency >= nyquist_frequency {
+                continue;
+            }
+            let amplitude = partial.amplitude * partial.envelope
+                .map_or(1.0, |envelope| envelope.volume_factor(position));
+            output += amplitude * oscillator_tables.get_sine_sample(phase);
+        }
+        self.sample_index += 1;
+        output
+    }
+}
+
+#[cfg(test)]
+mod test_additive_note {
+    use crate::audio_gen::oscillator::OscillatorTables;
+    use crate::envelope::envelope::EnvelopeBuilder;
+    use crate::envelope::envelope_pair::EnvelopePair;
+    use crate::note::additive_note::{harmonic_partials, AdditiveNoteBuilder, PartialBuilder};
+
+    fn sine(phase: f32) -> f32 {
+        (2.0 * std::f32::consts::PI * phase).sin()
+    }
+
+    #[test]
+    fn test_sums_partials() {
+        let oscillator_tables = OscillatorTables::new();
+        let mut additive_note = AdditiveNoteBuilder::default()
+            .frequency(441.0)
+            .end_time_ms(1000.0)
+            .partials(harmonic_partials(&[1.0, 0.5, 0.25]))
+            .build().unwrap();
+        let samples: Vec<f32> = (0..100)
+            .map(|_| additive_note.next_sample(&oscillator_tables)).collect();
+        // 100 samples per cycle at 441 Hz
+        for i in [10, 37, 80] {
+            let phase = i as f32 / 100.0;
+            let expected = sine(phase) + 0.5 * sine(2.0 * phase) + 0.25 * sine(3.0 * phase);
+            assert!((samples[i] - expected).abs() < 1e-3, "{} != {}", samples[i], expected);
+        }
+    }
+
+    #[test]
+    fn test_skips_partials_above_nyquist() {
+        let oscillator_tables = OscillatorTables::new();
+        let partial = PartialBuilder::default().ratio(60.0).build().unwrap();
+        let mut additive_note = AdditiveNoteBuilder::default()
+            .frequency(441.0)
+            .end_time_ms(1000.0)
+            .partials(vec![partial])
+            .build().unwrap();
+        assert!((0..100).all(|_| additive_note.next_sample(&oscillator_tables) == 0.0));
+    }
+
+    #[test]
+    fn test_partial_envelopes() {
+        let oscillator_tables = OscillatorTables::new();
+        // the fundamental fades out over the note while the second partial fades in
+        let fade_out = EnvelopeBuilder::default()
+            .attack(EnvelopePair(0.0, 1.0))
+            .decay(EnvelopePair(1.0, 0.0))
+            .sustain(EnvelopePair(1.0, 0.0))
+            .build().unwrap();
+        let fade_in = EnvelopeBuilder::default()
+            .attack(EnvelopePair(1.0, 1.0))
+            .decay(EnvelopePair(1.0, 1.0))
+            .sustain(EnvelopePair(1.0, 1.0))
+            .build().unwrap();
+        let mut additive_note = AdditiveNoteBuilder::default()
+            .frequency(441.0)
+            .end_time_ms(100.0 / 44.1)
+            .partials(vec![
+                PartialBuilder::default().envelope(Some(fade_out)).build().unwrap(),
+                PartialBuilder::default().ratio(2.0).envelope(Some(fade_in)).build().unwrap(),
+            ])
+            .build().unwrap();
+        let samples: Vec<f32> = (0..100)
+            .map(|_| additive_note.next_sample(&oscillator_tables)).collect();
+        for i in [10, 60, 90] {
+            let phase = i as f32 / 100.0;
+            let expected = (1.0 - phase) * sine(phase) + phase * sine(2.0 * phase);
+            assert!((samples[i] - expected).abs() < 1e-3, "{} != {}", samples[i], expected);
+        }
+    }
+}
@@ -8,6 +8,7 @@ pub(crate) static DEFAULT_FREQUENCY: f32 = 440.0;
 #[allow(dead_code)]
 pub(crate) static DEFAULT_DURATION: f32 = 0.0; // actually, it is
 pub(crate) static DEFAULT_VOLUME: f32 = 1.0;
+pub(crate) static DEFAULT_PAN: f32 = 0.0;
 
 // Array mapping index positions in piano / MIDI Western scale, with A4 = 440.0 Hz, to frequencies
 pub(crate) static PITCH_TO_FREQ_HZ: [f64; 128] = [
@@ -0,0 +1,231 @@
+use derive_builder::Builder;
+
+use crate::audio_gen::oscillator::{OscillatorTables, PhaseAccumulator};
+use crate::envelope::envelope::Envelope;
+use crate::note::constants::{DEFAULT_FREQUENCY, DEFAULT_VOLUME, INIT_START_TIME};
+
+static TWO_PI: f32 = 2.0 * std::f32::consts::PI;
+
+// How operators are routed. Operator 0 is always a carrier, heard in the output, and operators
+// are only modulated by higher numbered operators, so each sample is computed from the top down
+// Stack: each operator modulates the one below it, 3 -> 2 -> 1 -> 0
+// Parallel: every operator is a carrier, with no modulation, like an additive organ
+// Pairs: each odd operator modulates the even operator below it, 1 -> 0 and 3 -> 2
+// Branch: every other operator modulates operator 0
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum FMAlgorithm {
+    #[default]
+    Stack,
+    Parallel,
+    Pairs,
+    Branch,
+}
+
+impl FMAlgorithm {
+    // Operator that operator modulates, or None if it is a carrier
+    pub(crate) fn target(&self, operator: usize) -> Option<usize> {
+        match self {
+            _ if operator == 0 => None,
+            FMAlgorithm::Stack => Some(operator - 1),
+            FMAlgorithm::Parallel => None,
+            FMAlgorithm::Pairs if operator % 2 == 1 => Some(operator - 1),
+            FMAlgorithm::Pairs => None,
+            FMAlgorithm::Branch => Some(0),
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Builder, Clone, Copy, Debug, PartialEq)]
+pub(crate) struct FMOperator {
+    // frequency as a multiple of the note frequency
+    #[builder(default = "1.0")]
+    pub(crate) ratio: f32,
+
+    // a carrier's output amplitude, or a modulator's modulation index, the peak phase shift in
+    // radians it applies to the operator it modulates
+    #[builder(default = "1.0")]
+    pub(crate) level: f32,
+
+    // scales the level over the course of the note
+    #[builder(default = "None")]
+    pub(crate) envelope: Option<Envelope>,
+}
+
+// A voice of sine operators that phase modulate each other, routed by an FMAlgorithm
+#[allow(dead_code)]
+#[derive(Builder, Clone, Debug, PartialEq)]
+pub(crate) struct FMNote {
+    #[builder(default = "DEFAULT_FREQUENCY")]
+    pub(crate) frequency: f32,
+
+    #[builder(default = "DEFAULT_VOLUME")]
+    pub(crate) volume: f32,
+
+    #[builder(default = "INIT_START_TIME")]
+    pub(crate) start_time_ms: f32,
+
+    #[builder(default = "INIT_START_TIME")]
+    pub(crate) end_time_ms: f32,
+
+    #[builder(default = "vec![default_fm_operator()]")]
+    pub(crate) operators: Vec<FMOperator>,
+
+    #[builder(default = "FMAlgorithm::default()")]
+    pub(crate) algorithm: FMAlgorithm,
+
+    // samples rendered since the start of the note, which positions the operator envelopes
+    #[builder(default = "0", setter(skip))]
+    pub(crate) sample_index: usize,
+
+    // one per operator, created on the first sample
+    #[builder(default = "Vec::new()", setter(skip))]
+    phases: Vec<PhaseAccumulator>,
+
+    // modulation summed into each operator for the current sample
+    #[builder(default = "Vec::new()", setter(skip))]
+    modulation: Vec<f32>,
+}
+
+#[allow(dead_code)]
+pub(crate) fn default_fm_operator() -> FMOperator {
+    FMOperatorBuilder::default().build().unwrap()
+}
+
+#[allow(dead_code)]
+pub(crate) fn default_fm_note() -> FMNote {
+    FMNoteBuilder::default().build().unwrap()
+}
+
+#[allow(dead_code)]
+impl FMNote {
+    pub(crate) fn duration_ms(&self) -> f32 {
+        self.end_time_ms - self.start_time_ms
+    }
+
+    // Moves the voice elapsed_ms into the note, for a note that starts playing partway through
+    pub(crate) fn set_elapsed_ms(&mut self, elapsed_ms: f32, samples_per_ms: f32) {
+        self.sample_index = (elapsed_ms.max(0.0) * samples_per_ms) as usize;
+        self.phases = self.operators.iter()
+            .map(|operator| PhaseAccumulator::at_elapsed_ms(self.frequency * operator.ratio,
+                                                            elapsed_ms))
+            .collect();
+    }
+
+    // Next sample of the voice, before volume is applied. Carriers are averaged, so the output
+    // stays within -1.0 to 1.0 however many there are
+    pub(crate) fn next_sample(&mut self, oscillator_tables: &OscillatorTables) -> f32 {
+        let num_operators = self.operators.len();
+        if self.phases.len() != num_operators {
+            self.phases = vec![PhaseAccumulator::default(); num_operators];
+        }
+        self.modulation.clear();
+        self.modulation.resize(num_operators, 0.0);
+
+        let sample_rate = oscillator_tables.sample_rate();
+        let position = self.sample_index as f32 / (self.duration_ms() * sample_rate / 1000.0);
+        let mut output = 0.0;
+        let mut num_carriers = 0;
+        for index in (0..num_operators).rev() {
+            let operator = self.operators[index];
+            let frequency = self.frequency * operator.ratio;
+            let phase = self.phases[index].next_phase(frequency, sample_rate) +
+                (self.modulation[index] / TWO_PI) as f64;
+            let level = operator.level * operator.envelope
+                .map_or(1.0, |envelope| envelope.volume_factor(position));
+            let operator_output = level * oscillator_tables.get_sine_sample(phase);
+
+            match self.algorithm.target(index) {
+                Some(target) => self.modulation[target] += operator_output,
+                None => {
+                    output += operator_output;
+                    num_carriers += 1;
+                }
+            }
+        }
+        self.sample_index += 1;
+        output / num_carriers.max(1) as f32
+    }
+}
+
+#[cfg(test)]
+mod test_fm_note {
+    use crate::audio_gen::oscillator::OscillatorTables;
+    use crate::envelope::envelope::EnvelopeBuilder;
+    use crate::envelope::envelope_pair::EnvelopePair;
+    use crate::note::fm_note::{FMAlgorithm, FMNoteBuilder, FMOperatorBuilder};
+
+    #[test]
+    fn test_algorithm_targets() {
+        assert_eq!(FMAlgorithm::Stack.target(0), None);
+        assert_eq!(FMAlgorithm::Stack.target(3), Some(2));
+        assert_eq!(FMAlgorithm::Parallel.target(2), None);
+        assert_eq!(FMAlgorithm::Pairs.target(3), Some(2));
+        assert_eq!(FMAlgorithm::Pairs.target(2), None);
+        assert_eq!(FMAlgorithm::Branch.target(3), Some(0));
+    }
+
+    #[test]
+    fn test_single_operator_is_sine() {
+        let oscillator_tables = OscillatorTables::new();
+        let mut fm_note = FMNoteBuilder::default()
+            .frequency(441.0)
+            .end_time_ms(1000.0)
+            .build().unwrap();
+        let samples: Vec<f32> = (0..100).map(|_| fm_note.next_sample(&oscillator_tables))
+            .collect();
+        // 100 samples per cycle at 441 Hz
+        assert_eq!(samples[0], 0.0);
+        assert!((samples[25] - 1.0).abs() < 1e-4);
+        assert!((samples[75] + 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_modulator_changes_carrier() {
+        let oscillator_tables = OscillatorTables::new();
+        let carrier = FMOperatorBuilder::default().build().unwrap();
+        let modulator = FMOperatorBuilder::default().ratio(2.0).level(2.0).build().unwrap();
+        let mut stacked = FMNoteBuilder::default()
+            .frequency(441.0)
+            .end_time_ms(1000.0)
+            .operators(vec![carrier, modulator])
+            .build().unwrap();
+        let mut parallel = stacked.clone();
+        parallel.algorithm = FMAlgorithm::Parallel;
+
+        // Stacked, the carrier is phase modulated, within -1.0 to 1.0, and no longer a sine
+        let stacked_samples: Vec<f32> = (0..100)
+            .map(|_| stacked.next_sample(&oscillator_tables)).collect();
+        assert!(stacked_samples.iter().all(|sample| sample.abs() <= 1.0 + 1e-4));
+        let unmodulated = (2.0 * std::f32::consts::PI * 0.05).sin();
+        assert!((stacked_samples[5] - unmodulated).abs() > 0.5);
+
+        // In parallel both operators are heard and averaged. A quarter cycle in, the carrier is
+        // at its peak and the modulator, at twice the frequency, is crossing zero
+        let parallel_samples: Vec<f32> = (0..100)
+            .map(|_| parallel.next_sample(&oscillator_tables)).collect();
+        assert!((parallel_samples[25] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_operator_envelope() {
+        let oscillator_tables = OscillatorTables::new();
+        // decays from full level at the start of the note to silence at the end
+        let envelope = EnvelopeBuilder::default()
+            .attack(EnvelopePair(0.0, 1.0))
+            .decay(EnvelopePair(1.0, 0.0))
+            .sustain(EnvelopePair(1.0, 0.0))
+            .build().unwrap();
+        let operator = FMOperatorBuilder::default().envelope(Some(envelope)).build().unwrap();
+        let mut fm_note = FMNoteBuilder::default()
+            .frequency(441.0)
+            .end_time_ms(100.0 / 44.1)
+            .operators(vec![operator])
+            .build().unwrap();
+        let samples: Vec<f32> = (0..100).map(|_| fm_note.next_sample(&oscillator_tables))
+            .collect();
+        assert!((samples[25] - 0.75).abs() < 1e-3);
+        assert!((samples[75] + 0.25).abs() < 1e-3);
+    }
+}
@@ -1,8 +1,12 @@
+pub mod additive_note;
 pub mod constants;
+pub mod fm_note;
 pub mod note;
 pub mod note_pool;
+pub mod oscillator_layer;
 pub mod playback_note;
 pub mod sampled_note;
 pub mod scales;
+pub mod unison;
 mod note_trait;
 
@@ -6,6 +6,10 @@ use crate::audio_gen::oscillator::Waveform;
 use crate::common::float_utils::float_eq;
 use crate::note::constants::{DEFAULT_FREQUENCY, DEFAULT_VOLUME, INIT_START_TIME};
 use crate::note::note_trait::BuilderWrapper;
+use crate::note::oscillator_layer;
+use crate::note::oscillator_layer::OscillatorLayer;
+use crate::note::unison;
+use crate::note::unison::Unison;
 
 #[allow(dead_code)]
 #[derive(Builder, Clone, Debug)]
@@ -22,8 +26,21 @@ pub(crate) struct Note {
     #[builder(default = "INIT_START_TIME")]
     pub(crate) end_time_ms: f32,
 
+    // waveforms summed to make the note, each with its own level and pitch offset
     #[builder(default = "Vec::new()")]
-    pub(crate) waveforms: Vec<Waveform>,
+    pub(crate) layers: Vec<OscillatorLayer>,
+
+    #[builder(default = "unison::default_unison()")]
+    pub(crate) unison: Unison,
+}
+
+#[allow(dead_code)]
+impl NoteBuilder {
+    // Layers of each waveform at unity gain and the note's pitch
+    pub(crate) fn waveforms(&mut self, waveforms: Vec<Waveform>) -> &mut Self {
+        self.layers = Some(oscillator_layer::unity_layers(&waveforms));
+        self
+    }
 }
 
 pub(crate) fn default_note() -> Note {
@@ -96,9 +113,9 @@ mod test_note {
             .start_time_ms(0.0)
             .build().unwrap();
 
-        assert_eq!(note.is_playing(0.0), true);
-        assert_eq!(note.is_playing(500.0), true);
-        assert_eq!(note.is_playing(1000.0), false);
+        assert!(note.is_playing(0.0));
+        assert!(note.is_playing(500.0));
+        assert!(!note.is_playing(1000.0));
     }
 
     #[test]
@@ -107,8 +124,8 @@ mod test_note {
             .start_time_ms(0.01)
             .build().unwrap();
 
-        assert_eq!(note.is_before_playing(0.0), true);
-        assert_eq!(note.is_before_playing(0.02), false);
+        assert!(note.is_before_playing(0.0));
+        assert!(!note.is_before_playing(0.02));
     }
 
     #[test]
@@ -117,9 +134,9 @@ mod test_note {
             .start_time_ms(0.0)
             .build().unwrap();
 
-        assert_eq!(note.is_after_playing(0.0), false);
-        assert_eq!(note.is_after_playing(500.0), false);
-        assert_eq!(note.is_after_playing(1000.0), true);
+        assert!(!note.is_after_playing(0.0));
+        assert!(!note.is_after_playing(500.0));
+        assert!(note.is_after_playing(1000.0));
     }
 
     #[test]
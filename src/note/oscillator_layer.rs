@@ -0,0 +1,67 @@
+use derive_builder::Builder;
+
+use crate::audio_gen::oscillator::Waveform;
+
+// One waveform of an oscillator Note, with its own level and pitch and phase offsets from the
+// note, so a patch can balance and stack its waveforms without repeating them
+#[allow(dead_code)]
+#[derive(Builder, Clone, Copy, Debug, PartialEq)]
+pub(crate) struct OscillatorLayer {
+    #[builder(default = "Waveform::Sine")]
+    pub(crate) waveform: Waveform,
+
+    // gain applied to the waveform before the layers are summed
+    #[builder(default = "1.0")]
+    pub(crate) level: f32,
+
+    // pitch offset from the note, added together
+    #[builder(default = "0")]
+    pub(crate) octaves: i32,
+    #[builder(default = "0")]
+    pub(crate) semitones: i32,
+    #[builder(default = "0.0")]
+    pub(crate) cents: f32,
+
+    // fraction of a cycle the waveform starts into its cycle at the start of the note
+    #[builder(default = "0.0")]
+    pub(crate) phase: f32,
+}
+
+#[allow(dead_code)]
+impl OscillatorLayer {
+    // The waveform at unity gain and the note's pitch
+    pub(crate) fn from_waveform(waveform: Waveform) -> OscillatorLayer {
+        OscillatorLayerBuilder::default().waveform(waveform).build().unwrap()
+    }
+
+    // Layer's frequency as a multiple of the note frequency
+    pub(crate) fn frequency_ratio(&self) -> f32 {
+        let semitones = 12 * self.octaves + self.semitones;
+        2.0f32.powf((semitones as f32 + self.cents / 100.0) / 12.0)
+    }
+}
+
+pub(crate) fn unity_layers(waveforms: &[Waveform]) -> Vec<OscillatorLayer> {
+    waveforms.iter().map(|waveform| OscillatorLayer::from_waveform(*waveform)).collect()
+}
+
+#[cfg(test)]
+mod test_oscillator_layer {
+    use crate::audio_gen::oscillator::Waveform;
+    use crate::common::float_utils::assert_float_eq;
+    use crate::note::oscillator_layer::{OscillatorLayer, OscillatorLayerBuilder};
+
+    #[test]
+    fn test_frequency_ratio() {
+        assert_float_eq(OscillatorLayer::from_waveform(Waveform::Sine).frequency_ratio(), 1.0);
+        let octave_down = OscillatorLayerBuilder::default().octaves(-1).build().unwrap();
+        assert_float_eq(octave_down.frequency_ratio(), 0.5);
+        // a fifth up, less a little
+        let fifth = OscillatorLayerBuilder::default()
+            .octaves(1)
+            .semitones(7)
+            .cents(-10.0)
+            .build().unwrap();
+        assert!((fifth.frequency_ratio() - 2.0 * 2.0f32.powf(6.9 / 12.0)).abs() < 1e-5);
+    }
+}
@@ -1,12 +1,28 @@
+use std::sync::Arc;
+
 use derive_builder::Builder;
+use crate::audio_gen::noise::NoiseGenerator;
+use crate::audio_gen::oscillator::{PhaseAccumulator, Waveform};
+use crate::audio_gen::wavetable::{get_wavetable, Wavetable, WavetableId};
+use crate::common::stereo::{PanLaw, StereoFrame};
+use crate::effect::convolution::Convolution;
 use crate::effect::delay::Delay;
+use crate::effect::filter::Filter;
+use crate::effect::reverb::Reverb;
 use crate::envelope::envelope::Envelope;
+use crate::envelope::modulation_envelope::{semitones_to_ratio, total_modulation,
+                                           ModulationEnvelope};
 use crate::effect::flanger::Flanger;
 use crate::effect::lfo::LFO;
+use crate::note::additive_note;
+use crate::note::additive_note::AdditiveNote;
 use crate::note::constants;
+use crate::note::fm_note;
+use crate::note::fm_note::FMNote;
 use crate::note::note;
 use crate::note::note::Note;
 use crate::note::note_trait::BuilderWrapper;
+use crate::note::oscillator_layer::OscillatorLayer;
 use crate::note::sampled_note;
 use crate::note::sampled_note::SampledNote;
 use crate::track::track_effects::{no_op_effects, TrackEffects};
@@ -16,6 +32,8 @@ use crate::track::track_effects::{no_op_effects, TrackEffects};
 pub (crate) enum NoteType {
     Oscillator,
     Sample,
+    FM,
+    Additive,
 }
 
 #[derive(Builder, Clone, Debug, PartialEq)]
@@ -30,6 +48,12 @@ pub(crate) struct PlaybackNote {
     #[builder(default = "sampled_note::default_sample_note()")]
     pub(crate) sampled_note: SampledNote,
 
+    #[builder(default = "fm_note::default_fm_note()")]
+    pub(crate) fm_note: FMNote,
+
+    #[builder(default = "additive_note::default_additive_note()")]
+    pub(crate) additive_note: AdditiveNote,
+
     #[builder(default = "constants::INIT_START_TIME")]
     pub(crate) playback_start_time_ms: f32,
 
@@ -41,6 +65,14 @@ pub(crate) struct PlaybackNote {
     #[builder(default = "0")]
     pub(crate) playback_sample_end_time: u64,
 
+    // samples of the note played before this window, and the length of the whole note, so its
+    // envelopes and LFOs run over the note rather than restarting in every window. Without a
+    // length, the window is taken to be the whole note
+    #[builder(default = "0", setter(skip))]
+    elapsed_samples: u64,
+    #[builder(default = "None", setter(skip))]
+    note_num_samples: Option<u64>,
+
     #[builder(default = "Vec::new()")]
     pub(crate) envelopes: Vec<Envelope>,
 
@@ -53,12 +85,108 @@ pub(crate) struct PlaybackNote {
     #[builder(default = "Vec::new()")]
     pub(crate) delays: Vec<Delay>,
 
+    #[builder(default = "Vec::new()")]
+    pub(crate) filters: Vec<Filter>,
+
+    #[builder(default = "Vec::new()")]
+    pub(crate) reverbs: Vec<Reverb>,
+
+    #[builder(default = "Vec::new()")]
+    pub(crate) convolutions: Vec<Convolution>,
+
     #[builder(default = "no_op_effects()")]
     pub(crate) track_effects: TrackEffects,
+
+    // position in the stereo field, -1.0 hard left to 1.0 hard right
+    #[builder(default = "constants::DEFAULT_PAN")]
+    pub(crate) pan: f32,
+
+    // pan of the track the note is playing on, added to the note's own pan
+    #[builder(default = "constants::DEFAULT_PAN")]
+    pub(crate) track_pan: f32,
+
+    #[builder(default = "PanLaw::default()")]
+    pub(crate) pan_law: PanLaw,
+
+    // position in the cycle of each layer of each unison voice of an oscillator note, voice by
+    // voice, each advanced every sample at its own frequency. Created on the first sample
+    #[builder(default = "Vec::new()")]
+    pub(crate) oscillator_phases: Vec<PhaseAccumulator>,
+
+    // phase each unison voice starts the note at, all 0.0 if empty
+    #[builder(default = "Vec::new()")]
+    pub(crate) unison_start_phases: Vec<f64>,
+
+    // the waveforms of the note's layers modulated for the current sample, shared by its unison
+    // voices. Kept on the note so the buffer isn't allocated on every sample
+    #[builder(default = "Vec::new()", setter(skip))]
+    pub(crate) modulated_waveforms: Vec<Waveform>,
+
+    // this voice's source of noise for noise waveforms, seeded per voice from the render seed
+    #[builder(default = "NoiseGenerator::default()")]
+    pub(crate) noise: NoiseGenerator,
+
+    // modulate the width of the note's Pulse waveforms. The envelope scales the width over the
+    // note and the LFO's output is added to it
+    #[builder(default = "None")]
+    pub(crate) pulse_width_envelope: Option<Envelope>,
+    #[builder(default = "None")]
+    pub(crate) pulse_width_lfo: Option<LFO>,
+
+    // modulate the position of the note's Wavetable waveforms, to morph between their frames.
+    // The envelope scales the position over the note and the LFO's output is added to it
+    #[builder(default = "None")]
+    pub(crate) wavetable_position_envelope: Option<Envelope>,
+    #[builder(default = "None")]
+    pub(crate) wavetable_position_lfo: Option<LFO>,
+
+    // wavetable of each of the note's layers and the id it was looked up by, None for layers of
+    // other waveforms. Looked up in the registry when the note is built, and again only if the
+    // note's layers change, rather than on every sample
+    #[builder(default = "self.layer_wavetables()", setter(skip))]
+    pub(crate) wavetables: Vec<Option<(WavetableId, Arc<Wavetable>)>>,
+
+    // sweep the pitch of an oscillator note, in semitones, such as the drop of a kick drum
+    #[builder(default = "None")]
+    pub(crate) pitch_envelope: Option<ModulationEnvelope>,
+
+    // sweep the cutoff of the note's and track's filters, in octaves
+    #[builder(default = "None")]
+    pub(crate) cutoff_envelope: Option<ModulationEnvelope>,
+
+    // copy of this note's effects that processes the right channel of stereo samples, so each
+    // channel keeps its own effect state. Created on the first stereo frame
+    #[builder(default = "None", setter(skip))]
+    right_channel_note: Option<Box<PlaybackNote>>,
 }
 
 #[allow(dead_code)]
 impl PlaybackNote {
+    // oscillator_phases for an oscillator note elapsed_ms after it started, so a note that starts
+    // playing partway through picks up where it would have been
+    pub(crate) fn oscillator_phases_at(&self, elapsed_ms: f32) -> Vec<PhaseAccumulator> {
+        let unison = self.note.unison;
+        let mut phases = Vec::with_capacity(unison.num_voices * self.note.layers.len());
+        for voice in 0..unison.num_voices {
+            let start_phase = self.unison_start_phases.get(voice).copied().unwrap_or_default();
+            let frequency = self.note.frequency * unison.frequency_ratio(voice);
+            for layer in self.note.layers.iter() {
+                let elapsed = PhaseAccumulator::at_elapsed_ms(
+                    frequency * layer.frequency_ratio(), elapsed_ms);
+                phases.push(PhaseAccumulator::new(
+                    start_phase + layer.phase as f64 + elapsed.phase()));
+            }
+        }
+        phases
+    }
+
+    // Moves the note's envelopes and LFOs elapsed_samples into the note, for a window that starts
+    // partway through it
+    pub(crate) fn set_elapsed_samples(&mut self, elapsed_samples: u64, note_num_samples: u64) {
+        self.elapsed_samples = elapsed_samples;
+        self.note_num_samples = Some(note_num_samples);
+    }
+
     pub(crate) fn playback_duration_ms(&self) -> f32 {
         self.playback_end_time_ms - self.playback_start_time_ms
     }
@@ -67,6 +195,8 @@ impl PlaybackNote {
         match self.note_type {
             NoteType::Oscillator => self.note.start_time_ms,
             NoteType::Sample => self.sampled_note.start_time_ms,
+            NoteType::FM => self.fm_note.start_time_ms,
+            NoteType::Additive => self.additive_note.start_time_ms,
         }
     }
 
@@ -74,6 +204,8 @@ impl PlaybackNote {
         match self.note_type {
             NoteType::Oscillator => self.note.start_time_ms = start_time_ms,
             NoteType::Sample => self.sampled_note.start_time_ms = start_time_ms,
+            NoteType::FM => self.fm_note.start_time_ms = start_time_ms,
+            NoteType::Additive => self.additive_note.start_time_ms = start_time_ms,
         }
     }
 
@@ -81,6 +213,8 @@ impl PlaybackNote {
         match self.note_type {
             NoteType::Oscillator => self.note.end_time_ms,
             NoteType::Sample => self.sampled_note.end_time_ms,
+            NoteType::FM => self.fm_note.end_time_ms,
+            NoteType::Additive => self.additive_note.end_time_ms,
         }
     }
 
@@ -88,6 +222,8 @@ impl PlaybackNote {
         match self.note_type {
             NoteType::Oscillator => self.note.end_time_ms = end_time_ms,
             NoteType::Sample => self.sampled_note.end_time_ms = end_time_ms,
+            NoteType::FM => self.fm_note.end_time_ms = end_time_ms,
+            NoteType::Additive => self.additive_note.end_time_ms = end_time_ms,
         }
     }
 
@@ -95,6 +231,8 @@ impl PlaybackNote {
         match self.note_type {
             NoteType::Oscillator => self.note.duration_ms(),
             NoteType::Sample => self.sampled_note.duration_ms(),
+            NoteType::FM => self.fm_note.duration_ms(),
+            NoteType::Additive => self.additive_note.duration_ms(),
         }
     }
 
@@ -102,6 +240,8 @@ impl PlaybackNote {
         match self.note_type {
             NoteType::Oscillator => self.note.volume,
             NoteType::Sample => self.sampled_note.volume,
+            NoteType::FM => self.fm_note.volume,
+            NoteType::Additive => self.additive_note.volume,
         }
     }
 
@@ -109,56 +249,162 @@ impl PlaybackNote {
         match self.note_type {
             NoteType::Oscillator => self.note.volume = volume,
             NoteType::Sample => self.sampled_note.volume = volume,
+            NoteType::FM => self.fm_note.volume = volume,
+            NoteType::Additive => self.additive_note.volume = volume,
         }
     }
 
+    // Runs the sample through the note and track effects and then pans it into a stereo frame
     pub(crate) fn apply_effects(&mut self, sample: f32, sample_position: f32,
-                                sample_count: u64) -> f32 {
+                                sample_count: u64) -> StereoFrame {
+        let output_sample = self.apply_mono_effects(sample, sample_position, sample_count);
+        let frame = self.pan_law.pan(output_sample, self.pan + self.track_pan);
+        self.apply_reverbs(frame)
+    }
+
+    // Stereo samples run each channel through its own copy of the effects and are then balanced
+    // rather than panned, to keep their stereo image
+    pub(crate) fn apply_stereo_effects(&mut self, frame: StereoFrame, sample_position: f32,
+                                       sample_count: u64) -> StereoFrame {
+        if self.right_channel_note.is_none() {
+            // Don't copy the sample buffers, the right channel note only applies effects
+            let sampled_note = std::mem::replace(&mut self.sampled_note,
+                                                 sampled_note::default_sample_note());
+            self.right_channel_note = Some(Box::new(self.clone()));
+            self.sampled_note = sampled_note;
+        }
+
+        let left = self.apply_mono_effects(frame.left, sample_position, sample_count);
+        let right = self.right_channel_note.as_mut().unwrap()
+            .apply_mono_effects(frame.right, sample_position, sample_count);
+        let frame = StereoFrame::new(left, right).balance(self.pan + self.track_pan);
+        self.apply_reverbs(frame)
+    }
+
+    // Samples the note's own effects ring on for after it ends. Each effect's tail runs through
+    // the ones after it, so they add up
+    pub(crate) fn tail_samples(&self) -> usize {
+        self.delays.iter().map(Delay::tail_samples).sum::<usize>() +
+            self.reverbs.iter().map(Reverb::tail_samples).sum::<usize>() +
+            self.convolutions.iter().map(Convolution::tail_samples).sum::<usize>()
+    }
+
+    // The next frame of the note's tail, its delays and reverbs running on with no input after
+    // it has ended
+    pub(crate) fn next_tail_frame(&mut self) -> StereoFrame {
+        let pan = self.pan + self.track_pan;
+        let left = self.apply_delays(0.0);
+        let frame = match self.right_channel_note.as_mut() {
+            Some(right_channel_note) =>
+                StereoFrame::new(left, right_channel_note.apply_delays(0.0)).balance(pan),
+            None => self.pan_law.pan(left, pan),
+        };
+        self.apply_reverbs(frame)
+    }
+
+    // Reverbs come last, after panning, as they spread the note across the stereo field.
+    // Algorithmic reverbs run before convolution, so a cabinet impulse response colors them too.
+    // The track's reverbs and convolutions run on its bus, on the sum of its notes
+    fn apply_reverbs(&mut self, mut frame: StereoFrame) -> StereoFrame {
+        for reverb in self.reverbs.iter_mut() {
+            frame = reverb.apply_effect(frame);
+        }
+        for convolution in self.convolutions.iter_mut() {
+            frame = convolution.apply_effect(frame);
+        }
+        frame
+    }
+
+    // Looks the wavetables up again if the note's layers were changed after it was built
+    pub(crate) fn resolve_wavetables(&mut self) {
+        let is_resolved = self.wavetables.len() == self.note.layers.len() &&
+            self.note.layers.iter().zip(self.wavetables.iter())
+                .all(|(layer, wavetable)|
+                    wavetable_id(&layer.waveform) == wavetable.as_ref().map(|(id, _)| *id));
+        if !is_resolved {
+            self.wavetables = layer_wavetables(&self.note.layers);
+        }
+    }
+
+    // position through the note of the sample at sample_count into the window, as the envelopes
+    // see it
+    fn position(&self, sample_count: u64) -> f32 {
+        let note_num_samples = match self.note_num_samples {
+            Some(note_num_samples) => note_num_samples as f32,
+            None => self.playback_sample_end_time as f32 - self.playback_sample_start_time as f32,
+        };
+        self.note_sample_count(sample_count) as f32 / note_num_samples
+    }
+
+    // samples into the note of the sample at sample_count into the window
+    fn note_sample_count(&self, sample_count: u64) -> u64 {
+        self.elapsed_samples + sample_count
+    }
+
+    // multiple of the note's frequency the note and track pitch envelopes put it at for the
+    // sample at sample_count
+    pub(crate) fn pitch_ratio(&self, sample_count: u64) -> f32 {
+        if self.pitch_envelope.is_none() && self.track_effects.pitch_envelope.is_none() {
+            return 1.0;
+        }
+        semitones_to_ratio(total_modulation(
+            &[self.pitch_envelope, self.track_effects.pitch_envelope], self.position(sample_count)))
+    }
+
+    // waveform with the width of a Pulse or position of a Wavetable modulated for the sample at
+    // sample_count
+    pub(crate) fn modulated_waveform(&mut self, waveform: Waveform,
+                                     sample_count: u64) -> Waveform {
+        let position = self.position(sample_count);
+        let note_sample_count = self.note_sample_count(sample_count);
+        match waveform {
+            Waveform::Pulse(band_limit, width) => Waveform::Pulse(band_limit, modulate(
+                width, &self.pulse_width_envelope, &mut self.pulse_width_lfo, position,
+                note_sample_count)),
+            Waveform::Wavetable(id, position_in_table) => Waveform::Wavetable(id, modulate(
+                position_in_table, &self.wavetable_position_envelope,
+                &mut self.wavetable_position_lfo, position, note_sample_count)),
+            _ => waveform,
+        }
+    }
+
+    pub(crate) fn apply_mono_effects(&mut self, sample: f32, sample_position: f32,
+                                     sample_count: u64) -> f32 {
         let mut output_sample = sample;
 
-        match self.note_type {
-            
-            NoteType::Oscillator => {
-                for envelope in self.envelopes.iter() {
-                    output_sample = envelope.apply_effect(
-                        output_sample, // sample_position);
-                        sample_count as f32 /
-                            (self.playback_sample_end_time as f32 -
-                                self.playback_sample_start_time as f32));
-                }
-                for envelope in self.track_effects.envelopes.iter() {
-                    output_sample = envelope.apply_effect(
-                        output_sample, // sample_position);
-                        sample_count as f32 /
-                            (self.playback_sample_end_time as f32 -
-                                self.playback_sample_start_time as f32));
-                }
-            }
-            
-            NoteType::Sample => { 
-                for envelope in self.envelopes.iter() {
-                    output_sample = envelope.apply_effect(
-                        output_sample,
-                        sample_count as f32 /
-                            (self.playback_sample_end_time as f32 -
-                                self.playback_sample_start_time as f32));
-                }
-                for envelope in self.track_effects.envelopes.iter() {
-                    output_sample = envelope.apply_effect(
-                        output_sample,
-                        sample_count as f32 /
-                            (self.playback_sample_end_time as f32 -
-                                self.playback_sample_start_time as f32));
-                }
+        if self.cutoff_envelope.is_some() || self.track_effects.cutoff_envelope.is_some() {
+            let octaves = total_modulation(
+                &[self.cutoff_envelope, self.track_effects.cutoff_envelope],
+                self.position(sample_count));
+            for filter in self.filters.iter_mut().chain(self.track_effects.filters.iter_mut()) {
+                filter.modulate_cutoff(octaves);
             }
         }
-        
-        for lfo in self.lfos.iter() {
-            output_sample = lfo.apply_effect(output_sample, sample_count);
+
+        // filters shape the raw voice before its envelopes, as in a subtractive synth
+        for filter in self.filters.iter_mut() {
+            output_sample = filter.apply_effect(output_sample);
         }
 
-        for lfo in self.track_effects.lfos.iter() {
-            output_sample = lfo.apply_effect(output_sample, sample_count);
+        for filter in self.track_effects.filters.iter_mut() {
+            output_sample = filter.apply_effect(output_sample);
+        }
+
+        let position = self.position(sample_count);
+        for envelope in self.envelopes.iter() {
+            output_sample = envelope.apply_effect(output_sample, position);
+        }
+        for envelope in self.track_effects.envelopes.iter() {
+            output_sample = envelope.apply_effect(output_sample, position);
+        }
+
+        let note_sample_count = self.note_sample_count(sample_count);
+        for lfo in self.lfos.iter_mut() {
+            output_sample = lfo.apply_effect(output_sample, note_sample_count);
+        }
+
+        for lfo in self.track_effects.lfos.iter_mut() {
+            output_sample = lfo.apply_effect(output_sample, note_sample_count);
         }
 
         for flanger in self.flangers.iter_mut() {
@@ -169,16 +415,28 @@ impl PlaybackNote {
             output_sample = flanger.apply_effect(output_sample, sample_position);
         }
         
-        for delay in self.delays.iter_mut() {
-            output_sample = delay.apply_effect(output_sample, sample_position);
-        }
+        self.apply_delays(output_sample)
+    }
 
-        for delay in self.track_effects.delays.iter_mut() {
-            output_sample = delay.apply_effect(output_sample, sample_position);
+    // The track's delays run on its bus, on the sum of its notes
+    fn apply_delays(&mut self, mut sample: f32) -> f32 {
+        for delay in self.delays.iter_mut() {
+            sample = delay.apply_effect(sample);
         }
+        sample
+    }
+}
 
-        output_sample
+// value scaled by the envelope at position through the note, plus the LFO's output
+fn modulate(mut value: f32, envelope: &Option<Envelope>, lfo: &mut Option<LFO>, position: f32,
+            sample_count: u64) -> f32 {
+    if let Some(envelope) = envelope {
+        value *= envelope.volume_factor(position);
     }
+    if let Some(lfo) = lfo {
+        value += lfo.modulation(sample_count);
+    }
+    value
 }
 
 #[allow(dead_code)]
@@ -204,6 +462,28 @@ pub(crate) fn from_note(note_type: NoteType, note: Note) -> PlaybackNote {
         .build().unwrap()
 }
 
+fn wavetable_id(waveform: &Waveform) -> Option<WavetableId> {
+    match waveform {
+        Waveform::Wavetable(id, _) => Some(*id),
+        _ => None,
+    }
+}
+
+fn layer_wavetables(layers: &[OscillatorLayer]) -> Vec<Option<(WavetableId, Arc<Wavetable>)>> {
+    layers.iter()
+        .map(|layer| wavetable_id(&layer.waveform).map(|id| (id, get_wavetable(id))))
+        .collect()
+}
+
+impl PlaybackNoteBuilder {
+    fn layer_wavetables(&self) -> Vec<Option<(WavetableId, Arc<Wavetable>)>> {
+        match &self.note {
+            Some(note) => layer_wavetables(&note.layers),
+            None => Vec::new(),
+        }
+    }
+}
+
 impl BuilderWrapper<PlaybackNote> for PlaybackNoteBuilder {
     fn new() -> PlaybackNote {
         PlaybackNoteBuilder::default().build().unwrap()
@@ -212,12 +492,26 @@ impl BuilderWrapper<PlaybackNote> for PlaybackNoteBuilder {
 
 #[cfg(test)]
 mod test_playback_note {
+    use crate::audio_gen::band_limit::BandLimit;
+    use crate::audio_gen::oscillator::Waveform;
+    use crate::audio_gen::wavetable::{get_wavetable, register_wavetable, Wavetable};
+    use crate::common::float_utils::assert_float_eq;
+    use crate::common::stereo::{PanLaw, StereoFrame, PAN_CENTER, PAN_LEFT};
     use crate::envelope::envelope;
-    use crate::effect::{delay, flanger};
+    use crate::envelope::envelope::EnvelopeBuilder;
+    use crate::envelope::envelope_pair::EnvelopePair;
+    use crate::envelope::modulation_envelope::ModulationEnvelopeBuilder;
+    use crate::effect::{delay, filter, flanger, reverb};
+    use crate::effect::convolution::{ConvolutionBuilder, ImpulseResponse};
+    use crate::effect::filter::FilterBuilder;
     use crate::effect::lfo;
+    use crate::effect::lfo::LFOBuilder;
     use crate::note::constants;
     use crate::note::note;
-    use crate::note::playback_note::PlaybackNoteBuilder;
+    use crate::note::note::NoteBuilder;
+    use crate::note::oscillator_layer::unity_layers;
+    use crate::note::playback_note::{NoteType, PlaybackNoteBuilder};
+    use crate::track::track_effects::TrackEffectsBuilder;
 
     #[test]
     fn test_default_playback_note() {
@@ -226,10 +520,66 @@ mod test_playback_note {
         assert_eq!(playback_note.playback_start_time_ms, constants::INIT_START_TIME);
         assert_eq!(playback_note.playback_end_time_ms, constants::INIT_END_TIME);
         assert_eq!(playback_note.playback_duration_ms(), constants::DEFAULT_DURATION);
-        assert_eq!(playback_note.envelopes.is_empty(), true);
-        assert_eq!(playback_note.lfos.is_empty(), true);
-        assert_eq!(playback_note.flangers.is_empty(), true);
-        assert_eq!(playback_note.delays.is_empty(), true);
+        assert!(playback_note.envelopes.is_empty());
+        assert!(playback_note.lfos.is_empty());
+        assert!(playback_note.flangers.is_empty());
+        assert!(playback_note.delays.is_empty());
+        assert_eq!(playback_note.pan, constants::DEFAULT_PAN);
+        assert_eq!(playback_note.pan_law, PanLaw::ConstantPower);
+    }
+
+    #[test]
+    fn test_apply_effects_pans_note_and_track() {
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .pan(-0.5)
+            .track_pan(-0.5)
+            .pan_law(PanLaw::Linear)
+            .build().unwrap();
+
+        let frame = playback_note.apply_effects(1.0, 0.0, 0);
+        assert_eq!(frame, StereoFrame::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn test_apply_stereo_effects_balances() {
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .note_type(NoteType::Sample)
+            .track_pan(PAN_LEFT)
+            .build().unwrap();
+
+        let frame = playback_note.apply_stereo_effects(StereoFrame::new(0.5, -0.5), 0.0, 0);
+        assert_eq!(frame, StereoFrame::new(0.5, 0.0));
+        assert!(playback_note.right_channel_note.is_some());
+    }
+
+    #[test]
+    fn test_modulated_pulse_width() {
+        let pulse = Waveform::Pulse(BandLimit::PolyBlep, 0.5);
+        let envelope = EnvelopeBuilder::default()
+            .attack(EnvelopePair(0.3, 0.9))
+            .decay(EnvelopePair(0.35, 0.7))
+            .sustain(EnvelopePair(0.6, 0.65))
+            .build().unwrap();
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .playback_sample_end_time(100)
+            .pulse_width_envelope(Some(envelope))
+            .build().unwrap();
+        // 30% of the way into the note the envelope is at 0.9
+        match playback_note.modulated_waveform(pulse, 30) {
+            Waveform::Pulse(_, width) => assert_float_eq(width, 0.45),
+            waveform => panic!("expected a pulse, got {:?}", waveform),
+        }
+
+        let lfo = LFOBuilder::default().frequency(441.0).amplitude(0.2).build().unwrap();
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .pulse_width_lfo(Some(lfo))
+            .build().unwrap();
+        // a quarter of the way into the LFO's cycle its sine is at its peak
+        match playback_note.modulated_waveform(pulse, 25) {
+            Waveform::Pulse(_, width) => assert_float_eq(width, 0.7),
+            waveform => panic!("expected a pulse, got {:?}", waveform),
+        }
+        assert_eq!(playback_note.modulated_waveform(Waveform::Sine, 25), Waveform::Sine);
     }
 
     #[test]
@@ -256,6 +606,103 @@ mod test_playback_note {
         assert_eq!(playback_note.flangers, vec![flanger::default_flanger()]);
     }
 
+    #[test]
+    fn test_playback_note_with_filters() {
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .filters(vec![filter::default_filter()])
+            .build().unwrap();
+        assert_eq!(playback_note.filters, vec![filter::default_filter()]);
+        // a low-pass filter passes a constant signal once it settles
+        let output = (0..1000)
+            .map(|i| playback_note.apply_mono_effects(1.0, 0.0, i))
+            .last().unwrap();
+        assert!((output - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_playback_note_with_reverbs() {
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .reverbs(vec![reverb::default_reverb()])
+            .playback_sample_end_time(4410)
+            .build().unwrap();
+        assert_eq!(playback_note.reverbs, vec![reverb::default_reverb()]);
+        // the tail of a click carries on after it, spread across both channels
+        let frames: Vec<StereoFrame> = (0..4410)
+            .map(|i| playback_note.apply_effects(if i == 0 { 1.0 } else { 0.0 }, 0.0, i))
+            .collect();
+        assert!(frames[2000..].iter().any(|frame| frame.left != 0.0));
+        assert!(frames[2000..].iter().any(|frame| frame.left != frame.right));
+    }
+
+    #[test]
+    fn test_playback_note_with_convolutions() {
+        let impulse_response = ImpulseResponse { left: vec![0.0, 0.0, 1.0], right: Vec::new() };
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .convolutions(vec![ConvolutionBuilder::default()
+                .impulse_response(impulse_response)
+                .mix(1.0)
+                .build().unwrap()])
+            .playback_sample_end_time(10)
+            .build().unwrap();
+        let frames: Vec<StereoFrame> = (0..4)
+            .map(|i| playback_note.apply_effects(if i == 0 { 1.0 } else { 0.0 }, 0.0, i))
+            .collect();
+        // the click comes through two samples late, panned to the center
+        assert_eq!(frames[0], StereoFrame::default());
+        assert_eq!(frames[2], PanLaw::default().pan(1.0, PAN_CENTER));
+    }
+
+    #[test]
+    fn test_pitch_envelope() {
+        // an octave drop over the first half of the note, a semitone of it from the track
+        let drop = EnvelopeBuilder::default()
+            .attack(EnvelopePair(0.0, 1.0))
+            .decay(EnvelopePair(0.5, 0.0))
+            .sustain(EnvelopePair(0.9, 0.0))
+            .build().unwrap();
+        let modulation_envelope = |depth: f32| Some(ModulationEnvelopeBuilder::default()
+            .envelope(drop)
+            .depth(depth)
+            .build().unwrap());
+        let playback_note = PlaybackNoteBuilder::default()
+            .pitch_envelope(modulation_envelope(11.0))
+            .track_effects(TrackEffectsBuilder::default()
+                .pitch_envelope(modulation_envelope(1.0))
+                .build().unwrap())
+            .playback_sample_end_time(100)
+            .build().unwrap();
+        assert_float_eq(playback_note.pitch_ratio(0), 2.0);
+        assert_float_eq(playback_note.pitch_ratio(25), 2.0f32.sqrt());
+        assert_float_eq(playback_note.pitch_ratio(50), 1.0);
+        assert_float_eq(playback_note.pitch_ratio(80), 1.0);
+        assert_float_eq(PlaybackNoteBuilder::default().build().unwrap().pitch_ratio(0), 1.0);
+    }
+
+    #[test]
+    fn test_cutoff_envelope() {
+        // holds the cutoff an octave up for the whole note
+        let held = EnvelopeBuilder::default()
+            .attack(EnvelopePair(0.0, 1.0))
+            .decay(EnvelopePair(1.0, 1.0))
+            .sustain(EnvelopePair(1.0, 1.0))
+            .build().unwrap();
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .filters(vec![FilterBuilder::default().cutoff_hz(500.0).build().unwrap()])
+            .cutoff_envelope(Some(ModulationEnvelopeBuilder::default()
+                .envelope(held)
+                .depth(1.0)
+                .build().unwrap()))
+            .playback_sample_end_time(100)
+            .build().unwrap();
+        let mut filter = FilterBuilder::default().cutoff_hz(1000.0).build().unwrap();
+        for i in 0..99 {
+            let sample = (i as f32 * 0.7).sin();
+            assert_float_eq(playback_note.apply_mono_effects(sample, 0.0, i),
+                            filter.apply_effect(sample));
+        }
+        assert_eq!(playback_note.filters[0].cutoff_hz, 500.0);
+    }
+
     #[test]
     fn test_playback_note_with_delays() {
         let playback_note = PlaybackNoteBuilder::default()
@@ -263,4 +710,24 @@ mod test_playback_note {
             .build().unwrap();
         assert_eq!(playback_note.delays, vec![delay::default_delay()]);
     }
+
+    #[test]
+    fn test_resolve_wavetables() {
+        let first_id = register_wavetable("PlaybackNoteFirstTable",
+                                          Wavetable::from_harmonics(&[vec![1.0]]).unwrap());
+        let second_id = register_wavetable("PlaybackNoteSecondTable",
+                                           Wavetable::from_harmonics(&[vec![1.0, 0.5]]).unwrap());
+        let mut playback_note = PlaybackNoteBuilder::default()
+            .note(NoteBuilder::default()
+                .waveforms(vec![Waveform::Sine, Waveform::Wavetable(first_id, 0.0)])
+                .build().unwrap())
+            .build().unwrap();
+        assert_eq!(playback_note.wavetables,
+                   vec![None, Some((first_id, get_wavetable(first_id)))]);
+
+        // layers changed after the note was built are looked up again
+        playback_note.note.layers = unity_layers(&[Waveform::Wavetable(second_id, 0.0)]);
+        playback_note.resolve_wavetables();
+        assert_eq!(playback_note.wavetables, vec![Some((second_id, get_wavetable(second_id)))]);
+    }
 }
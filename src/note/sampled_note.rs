@@ -1,10 +1,24 @@
 use derive_builder::Builder;
-use crate::common::constants::SAMPLE_RATE;
+use crate::audio_gen::audio_gen::read_audio_file;
+use crate::audio_gen::resample::resample;
+use crate::common::constants::DEFAULT_SAMPLE_RATE;
+use crate::common::stereo::StereoFrame;
 
 use crate::note::constants::{DEFAULT_VOLUME, INIT_START_TIME};
 use crate::note::note_trait::BuilderWrapper;
 
-pub(crate) const BUF_STORAGE_SIZE: usize = (SAMPLE_RATE as usize * 2) as usize;
+pub(crate) const BUF_STORAGE_SIZE: usize = DEFAULT_SAMPLE_RATE as usize * 2;
+
+// How the channels of a multichannel file are loaded
+// Downmix: average all channels into one mono buffer
+// Keep: load the first two channels as left and right, mono files stay mono
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum ChannelMode {
+    Downmix,
+    #[default]
+    Keep,
+}
 
 #[allow(dead_code)]
 #[derive(Builder, Clone, Debug, PartialEq)]
@@ -30,6 +44,17 @@ pub(crate) struct SampledNote {
 
     #[builder(default = "Vec::with_capacity(BUF_STORAGE_SIZE)", setter(skip))]
     sample_buf: Vec<f32>,
+
+    // right channel of a stereo sample, empty for mono samples which only use sample_buf
+    #[builder(default = "Vec::new()", setter(skip))]
+    sample_buf_right: Vec<f32>,
+
+    #[builder(default = "ChannelMode::default()")]
+    pub(crate) channel_mode: ChannelMode,
+
+    // engine rate the file is resampled to when loaded
+    #[builder(default = "DEFAULT_SAMPLE_RATE")]
+    pub(crate) sample_rate: f32,
 }
 
 #[allow(dead_code)]
@@ -47,6 +72,25 @@ impl SampledNote {
             0.0
         }
     }
+
+    pub(crate) fn is_stereo(&self) -> bool {
+        !self.sample_buf_right.is_empty()
+    }
+
+    // Stereo counterpart to next_sample, mono samples are copied to both channels
+    pub(crate) fn next_frame(&mut self) -> StereoFrame {
+        if !self.is_stereo() {
+            return StereoFrame::from_mono(self.next_sample());
+        }
+        if self.sample_index < self.buf_size {
+            let frame = StereoFrame::new(self.sample_buf[self.sample_index],
+                                         self.sample_buf_right[self.sample_index]);
+            self.sample_index += 1;
+            frame
+        } else {
+            StereoFrame::default()
+        }
+    }
     
     // TODO Can now add range and "scrach" kinds of access to the buffer
     
@@ -56,11 +100,20 @@ impl SampledNote {
 
     // TODO remove unused arg buf_size
     pub(crate) fn set_sample_buf(&mut self, samples: &[f32]) {
-        self.sample_buf = samples.try_into().unwrap();
+        self.sample_buf = samples.to_vec();
+        self.sample_buf_right.clear();
         self.buf_size = samples.len();
         self.sample_index = 0;
     }
 
+    pub(crate) fn set_stereo_sample_buf(&mut self, left_samples: &[f32], right_samples: &[f32]) {
+        if left_samples.len() != right_samples.len() {
+            panic!("Left and right sample buffers must be the same length");
+        }
+        self.set_sample_buf(left_samples);
+        self.sample_buf_right = right_samples.to_vec();
+    }
+
     pub(crate) fn append_sample(&mut self, sample: f32) {
         self.sample_buf.push(sample);
         self.buf_size += 1;
@@ -68,6 +121,7 @@ impl SampledNote {
 
     pub(crate) fn reverse(&mut self) {
         self.sample_buf.reverse();
+        self.sample_buf_right.reverse();
     }
 
     pub(crate) fn chopped(&self, num_segments: usize) -> Vec<SampledNote> {
@@ -78,6 +132,9 @@ impl SampledNote {
             let end = (i + 1) * segment_size;
             let mut chopped_note = self.clone();
             chopped_note.sample_buf = self.sample_buf[start..end].to_vec();
+            if self.is_stereo() {
+                chopped_note.sample_buf_right = self.sample_buf_right[start..end].to_vec();
+            }
             chopped_note.buf_size = segment_size;
             chopped_notes.push(chopped_note);
         }
@@ -88,21 +145,48 @@ impl SampledNote {
     pub(crate) fn stretched(&self, stretch_factor: u8) -> SampledNote {
         let mut stretched_note: SampledNote = self.clone();
         let stretched_buf_size = self.buf_size * stretch_factor as usize;
-        stretched_note.sample_buf = Vec::with_capacity(stretched_buf_size);
+        stretched_note.sample_buf = stretch_buf(&self.sample_buf, stretch_factor);
+        stretched_note.sample_buf_right = stretch_buf(&self.sample_buf_right, stretch_factor);
         stretched_note.buf_size = stretched_buf_size;
-        for i in 0..self.buf_size - 1 {
-            let start = self.sample_buf[i];
-            let end = self.sample_buf[i + 1];
-            let step = (end - start) / stretch_factor as f32;
-            for j in 0..stretch_factor {
-                stretched_note.sample_buf.push(start + j as f32 * step);
-            }
-        }
 
         stretched_note
     }
 }
 
+fn stretch_buf(sample_buf: &[f32], stretch_factor: u8) -> Vec<f32> {
+    let mut stretched_buf = Vec::with_capacity(sample_buf.len() * stretch_factor as usize);
+    for i in 0..sample_buf.len().saturating_sub(1) {
+        let start = sample_buf[i];
+        let end = sample_buf[i + 1];
+        let step = (end - start) / stretch_factor as f32;
+        for j in 0..stretch_factor {
+            stretched_buf.push(start + j as f32 * step);
+        }
+    }
+    stretched_buf
+}
+
+// Loads a WAV file as (left, right) buffers resampled to sample_rate. right is empty if the
+// file is mono or channel_mode is Downmix
+pub(crate) fn load_sample_bufs(file_path: &str, channel_mode: ChannelMode, sample_rate: f32)
+    -> Result<(Vec<f32>, Vec<f32>), String> {
+    let audio_file = read_audio_file(file_path)?;
+    let channels = audio_file.channels;
+    if channels.len() > 1 && channel_mode == ChannelMode::Keep {
+        return Ok((resample(&channels[0], audio_file.sample_rate, sample_rate),
+                   resample(&channels[1], audio_file.sample_rate, sample_rate)));
+    }
+
+    let num_channels = channels.len() as f32;
+    let mut mono_buf = vec![0.0; channels.iter().map(|channel| channel.len()).max().unwrap_or(0)];
+    for channel in channels.iter() {
+        for (mono_sample, sample) in mono_buf.iter_mut().zip(channel) {
+            *mono_sample += sample / num_channels;
+        }
+    }
+    Ok((resample(&mono_buf, audio_file.sample_rate, sample_rate), Vec::new()))
+}
+
 impl BuilderWrapper<SampledNote> for SampledNoteBuilder {
     fn new() -> SampledNote {
         SampledNoteBuilder::default().build().unwrap()
@@ -116,17 +200,19 @@ impl SampledNoteBuilder {
         let volume = self.volume.unwrap_or(DEFAULT_VOLUME);
         let start_time_ms = self.start_time_ms.unwrap_or(INIT_START_TIME);
         let end_time_ms = self.end_time_ms.unwrap_or(INIT_START_TIME);
+        let channel_mode = self.channel_mode.unwrap_or_default();
+        let sample_rate = self.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
 
         let mut sample_buf: Vec<f32> = Vec::with_capacity(crate::note::sampled_note::BUF_STORAGE_SIZE);
+        let mut sample_buf_right: Vec<f32> = Vec::new();
         
         // Only try to read audio file if file_path is provided and not empty
         if let Some(file_path) = &self.file_path {
             if !file_path.is_empty() {
-                let sample_data =
-                    crate::audio_gen::audio_gen::read_audio_file(file_path).into_boxed_slice();
-                for sample in sample_data.iter() {
-                    sample_buf.push(*sample as f32);
-                }
+                let (left_buf, right_buf) =
+                    load_sample_bufs(file_path, channel_mode, sample_rate)?;
+                sample_buf.extend(left_buf);
+                sample_buf_right = right_buf;
             }
         }
         let buf_size = sample_buf.len();
@@ -140,6 +226,9 @@ impl SampledNoteBuilder {
                 start_time_ms,
                 end_time_ms,
                 sample_buf,
+                sample_buf_right,
+                channel_mode,
+                sample_rate,
             }
         )
     }
@@ -148,4 +237,72 @@ impl SampledNoteBuilder {
 #[allow(dead_code)]
 pub(crate) fn default_sample_note() -> SampledNote {
     SampledNoteBuilder::default().build().unwrap()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test_sampled_note {
+    use crate::common::stereo::StereoFrame;
+    use crate::note::sampled_note::{ChannelMode, SampledNoteBuilder};
+
+    // One second stereo file at 48 kHz, left channel at 0.5 and right at -0.25
+    fn write_stereo_file(file_name: &str) -> std::path::PathBuf {
+        let file_path = std::env::temp_dir().join(file_name);
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 24,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&file_path, spec).unwrap();
+        for _ in 0..48000 {
+            writer.write_sample(1i32 << 22).unwrap();
+            writer.write_sample(-(1i32 << 21)).unwrap();
+        }
+        writer.finalize().unwrap();
+        file_path
+    }
+
+    #[test]
+    fn test_load_stereo_keep() {
+        let file_path = write_stereo_file("osc_test_load_stereo_keep.wav");
+        let mut sampled_note = SampledNoteBuilder::default()
+            .file_path(file_path.to_str().unwrap().to_string())
+            .channel_mode(ChannelMode::Keep)
+            .sample_rate(44100.0)
+            .build().unwrap();
+        std::fs::remove_file(file_path).unwrap();
+
+        assert!(sampled_note.is_stereo());
+        assert_eq!(sampled_note.buf_size, 44100);
+        sampled_note.sample_index = 22050;
+        let frame = sampled_note.next_frame();
+        assert!((frame.left - 0.5).abs() < 1e-3);
+        assert!((frame.right + 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_load_stereo_downmix() {
+        let file_path = write_stereo_file("osc_test_load_stereo_downmix.wav");
+        let mut sampled_note = SampledNoteBuilder::default()
+            .file_path(file_path.to_str().unwrap().to_string())
+            .channel_mode(ChannelMode::Downmix)
+            .sample_rate(96000.0)
+            .build().unwrap();
+        std::fs::remove_file(file_path).unwrap();
+
+        assert!(!sampled_note.is_stereo());
+        assert_eq!(sampled_note.buf_size, 96000);
+        sampled_note.sample_index = 48000;
+        assert!((sampled_note.next_sample() - 0.125).abs() < 1e-3);
+        assert_eq!(sampled_note.next_frame().left, sampled_note.get_sample_at(48001));
+    }
+
+    #[test]
+    fn test_stereo_reverse() {
+        let mut sampled_note = SampledNoteBuilder::default().build().unwrap();
+        sampled_note.set_stereo_sample_buf(&[0.1, 0.2], &[0.3, 0.4]);
+        sampled_note.reverse();
+        assert_eq!(sampled_note.next_frame(), StereoFrame::new(0.2, 0.4));
+        assert_eq!(sampled_note.next_frame(), StereoFrame::new(0.1, 0.3));
+        assert_eq!(sampled_note.next_frame(), StereoFrame::default());
+    }
+}
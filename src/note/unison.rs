@@ -0,0 +1,126 @@
+use derive_builder::Builder;
+
+use crate::audio_gen::noise::NoiseGenerator;
+
+// Stacks copies of an oscillator note's waveforms, each slightly detuned and panned, for a
+// thicker sound such as a supersaw. One voice is the plain note
+#[allow(dead_code)]
+#[derive(Builder, Clone, Copy, Debug, PartialEq)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub(crate) struct Unison {
+    #[builder(default = "1")]
+    pub(crate) num_voices: usize,
+
+    // the outermost voices are this many cents above and below the note, with the rest spread
+    // evenly between them
+    #[builder(default = "0.0")]
+    pub(crate) detune_cents: f32,
+
+    // the outermost voices are panned this far left and right, from 0.0 to 1.0
+    #[builder(default = "0.0")]
+    pub(crate) stereo_spread: f32,
+
+    // each voice starts up to this fraction of a cycle into its waveform, from 0.0 for all voices
+    // in phase to 1.0 for fully random
+    #[builder(default = "0.0")]
+    pub(crate) random_phase: f32,
+}
+
+impl UnisonBuilder {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.num_voices == Some(0) {
+            return Err(String::from("Unison: num_voices must be at least 1"));
+        }
+        if let Some(stereo_spread) = self.stereo_spread {
+            if !(0.0..=1.0).contains(&stereo_spread) {
+                return Err(String::from("Unison: stereo_spread must be between 0.0 and 1.0"));
+            }
+        }
+        if let Some(random_phase) = self.random_phase {
+            if !(0.0..=1.0).contains(&random_phase) {
+                return Err(String::from("Unison: random_phase must be between 0.0 and 1.0"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[allow(dead_code)]
+pub(crate) fn default_unison() -> Unison {
+    UnisonBuilder::default().build().unwrap()
+}
+
+#[allow(dead_code)]
+impl Unison {
+    pub(crate) fn is_unison(&self) -> bool {
+        self.num_voices > 1
+    }
+
+    // Position of voice from -1.0 for the lowest to 1.0 for the highest
+    fn voice_position(&self, voice: usize) -> f32 {
+        if self.num_voices == 1 {
+            return 0.0;
+        }
+        2.0 * voice as f32 / (self.num_voices - 1) as f32 - 1.0
+    }
+
+    // Voice's frequency as a multiple of the note frequency
+    pub(crate) fn frequency_ratio(&self, voice: usize) -> f32 {
+        2.0f32.powf(self.detune_cents * self.voice_position(voice) / 1200.0)
+    }
+
+    pub(crate) fn pan(&self, voice: usize) -> f32 {
+        self.stereo_spread * self.voice_position(voice)
+    }
+
+    // Gain applied to each voice so the stack is about as loud as a single voice. Detuned voices
+    // are uncorrelated, so their power rather than their amplitude adds up
+    pub(crate) fn voice_gain(&self) -> f32 {
+        1.0 / (self.num_voices as f32).sqrt()
+    }
+
+    // Phase each voice starts the note at
+    pub(crate) fn start_phases(&self, noise: &mut NoiseGenerator) -> Vec<f64> {
+        (0..self.num_voices)
+            .map(|_| (self.random_phase * (noise.white() + 1.0) / 2.0) as f64)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_unison {
+    use crate::audio_gen::noise::NoiseGenerator;
+    use crate::common::float_utils::assert_float_eq;
+    use crate::note::unison::{default_unison, UnisonBuilder};
+
+    #[test]
+    fn test_detune_and_spread() {
+        let unison = UnisonBuilder::default()
+            .num_voices(3)
+            .detune_cents(1200.0)
+            .stereo_spread(0.5)
+            .build().unwrap();
+        assert_float_eq(unison.frequency_ratio(0), 0.5);
+        assert_float_eq(unison.frequency_ratio(1), 1.0);
+        assert_float_eq(unison.frequency_ratio(2), 2.0);
+        assert_float_eq(unison.pan(0), -0.5);
+        assert_float_eq(unison.pan(2), 0.5);
+
+        assert!(!default_unison().is_unison());
+        assert_float_eq(default_unison().frequency_ratio(0), 1.0);
+        assert!(UnisonBuilder::default().num_voices(0).build().is_err());
+        assert!(UnisonBuilder::default().stereo_spread(1.5).build().is_err());
+    }
+
+    #[test]
+    fn test_start_phases() {
+        let mut noise = NoiseGenerator::from_seed(3);
+        let in_phase = UnisonBuilder::default().num_voices(4).build().unwrap();
+        assert_eq!(in_phase.start_phases(&mut noise), vec![0.0; 4]);
+
+        let random = UnisonBuilder::default().num_voices(4).random_phase(0.5).build().unwrap();
+        let phases = random.start_phases(&mut noise);
+        assert!(phases.iter().all(|phase| (0.0..=0.5).contains(phase)));
+        assert!(phases.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+}
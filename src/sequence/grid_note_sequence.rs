@@ -24,7 +24,7 @@ impl AppendNote for GridNoteSequence {
 }
 
 impl AppendNotes for GridNoteSequence {
-    fn append_notes(&mut self, notes: &Vec<PlaybackNote>) {
+    fn append_notes(&mut self, notes: &[PlaybackNote]) {
         self.append_notes(notes);
     }
 }
@@ -50,7 +50,7 @@ impl SetCurPosition for GridNoteSequence {
 }
 
 impl IterMutWrapper for GridNoteSequence {
-    fn iter_mut(&mut self) -> std::slice::IterMut<Vec<PlaybackNote>> {
+    fn iter_mut(&mut self) -> std::slice::IterMut<'_, Vec<PlaybackNote>> {
         self.sequence.iter_mut()
     }
 }
@@ -58,12 +58,12 @@ impl IterMutWrapper for GridNoteSequence {
 #[allow(dead_code)]
 impl GridNoteSequence {
 
-    pub(crate) fn append_notes(&mut self, playback_notes: &Vec<PlaybackNote>) {
+    pub(crate) fn append_notes(&mut self, playback_notes: &[PlaybackNote]) {
         if playback_notes.is_empty() {
             panic!("Notes to add must not be empty");
         }
         
-        self.sequence.push(playback_notes.clone());
+        self.sequence.push(playback_notes.to_vec());
     }
     
     pub(crate) fn insert_notes(&mut self, playback_notes: Vec<PlaybackNote>) {
@@ -198,13 +198,13 @@ impl GridNoteSequence {
 
     // Only makes sense with an index and as an internal method
     // Would be public in a grid- rather than time-based sequencer
-    pub(crate) fn sequence_iter_mut(&mut self) -> std::slice::IterMut<Vec<PlaybackNote>> {
+    pub(crate) fn sequence_iter_mut(&mut self) -> std::slice::IterMut<'_, Vec<PlaybackNote>> {
         self.sequence.iter_mut()
     }
 
     // Only makes sense with an index and as an internal method
     // Would be public in a grid- rather than time-based sequencer
-    pub(crate) fn sequence_iter(&self) -> std::slice::Iter<Vec<PlaybackNote>> {
+    pub(crate) fn sequence_iter(&self) -> std::slice::Iter<'_, Vec<PlaybackNote>> {
         self.sequence.iter()
     }
 
@@ -215,7 +215,7 @@ impl GridNoteSequence {
     }
 }
 
-impl<'a> Iterator for GridNoteSequence {
+impl Iterator for GridNoteSequence {
     type Item = Vec<PlaybackNote>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -277,7 +277,7 @@ mod test_grid_note_sequence {
             .build().unwrap();
 
         let actual = sequence.get_notes() ;
-        assert_eq!(float_eq(actual[0].note_start_time_ms(), note_1.note_start_time_ms()), true);
+        assert!(float_eq(actual[0].note_start_time_ms(), note_1.note_start_time_ms()));
         assert_eq!(actual[0], note_1);
         assert_eq!(actual[1], note_2);
     }
@@ -373,7 +373,7 @@ mod test_grid_note_sequence {
             .index(0)
             .build().unwrap();
 
-        sequence.append_notes(&vec![]);
+        sequence.append_notes(&[]);
     }
 
     #[test]
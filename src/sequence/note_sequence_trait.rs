@@ -6,7 +6,7 @@ pub(crate) trait AppendNote {
 }
 
 pub(crate) trait AppendNotes {
-    fn append_notes(&mut self, notes: &Vec<PlaybackNote>);
+    fn append_notes(&mut self, notes: &[PlaybackNote]);
 }
 
 pub(crate) trait BuilderWrapper<SequenceType> {
@@ -23,5 +23,5 @@ pub(crate) trait SetCurPosition {
 
 #[allow(dead_code)]
 pub(crate) trait IterMutWrapper {
-    fn iter_mut(&mut self) -> std::slice::IterMut<Vec<PlaybackNote>>;
+    fn iter_mut(&mut self) -> std::slice::IterMut<'_, Vec<PlaybackNote>>;
 }
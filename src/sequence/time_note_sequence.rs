@@ -30,12 +30,12 @@ pub(crate) struct TimeNoteSequence {
 
 impl AppendNote for TimeNoteSequence {
     fn append_note(&mut self, note: PlaybackNote) {
-        self.append_notes(&vec![note]);
+        self.append_notes(&[note]);
     }
 }
 
 impl AppendNotes for TimeNoteSequence {
-    fn append_notes(&mut self, notes: &Vec<PlaybackNote>) {
+    fn append_notes(&mut self, notes: &[PlaybackNote]) {
         self.append_notes(notes);
     }
 }
@@ -59,7 +59,7 @@ impl SetCurPosition for TimeNoteSequence {
 }
 
 impl IterMutWrapper for TimeNoteSequence {
-    fn iter_mut(&mut self) -> std::slice::IterMut<Vec<PlaybackNote>> {
+    fn iter_mut(&mut self) -> std::slice::IterMut<'_, Vec<PlaybackNote>> {
         self.sequence.iter_mut()
     }
 }
@@ -68,11 +68,11 @@ impl IterMutWrapper for TimeNoteSequence {
 impl TimeNoteSequence {
 
     // Manage PlaybackNotes
-    pub(crate) fn append_notes(&mut self, playback_notes: &Vec<PlaybackNote>) {
-        self.validate_notes_to_add(&playback_notes);
+    pub(crate) fn append_notes(&mut self, playback_notes: &[PlaybackNote]) {
+        self.validate_notes_to_add(playback_notes);
 
         if self.frontier_indexes.is_empty() {
-            self.sequence.push(playback_notes.clone());
+            self.sequence.push(playback_notes.to_vec());
             // Went from no indexes with notes to the 0th index now has notes, start of frontier
             self.frontier_indexes.push_back(0);
             return;
@@ -86,18 +86,18 @@ impl TimeNoteSequence {
         let max_frontier_index = self.frontier_indexes[self.frontier_indexes.len() - 1];
         let min_frontier_start_time_ms = self.get_frontier_min_start_time();
         if float_eq(min_frontier_start_time_ms, playback_notes[0].note_start_time_ms()) {
-            self.sequence[max_frontier_index].append(&mut playback_notes.clone());
+            self.sequence[max_frontier_index].extend_from_slice(playback_notes);
         } else {
             if min_frontier_start_time_ms > playback_notes[0].note_start_time_ms() {
                 panic!("PlaybackNotes must be appended sorted by start time");
             }
-            self.sequence.push(playback_notes.clone());
+            self.sequence.push(playback_notes.to_vec());
             self.frontier_indexes.push_back(max_frontier_index + 1);
         }
     }
 
     pub(crate) fn append_note(&mut self, playback_note: PlaybackNote) {
-        self.append_notes(&vec![playback_note]);
+        self.append_notes(&[playback_note]);
     }
 
     pub(crate) fn insert_notes(&mut self, playback_notes: Vec<PlaybackNote>) {
@@ -214,7 +214,7 @@ impl TimeNoteSequence {
         window_playback_notes
     }
     
-    pub(crate) fn notes_iter_mut(&mut self) -> std::slice::IterMut<Vec<PlaybackNote>> {
+    pub(crate) fn notes_iter_mut(&mut self) -> std::slice::IterMut<'_, Vec<PlaybackNote>> {
         self.sequence.iter_mut()
     }
 
@@ -290,7 +290,7 @@ impl TimeNoteSequence {
         end_time_ms
     }
 
-    fn validate_notes_to_add(&self, playback_notes: &Vec<PlaybackNote>) {
+    fn validate_notes_to_add(&self, playback_notes: &[PlaybackNote]) {
         for playback_note in playback_notes {
             if playback_note.note_start_time_ms() < 0.0 {
                 panic!("PlaybackNote start time must be >= 0.0");
@@ -305,7 +305,7 @@ impl TimeNoteSequence {
 }
 
 // Custom iterator for TrackGrid over the note_windows in the grid
-impl<'a> Iterator for TimeNoteSequence {
+impl Iterator for TimeNoteSequence {
     type Item = Vec<PlaybackNote>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -379,7 +379,7 @@ mod test_time_note_sequence {
 
         sequence.append_note(pb_note_1.clone());
         sequence.append_note(pb_note_2.clone());
-        sequence.append_notes(&vec![pb_note_3.clone(), pb_note_4.clone()]);
+        sequence.append_notes(&[pb_note_3.clone(), pb_note_4.clone()]);
         sequence.append_note(pb_note_5.clone());
 
         assert_eq!(sequence.frontier_indexes.len(), 4);
@@ -1,3 +1,4 @@
 pub mod track;
+pub mod track_bus;
 pub mod track_effects;
-pub mod track_grid;
\ No newline at end of file
+pub mod track_grid;
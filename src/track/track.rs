@@ -5,6 +5,7 @@ use crate::track::track_effects;
 use crate::track::track_effects::TrackEffects;
 
 static DEFAULT_TRACK_VOLUME: f32 = 1.0;
+static DEFAULT_TRACK_PAN: f32 = 0.0;
 
 #[allow(dead_code)]
 #[derive(Builder, Clone, Debug)]
@@ -12,13 +13,34 @@ pub(crate) struct Track<SequenceType> {
     #[builder(default = "NO_TRACK")]
     pub(crate) num: i16,
 
+    // names the track's stem file
+    #[builder(default = "None")]
+    pub(crate) name: Option<String>,
+
     #[builder(default = "DEFAULT_TRACK_VOLUME")]
     pub(crate) volume: f32,
 
+    // position in the stereo field, -1.0 hard left to 1.0 hard right
+    #[builder(default = "DEFAULT_TRACK_PAN")]
+    pub(crate) pan: f32,
+
     pub(crate) sequence: SequenceType,
 
     #[builder(default = "track_effects::no_op_effects()")]
     pub(crate) effects: TrackEffects,
 }
 
-impl<SequenceType> Track<SequenceType> {}
+#[allow(dead_code)]
+impl<SequenceType> Track<SequenceType> {
+    // Name of the track's stem file, without the extension. Made from the track's name, else its
+    // number, else its index in the TrackGrid, counting from 1
+    pub(crate) fn stem_name(&self, track_index: usize) -> String {
+        match &self.name {
+            Some(name) => name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                .collect(),
+            None if self.num != NO_TRACK => format!("track_{}", self.num),
+            None => format!("track_{}", track_index + 1),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use derive_builder::Builder;
+
+use crate::common::stereo::StereoFrame;
+use crate::effect::convolution::Convolution;
+use crate::effect::delay::Delay;
+use crate::effect::reverb::Reverb;
+use crate::track::track_effects::TrackEffects;
+
+// The effects of a track that run on the sum of its notes rather than on each note: delays, then
+// algorithmic reverbs, then convolutions, so a cabinet impulse response colors the rest. A
+// TrackGrid keeps one bus per track for the whole render, so their state carries on from window
+// to window and their tails ring on after the track's notes end
+#[derive(Builder, Clone, Debug, PartialEq)]
+pub(crate) struct TrackBus {
+    // mono, so each runs on the left channel and a copy of it on the right
+    #[builder(default = "Vec::new()")]
+    pub(crate) delays: Vec<Delay>,
+    #[builder(default = "self.delays.clone().unwrap_or_default()", setter(skip))]
+    right_delays: Vec<Delay>,
+
+    #[builder(default = "Vec::new()")]
+    pub(crate) reverbs: Vec<Reverb>,
+
+    #[builder(default = "Vec::new()")]
+    pub(crate) convolutions: Vec<Convolution>,
+
+    // samples since the last one with any signal in, to tell how much of the tail is left.
+    // Nothing has gone in yet, so there is no tail
+    #[builder(default = "usize::MAX", setter(skip))]
+    num_silent_samples: usize,
+}
+
+impl TrackBus {
+    pub(crate) fn process(&mut self, mut frame: StereoFrame) -> StereoFrame {
+        if frame == StereoFrame::default() {
+            self.num_silent_samples = self.num_silent_samples.saturating_add(1);
+        } else {
+            self.num_silent_samples = 0;
+        }
+
+        for (delay, right_delay) in self.delays.iter_mut().zip(self.right_delays.iter_mut()) {
+            frame = StereoFrame::new(delay.apply_effect(frame.left),
+                                     right_delay.apply_effect(frame.right));
+        }
+        for reverb in self.reverbs.iter_mut() {
+            frame = reverb.apply_effect(frame);
+        }
+        for convolution in self.convolutions.iter_mut() {
+            frame = convolution.apply_effect(frame);
+        }
+        frame
+    }
+
+    // Samples of the tail still to come, if the input stays silent. Each effect's tail runs
+    // through the ones after it, so they add up
+    pub(crate) fn tail_samples(&self) -> usize {
+        let tail_samples = self.delays.iter().map(Delay::tail_samples).sum::<usize>() +
+            self.reverbs.iter().map(Reverb::tail_samples).sum::<usize>() +
+            self.convolutions.iter().map(Convolution::tail_samples).sum::<usize>();
+        tail_samples.saturating_sub(self.num_silent_samples)
+    }
+}
+
+pub(crate) fn track_bus(track_effects: &TrackEffects) -> TrackBus {
+    TrackBusBuilder::default()
+        .delays(track_effects.delays.clone())
+        .reverbs(track_effects.reverbs.clone())
+        .convolutions(track_effects.convolutions.clone())
+        .build().unwrap()
+}
+
+#[cfg(test)]
+mod test_track_bus {
+    use crate::common::stereo::StereoFrame;
+    use crate::effect::convolution::{ConvolutionBuilder, ImpulseResponse};
+    use crate::effect::reverb::default_reverb;
+    use crate::track::track_bus::{TrackBus, TrackBusBuilder};
+
+    fn reverb_bus() -> TrackBus {
+        TrackBusBuilder::default()
+            .reverbs(vec![default_reverb()])
+            .build().unwrap()
+    }
+
+    #[test]
+    fn test_tail_samples() {
+        // no tail until something goes in, then the whole of it, counting down once it's silent
+        let mut track_bus = reverb_bus();
+        let reverb_tail_samples = default_reverb().tail_samples();
+        assert_eq!(track_bus.tail_samples(), 0);
+        track_bus.process(StereoFrame::from_mono(1.0));
+        assert_eq!(track_bus.tail_samples(), reverb_tail_samples);
+        let tail: Vec<StereoFrame> = (0..2000)
+            .map(|_| track_bus.process(StereoFrame::default()))
+            .collect();
+        assert_eq!(track_bus.tail_samples(), reverb_tail_samples - 2000);
+        assert!(tail.iter().any(|frame| *frame != StereoFrame::default()));
+
+        assert_eq!(TrackBusBuilder::default().build().unwrap().tail_samples(), 0);
+    }
+
+    #[test]
+    fn test_convolution_tail() {
+        // a click convolved on the bus comes through two samples late, after the input has
+        // stopped, and the bus says how long that is
+        let impulse_response = ImpulseResponse { left: vec![0.0, 0.0, 1.0], right: Vec::new() };
+        let mut track_bus = TrackBusBuilder::default()
+            .convolutions(vec![ConvolutionBuilder::default()
+                .impulse_response(impulse_response)
+                .mix(1.0)
+                .build().unwrap()])
+            .build().unwrap();
+        assert_eq!(track_bus.process(StereoFrame::from_mono(1.0)), StereoFrame::default());
+        assert_eq!(track_bus.tail_samples(), 2);
+        let tail: Vec<StereoFrame> = (0..2)
+            .map(|_| track_bus.process(StereoFrame::default()))
+            .collect();
+        assert_eq!(tail[1], StereoFrame::from_mono(1.0));
+        assert_eq!(track_bus.tail_samples(), 0);
+    }
+}
@@ -1,6 +1,10 @@
 use derive_builder::Builder;
+use crate::effect::convolution::Convolution;
 use crate::effect::delay::Delay;
+use crate::effect::filter::Filter;
+use crate::effect::reverb::Reverb;
 use crate::envelope::envelope::Envelope;
+use crate::envelope::modulation_envelope::ModulationEnvelope;
 use crate::effect::flanger::Flanger;
 use crate::effect::lfo::LFO;
 
@@ -21,6 +25,27 @@ pub(crate) struct TrackEffects {
     #[allow(dead_code)]
     #[builder(default = "Vec::new()")]
     pub(crate) delays: Vec<Delay>,
+
+    #[allow(dead_code)]
+    #[builder(default = "Vec::new()")]
+    pub(crate) filters: Vec<Filter>,
+
+    #[allow(dead_code)]
+    #[builder(default = "Vec::new()")]
+    pub(crate) reverbs: Vec<Reverb>,
+
+    #[allow(dead_code)]
+    #[builder(default = "Vec::new()")]
+    pub(crate) convolutions: Vec<Convolution>,
+
+    // modulate the pitch of every oscillator note on the track, and the cutoff of its filters,
+    // added to any the note has itself
+    #[allow(dead_code)]
+    #[builder(default = "None")]
+    pub(crate) pitch_envelope: Option<ModulationEnvelope>,
+    #[allow(dead_code)]
+    #[builder(default = "None")]
+    pub(crate) cutoff_envelope: Option<ModulationEnvelope>,
 }
 
 pub(crate) fn no_op_effects() -> TrackEffects {
@@ -29,6 +54,17 @@ pub(crate) fn no_op_effects() -> TrackEffects {
 
 impl TrackEffects {
 
+    // The effects each note on the track runs itself, without the delays, reverbs and
+    // convolutions the track's bus runs on the sum of its notes
+    pub(crate) fn note_effects(&self) -> TrackEffects {
+        TrackEffects {
+            delays: Vec::new(),
+            reverbs: Vec::new(),
+            convolutions: Vec::new(),
+            ..self.clone()
+        }
+    }
+
     #[allow(dead_code)]
     pub(crate) fn has_envelopes(&self) -> bool {
         !self.envelopes.is_empty()
@@ -48,9 +84,25 @@ impl TrackEffects {
     pub(crate) fn has_delays(&self) -> bool {
         !self.delays.is_empty()
     }
+
+    #[allow(dead_code)]
+    pub(crate) fn has_filters(&self) -> bool {
+        !self.filters.is_empty()
+    }
+    
+    #[allow(dead_code)]
+    pub(crate) fn has_reverbs(&self) -> bool {
+        !self.reverbs.is_empty()
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn has_convolutions(&self) -> bool {
+        !self.convolutions.is_empty()
+    }
     
     #[allow(dead_code)]
     pub(crate) fn has_effects(&self) -> bool {
-        self.has_envelopes() || self.has_lfos() || self.has_flangers() || self.has_delays()
+        self.has_envelopes() || self.has_lfos() || self.has_flangers() || self.has_delays() ||
+            self.has_filters() || self.has_reverbs() || self.has_convolutions()
     }
 }
\ No newline at end of file
@@ -1,11 +1,20 @@
+use std::collections::HashMap;
+
 use derive_builder::Builder;
 
-use crate::common::constants::{FLOAT_EPSILON, SAMPLE_RATE};
+use crate::audio_gen::audio_gen::{gen_notes_buffer, window_bounds_ms};
+use crate::audio_gen::master_bus::{default_master_bus, MasterBus};
+use crate::audio_gen::noise::NoiseGenerator;
+use crate::audio_gen::oscillator::OscillatorTables;
+use crate::common::constants::FLOAT_EPSILON;
+use crate::common::render_config::RenderConfig;
+use crate::common::stereo::{PanLaw, StereoFrame};
 use crate::common::float_utils::{float_eq, float_geq, float_leq};
 use crate::note::playback_note;
 use crate::note::playback_note::{PlaybackNoteBuilder, PlaybackNote, NoteType};
 use crate::sequence::note_sequence_trait::{NextNotes, SetCurPosition};
 use crate::track::track::Track;
+use crate::track::track_bus::{track_bus, TrackBus};
 
 #[derive(Builder, Clone, Debug)]
 pub(crate) struct TrackGrid<SequenceType: NextNotes + Iterator + SetCurPosition> {
@@ -13,58 +22,323 @@ pub(crate) struct TrackGrid<SequenceType: NextNotes + Iterator + SetCurPosition>
 
     #[builder(default = "0.0")]
     cur_position_ms: f32,
+
+    // pan law applied to every note in the grid when it is panned into the stereo mix
+    #[builder(default = "PanLaw::default()")]
+    pub(crate) pan_law: PanLaw,
+
+    // sample rate used to convert note times to sample times, and to render the grid
+    #[builder(default = "RenderConfig::default()")]
+    pub(crate) render_config: RenderConfig,
+
+    // gain, limiting and clipping applied to the mix of all the tracks
+    #[builder(default = "default_master_bus()")]
+    pub(crate) master_bus: MasterBus,
+
+    // notes still playing at the end of the last window rendered, as they were left, to carry on
+    // from in the next window
+    #[builder(default = "HashMap::new()", setter(skip))]
+    carried_notes: HashMap<NoteKey, PlaybackNote>,
+
+    // sample the next window rendered starts at
+    #[builder(default = "0", setter(skip))]
+    next_sample_index: usize,
+
+    // each track's bus, built from its effects when the first window is rendered
+    #[builder(default = "Vec::new()", setter(skip))]
+    track_buses: Vec<TrackBus>,
+
+    // notes that have ended whose own effects are still ringing
+    #[builder(default = "Vec::new()", setter(skip))]
+    ringing_notes: Vec<RingingNote>,
+}
+
+// A note that has ended, with num_tail_samples of its tail still to ring on the track at
+// track_index
+#[derive(Clone, Debug)]
+struct RingingNote {
+    track_index: usize,
+    playback_note: PlaybackNote,
+    num_tail_samples: usize,
+}
+
+// A note of a track, which the grid emits again in every window it plays in. index tells apart
+// notes of the track that start and end at the same time
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+struct NoteKey {
+    track_index: usize,
+    start_time_bits: u32,
+    end_time_bits: u32,
+    index: usize,
 }
 
 impl<SequenceType: NextNotes + Iterator + SetCurPosition> TrackGrid<SequenceType> {
 
     pub(crate) fn next_notes(&mut self) -> Vec<PlaybackNote> {
+        self.next_track_notes().into_iter().map(|(_, playback_note)| playback_note).collect()
+    }
+
+    // next_notes, each with the index of the track it plays on. The rest notes that fill the
+    // gaps between notes belong to no track
+    pub(crate) fn next_track_notes(&mut self) -> Vec<(Option<usize>, PlaybackNote)> {
+        self.next_window_notes().into_iter()
+            .map(|(note_key, playback_note)|
+                (note_key.map(|note_key| note_key.track_index), playback_note))
+            .collect()
+    }
+
+    // Renders the next window of the grid to one buffer of frames per track. Each window's
+    // buffers start where the last one's ended, with silence over any gap before its notes, so a
+    // track's windows join up into one continuous signal. Notes still playing at the end of the
+    // window carry their state into the next one, so a window split by a note on another track
+    // doesn't reset their filters, phases or other effects. Each track's frames then run through
+    // its bus. After the last note one more window holds the tails still ringing, and then None
+    pub(crate) fn next_window_frames(&mut self, oscillator_tables: &OscillatorTables)
+        -> Option<Vec<Vec<StereoFrame>>> {
+        if self.track_buses.len() != self.tracks.len() {
+            self.track_buses = self.tracks.iter()
+                .map(|track| track_bus(&track.effects))
+                .collect();
+        }
+
+        let window_notes = self.next_window_notes();
+        if window_notes.is_empty() {
+            return self.next_tail_frames();
+        }
+
+        // rest notes are silent, and the gaps they fill are padded when the next notes start
+        let (note_keys, playback_notes): (Vec<_>, Vec<_>) = window_notes.into_iter()
+            .filter_map(|(note_key, playback_note)|
+                note_key.map(|note_key| (note_key, playback_note)))
+            .unzip();
+        if playback_notes.is_empty() {
+            return Some(vec![Vec::new(); self.tracks.len()]);
+        }
+        let (window_start_time_ms, window_end_time_ms) = window_bounds_ms(&playback_notes);
+        let window_start_index = self.render_config.ms_to_samples(window_start_time_ms);
+        let window_end_index = self.render_config.ms_to_samples(window_end_time_ms)
+            .max(window_start_index);
+        let num_gap_samples = window_start_index.saturating_sub(self.next_sample_index);
+        let num_overlap_samples = self.next_sample_index.saturating_sub(window_start_index);
+
+        let mut tracks_notes: Vec<Vec<(NoteKey, PlaybackNote)>> =
+            vec![Vec::new(); self.tracks.len()];
+        for (note_key, playback_note) in note_keys.into_iter().zip(playback_notes) {
+            tracks_notes[note_key.track_index].push((note_key, playback_note));
+        }
 
-        fn note_ref_into_note(playback_note: &PlaybackNote, cur_notes_time_ms: f32,
-                              window_end_time_ms: f32) -> PlaybackNote {
-            let mut new_pb_note: PlaybackNote = playback_note.clone();
+        let mut tracks_frames = Vec::with_capacity(self.tracks.len());
+        let mut ended_notes = Vec::new();
+        for (track_index, track_notes) in tracks_notes.into_iter().enumerate() {
+            let (note_keys, mut playback_notes): (Vec<_>, Vec<_>) =
+                track_notes.into_iter().unzip();
+            let mut window_frames = if playback_notes.is_empty() {
+                Vec::new()
+            } else {
+                gen_notes_buffer(&mut playback_notes, oscillator_tables)
+            };
+            window_frames.resize(window_end_index - window_start_index, StereoFrame::default());
+
+            let mut frames = vec![StereoFrame::default(); num_gap_samples];
+            frames.extend(window_frames.into_iter().skip(num_overlap_samples));
+            tracks_frames.push(self.finish_track_frames(track_index, frames));
+
+            for (note_key, playback_note) in note_keys.into_iter().zip(playback_notes) {
+                if !float_leq(playback_note.note_end_time_ms(), window_end_time_ms) {
+                    self.carried_notes.insert(note_key, playback_note);
+                } else if playback_note.tail_samples() > 0 {
+                    ended_notes.push(RingingNote {
+                        track_index,
+                        num_tail_samples: playback_note.tail_samples(),
+                        playback_note,
+                    });
+                }
+            }
+        }
+        // notes that ended in this window start ringing in the next
+        self.ringing_notes.extend(ended_notes);
+        self.next_sample_index = self.next_sample_index.max(window_end_index);
+
+        Some(tracks_frames)
+    }
+
+    // The tails still ringing after the last note, as long as the longest of them, or None if
+    // none are
+    fn next_tail_frames(&mut self) -> Option<Vec<Vec<StereoFrame>>> {
+        let num_tail_samples = self.ringing_notes.iter()
+            .map(|ringing_note| ringing_note.num_tail_samples)
+            .chain(self.track_buses.iter().map(TrackBus::tail_samples))
+            .max().unwrap_or(0);
+        if num_tail_samples == 0 {
+            return None;
+        }
+
+        let tracks_frames = (0..self.tracks.len())
+            .map(|track_index| self.finish_track_frames(
+                track_index, vec![StereoFrame::default(); num_tail_samples]))
+            .collect();
+        self.next_sample_index += num_tail_samples;
+        Some(tracks_frames)
+    }
+
+    // Adds the tails of the track's ringing notes to its frames and runs them through its bus
+    fn finish_track_frames(&mut self, track_index: usize,
+                           mut frames: Vec<StereoFrame>) -> Vec<StereoFrame> {
+        for ringing_note in self.ringing_notes.iter_mut()
+            .filter(|ringing_note| ringing_note.track_index == track_index) {
+            let num_samples = ringing_note.num_tail_samples.min(frames.len());
+            for frame in frames[..num_samples].iter_mut() {
+                *frame += ringing_note.playback_note.next_tail_frame();
+            }
+            ringing_note.num_tail_samples -= num_samples;
+        }
+        self.ringing_notes.retain(|ringing_note| ringing_note.num_tail_samples > 0);
+
+        let track_bus = &mut self.track_buses[track_index];
+        frames.into_iter().map(|frame| track_bus.process(frame)).collect()
+    }
+
+    // next_track_notes, each with the key of the note it plays. A note carried over from the
+    // last window rendered picks up from where it was left
+    fn next_window_notes(&mut self) -> Vec<(Option<NoteKey>, PlaybackNote)> {
+
+        fn note_ref_into_note(playback_note: &PlaybackNote, carried_note: Option<PlaybackNote>,
+                              cur_notes_time_ms: f32, window_end_time_ms: f32,
+                              render_config: &RenderConfig) -> PlaybackNote {
+            let samples_per_ms = render_config.samples_per_ms();
+            let is_carried = carried_note.is_some();
+            let mut new_pb_note: PlaybackNote =
+                carried_note.unwrap_or_else(|| playback_note.clone());
             new_pb_note.playback_start_time_ms = cur_notes_time_ms;
             new_pb_note.playback_end_time_ms = window_end_time_ms;
+
+            // envelopes and LFOs run from the start of the note, not of the window
+            let note_start_index = render_config.ms_to_samples(new_pb_note.note_start_time_ms());
+            let elapsed_samples =
+                render_config.ms_to_samples(cur_notes_time_ms).saturating_sub(note_start_index);
+            let note_num_samples = render_config.ms_to_samples(new_pb_note.note_end_time_ms())
+                .saturating_sub(note_start_index);
+            new_pb_note.set_elapsed_samples(elapsed_samples as u64, note_num_samples as u64);
+
+            // flangers sweep in time with the piece rather than from the start of each note
+            if !is_carried {
+                let sample_index = render_config.ms_to_samples(cur_notes_time_ms) as u64;
+                for flanger in new_pb_note.flangers.iter_mut()
+                    .chain(new_pb_note.track_effects.flangers.iter_mut()) {
+                    flanger.set_lfo_phase_at(sample_index);
+                }
+            }
             
             // TODO BUG
             //  adjust playback_sample_start_time_ms and end_time_ms and sample_index if SampleNote
-            if playback_note.note_type == NoteType::Sample {
-                new_pb_note.playback_sample_start_time =
-                    (new_pb_note.playback_start_time_ms * (SAMPLE_RATE / 1000.0)).floor() as u64;
-                new_pb_note.playback_sample_end_time =
-                    (new_pb_note.playback_end_time_ms * (SAMPLE_RATE / 1000.0)).floor() as u64;
-                new_pb_note.sampled_note.sample_index = ((new_pb_note.playback_start_time_ms -
-                    new_pb_note.sampled_note.start_time_ms) * (SAMPLE_RATE / 1000.0)) as usize;
+            match playback_note.note_type {
+                NoteType::Sample => {
+                    new_pb_note.playback_sample_start_time =
+                        (new_pb_note.playback_start_time_ms * samples_per_ms).floor() as u64;
+                    new_pb_note.playback_sample_end_time =
+                        (new_pb_note.playback_end_time_ms * samples_per_ms).floor() as u64;
+                    if !is_carried {
+                        new_pb_note.sampled_note.sample_index = ((new_pb_note
+                            .playback_start_time_ms - new_pb_note.sampled_note.start_time_ms) *
+                            samples_per_ms) as usize;
+                    }
+                }
+                // a carried note's oscillators are already where the last window left them
+                _ if is_carried => {}
+                NoteType::Oscillator => {
+                    // a note first emitted partway through starts its oscillators where they
+                    // would be at the note's unswept pitch
+                    new_pb_note.oscillator_phases = new_pb_note.oscillator_phases_at(
+                        new_pb_note.playback_start_time_ms - new_pb_note.note.start_time_ms);
+                }
+                NoteType::FM => {
+                    let elapsed_ms =
+                        new_pb_note.playback_start_time_ms - new_pb_note.fm_note.start_time_ms;
+                    new_pb_note.fm_note.set_elapsed_ms(elapsed_ms, samples_per_ms);
+                }
+                NoteType::Additive => {
+                    let elapsed_ms = new_pb_note.playback_start_time_ms -
+                        new_pb_note.additive_note.start_time_ms;
+                    new_pb_note.additive_note.set_elapsed_ms(elapsed_ms, samples_per_ms);
+                }
             }
 
             new_pb_note
         }
 
+        let samples_per_ms = self.render_config.samples_per_ms();
         let mut track_playback_notes = Vec::new();
+        let mut note_keys: Vec<NoteKey> = Vec::new();
+        let mut carried_notes = std::mem::take(&mut self.carried_notes);
 
-        for track in self.tracks.iter_mut() {
+        for (track_index, track) in self.tracks.iter_mut().enumerate() {
             track.sequence.set_cur_position(self.cur_position_ms);
             
-            for playback_note in track.sequence.next_notes() {
+            for (note_index, playback_note) in track.sequence.next_notes().into_iter().enumerate() {
+                // A note's voice is only built in the first window it plays in and is carried
+                // on from there, but the window is still part of the voice's identity
+                let noise = NoiseGenerator::for_voice(
+                    self.render_config.seed,
+                    &[track_index as u64, note_index as u64,
+                      playback_note.playback_start_time_ms.to_bits() as u64,
+                      self.cur_position_ms.to_bits() as u64]);
+                let note_key = NoteKey {
+                    track_index,
+                    start_time_bits: playback_note.note_start_time_ms().to_bits(),
+                    end_time_bits: playback_note.note_end_time_ms().to_bits(),
+                    index: 0,
+                };
+                let index = note_keys.iter()
+                    .filter(|other| NoteKey { index: 0, ..**other } == note_key)
+                    .count();
+                let note_key = NoteKey { index, ..note_key };
                 let mut playback_note_builder = PlaybackNoteBuilder::default();
                     playback_note_builder
                         .playback_start_time_ms(playback_note.playback_start_time_ms)
                         .playback_end_time_ms(playback_note.playback_end_time_ms)
                         .playback_sample_start_time((playback_note.playback_start_time_ms *
-                            (SAMPLE_RATE / 1000.0)).floor() as u64)
+                            samples_per_ms).floor() as u64)
                         .playback_end_time_ms(playback_note.playback_end_time_ms)
                         .playback_sample_end_time((playback_note.playback_end_time_ms *
-                            (SAMPLE_RATE / 1000.0)).floor() as u64)
+                            samples_per_ms).floor() as u64)
                         .envelopes(playback_note.envelopes.clone())
                         .lfos(playback_note.lfos.clone())
                         .flangers(playback_note.flangers.clone())
+                        .filters(playback_note.filters.clone())
+                        .reverbs(playback_note.reverbs.clone())
+                        .convolutions(playback_note.convolutions.clone())
                         .delays(playback_note.delays.clone())
-                        .track_effects(track.effects.clone());
+                        .track_effects(track.effects.note_effects())
+                        .pan(playback_note.pan)
+                        .track_pan(track.pan)
+                        .pan_law(self.pan_law)
+                        .noise(noise)
+                        .pulse_width_envelope(playback_note.pulse_width_envelope)
+                        .pulse_width_lfo(playback_note.pulse_width_lfo.clone())
+                        .wavetable_position_envelope(playback_note.wavetable_position_envelope)
+                        .wavetable_position_lfo(playback_note.wavetable_position_lfo.clone())
+                        .pitch_envelope(playback_note.pitch_envelope)
+                        .cutoff_envelope(playback_note.cutoff_envelope);
                 
                 match playback_note.note_type {
                     NoteType::Oscillator => {
+                        // Unlike the noise, the voices' start phases must be the same in every
+                        // window, so they are derived from the note rather than the window
+                        let unison = playback_note.note.unison;
+                        let unison_start_phases = if unison.is_unison() {
+                            let mut phase_noise = NoiseGenerator::for_voice(
+                                Some(self.render_config.seed.unwrap_or_default()),
+                                &[track_index as u64,
+                                  playback_note.note.start_time_ms.to_bits() as u64,
+                                  playback_note.note.frequency.to_bits() as u64]);
+                            unison.start_phases(&mut phase_noise)
+                        } else {
+                            Vec::new()
+                        };
                         track_playback_notes.push(
                             playback_note_builder
                                 .note_type(NoteType::Oscillator)
+                                .unison_start_phases(unison_start_phases)
                                 .note(playback_note.note)
                                 .build().unwrap()
                         );
@@ -77,7 +351,24 @@ impl<SequenceType: NextNotes + Iterator + SetCurPosition> TrackGrid<SequenceType
                                 .build().unwrap()
                         );
                     }
+                    NoteType::FM => {
+                        track_playback_notes.push(
+                            playback_note_builder
+                                .note_type(NoteType::FM)
+                                .fm_note(playback_note.fm_note)
+                                .build().unwrap()
+                        );
+                    }
+                    NoteType::Additive => {
+                        track_playback_notes.push(
+                            playback_note_builder
+                                .note_type(NoteType::Additive)
+                                .additive_note(playback_note.additive_note)
+                                .build().unwrap()
+                        );
+                    }
                 }
+                note_keys.push(note_key);
             }
         }
 
@@ -89,8 +380,8 @@ impl<SequenceType: NextNotes + Iterator + SetCurPosition> TrackGrid<SequenceType
         // the current notes time to the frontier min start time + epsilon
         if self.cur_position_ms < window_start_time_ms {
             self.cur_position_ms = window_start_time_ms + FLOAT_EPSILON;
-            return vec![playback_note::playback_rest_note(self.cur_position_ms,
-                                                          window_start_time_ms)];
+            return vec![(None, playback_note::playback_rest_note(self.cur_position_ms,
+                                                                window_start_time_ms))];
         }
 
         let mut out_playback_notes = Vec::new();
@@ -98,29 +389,35 @@ impl<SequenceType: NextNotes + Iterator + SetCurPosition> TrackGrid<SequenceType
         // If the current note time is the same as the frontier min start time, emit all notes
         // that start in the current window (from cur_position_ms to window_end_time_ms)
         if float_eq(self.cur_position_ms, window_start_time_ms) {
-            let playback_notes: Vec<PlaybackNote> = track_playback_notes
+            let playback_notes: Vec<(Option<NoteKey>, PlaybackNote)> = track_playback_notes
                 .iter()
-                .filter(|playback_note| {
+                .zip(note_keys.iter())
+                .filter(|(playback_note, _)| {
                     let start_time = playback_note.note_start_time_ms();
                     float_geq(start_time, self.cur_position_ms) &&
                     float_leq(start_time, window_end_time_ms)
                 })
-                .map(|playback_note| note_ref_into_note(
-                    playback_note, self.cur_position_ms, window_end_time_ms))
+                .map(|(playback_note, note_key)| (Some(*note_key), note_ref_into_note(
+                    playback_note, carried_notes.remove(note_key), self.cur_position_ms,
+                    window_end_time_ms, &self.render_config)))
                 .collect();
 
             out_playback_notes.extend_from_slice(&playback_notes);
 
         } else if self.cur_position_ms > window_start_time_ms {
-            let playback_notes: Vec<PlaybackNote> = track_playback_notes
+            let playback_notes: Vec<(Option<NoteKey>, PlaybackNote)> = track_playback_notes
                 .iter()
-                .filter(|playback_note|
+                .zip(note_keys.iter())
+                .filter(|(playback_note, _)|
                     float_leq(playback_note.note_start_time_ms(), self.cur_position_ms) &&
                     float_geq(playback_note.note_end_time_ms(), self.cur_position_ms)
                 )
-                .filter(|playback_note| playback_note.note_duration_ms() > 0.0)
-                .map(|playback_note|
-                    note_ref_into_note(playback_note, self.cur_position_ms, window_end_time_ms)
+                .filter(|(playback_note, _)| playback_note.note_duration_ms() > 0.0)
+                .map(|(playback_note, note_key)|
+                    (Some(*note_key),
+                     note_ref_into_note(playback_note, carried_notes.remove(note_key),
+                                        self.cur_position_ms, window_end_time_ms,
+                                        &self.render_config))
                 )
                 .collect();
 
@@ -133,7 +430,7 @@ impl<SequenceType: NextNotes + Iterator + SetCurPosition> TrackGrid<SequenceType
     }
 }
 
-fn get_frontier_min_start_time(playback_notes: &Vec<PlaybackNote>) -> f32 {
+fn get_frontier_min_start_time(playback_notes: &[PlaybackNote]) -> f32 {
     let mut start_time_ms = f32::MAX;
     for playback_note in playback_notes.iter() {
         if playback_note.note_start_time_ms() < start_time_ms {
@@ -143,7 +440,7 @@ fn get_frontier_min_start_time(playback_notes: &Vec<PlaybackNote>) -> f32 {
     start_time_ms
 }
 
-fn get_frontier_min_end_time(playback_notes: &Vec<PlaybackNote>, note_time_ms: f32) -> f32 {
+fn get_frontier_min_end_time(playback_notes: &[PlaybackNote], note_time_ms: f32) -> f32 {
     let mut end_time_ms = f32::MAX;
 
     // First pass, is what is the earliest end time in the future, after note_time_ms
@@ -183,14 +480,24 @@ impl<SequenceType: NextNotes + Iterator + SetCurPosition> Iterator for TrackGrid
 
 #[cfg(test)]
 mod test_sequence_grid {
-    use crate::effect::{flanger, lfo};
+    use crate::audio_gen::band_limit::BandLimit;
+    use crate::audio_gen::oscillator::{OscillatorTables, Waveform};
+    use crate::common::stereo::StereoFrame;
+    use crate::effect::delay::DelayBuilder;
+    use crate::effect::filter::FilterBuilder;
+    use crate::effect::flanger::FlangerBuilder;
+    use crate::effect::{flanger, lfo, reverb};
     use crate::envelope::envelope;
+    use crate::envelope::envelope::EnvelopeBuilder;
+    use crate::envelope::envelope_pair::EnvelopePair;
+    use crate::envelope::modulation_envelope::ModulationEnvelopeBuilder;
     use crate::note::note::NoteBuilder;
-    use crate::note::playback_note::PlaybackNoteBuilder;
+    use crate::note::playback_note::{PlaybackNote, PlaybackNoteBuilder};
     use crate::sequence::grid_note_sequence::GridNoteSequenceBuilder;
+    use crate::sequence::time_note_sequence::{TimeNoteSequence, TimeNoteSequenceBuilder};
     use crate::track::track::TrackBuilder;
-    use crate::track::track_effects::TrackEffectsBuilder;
-    use crate::track::track_grid::TrackGridBuilder;
+    use crate::track::track_effects::{TrackEffects, TrackEffectsBuilder};
+    use crate::track::track_grid::{TrackGrid, TrackGridBuilder};
 
     #[test]
     fn test_active_notes_grid_sequence() {
@@ -243,6 +550,182 @@ mod test_sequence_grid {
         assert_eq!(playback_notes.len(), 2);
     }
 
+    #[test]
+    fn test_window_split_keeps_note_state() {
+        // a resonant low-passed saw held for 100 ms, rendered alone and with a short note on
+        // another track that splits it into three windows. It carries on across the splits as
+        // if they weren't there
+        let held_note = || split_track_note(Waveform::Saw(BandLimit::default()), 0.0, 100.0);
+        assert_split_matches_held(held_note);
+    }
+
+    #[test]
+    fn test_window_split_keeps_envelope_position() {
+        // the same, with the split falling partway through the note's volume envelope and a pitch
+        // and cutoff sweep, which carry on from where they are rather than starting again
+        let held_note = || {
+            let mut playback_note =
+                split_track_note(Waveform::Saw(BandLimit::default()), 0.0, 100.0);
+            playback_note.envelopes = vec![EnvelopeBuilder::default()
+                .attack(EnvelopePair(0.3, 1.0))
+                .decay(EnvelopePair(0.6, 0.5))
+                .sustain(EnvelopePair(0.9, 0.5))
+                .build().unwrap()];
+            let sweep = |depth| ModulationEnvelopeBuilder::default()
+                .envelope(EnvelopeBuilder::default()
+                    .attack(EnvelopePair(0.0, 1.0))
+                    .decay(EnvelopePair(0.8, 0.0))
+                    .sustain(EnvelopePair(0.9, 0.0))
+                    .build().unwrap())
+                .depth(depth)
+                .build().unwrap();
+            playback_note.pitch_envelope = Some(sweep(12.0));
+            playback_note.cutoff_envelope = Some(sweep(2.0));
+            playback_note
+        };
+        assert_split_matches_held(held_note);
+    }
+
+    #[test]
+    fn test_track_reverb_rings_on() {
+        // a track's reverb runs on the track as a whole, across the window splits, and its tail
+        // is rendered after the last note ends rather than cut off with it
+        assert_track_tail_rings_on(
+            |track_effects| track_effects.reverbs = vec![reverb::default_reverb()],
+            reverb::default_reverb().tail_samples());
+
+        // a note's own reverb rings on after it in the same way
+        assert_note_tail_rings_on(
+            |playback_note| playback_note.reverbs = vec![reverb::default_reverb()]);
+    }
+
+    #[test]
+    fn test_track_delay_rings_on() {
+        // the same for delays, whose echoes of the note carry on after it
+        let delay = DelayBuilder::default()
+            .delay_time_ms(30.0)
+            .feedback(0.5)
+            .build().unwrap();
+        assert_track_tail_rings_on(
+            |track_effects| track_effects.delays = vec![delay.clone()], delay.tail_samples());
+        assert_note_tail_rings_on(|playback_note| playback_note.delays = vec![delay.clone()]);
+    }
+
+    // the 100 ms held note with set_effects setting its track's effects, rendered alone and split
+    fn assert_track_tail_rings_on(set_effects: impl Fn(&mut TrackEffects),
+                                  tail_samples: usize) {
+        let track_grid = |split| {
+            let mut track_grid = setup_split_track_grid(
+                split_track_note(Waveform::Saw(BandLimit::default()), 0.0, 100.0), split);
+            set_effects(&mut track_grid.tracks[0].effects);
+            track_grid
+        };
+        let held = render_first_track(track_grid(false));
+        let split = render_first_track(track_grid(true));
+        let note_num_samples = 4410;
+        assert!(held.len() > note_num_samples);
+        assert!(held.len() <= note_num_samples + tail_samples);
+        assert!(held[note_num_samples..].iter().any(|frame| frame.left.abs() > 1e-3));
+        assert_frames_match(&held, &split);
+    }
+
+    fn assert_note_tail_rings_on(set_effects: impl Fn(&mut PlaybackNote)) {
+        let mut ringing_note = split_track_note(Waveform::Saw(BandLimit::default()), 0.0, 100.0);
+        set_effects(&mut ringing_note);
+        let frames = render_first_track(setup_split_track_grid(ringing_note, false));
+        assert!(frames[4410..].iter().any(|frame| frame.left.abs() > 1e-3));
+    }
+
+    #[test]
+    fn test_flanger_sweeps_with_the_piece() {
+        // a note starting partway through the piece picks up its track's flanger sweep where it
+        // would be had it been running since the start
+        let flanger = FlangerBuilder::default()
+            .rate_hz(10.0)
+            .depth(0.5)
+            .build().unwrap();
+        let mut track_grid =
+            setup_split_track_grid(split_track_note(Waveform::Sine, 25.0, 100.0), false);
+        track_grid.tracks[0].effects.flangers = vec![flanger.clone()];
+        let render_config = track_grid.render_config;
+        // past the rest the track's sequence fills the start with
+        let playback_note = track_grid
+            .find_map(|playback_notes| playback_notes.into_iter()
+                .find(|playback_note| playback_note.note_start_time_ms() > 0.0))
+            .unwrap();
+
+        let mut expected = flanger.clone();
+        expected.set_lfo_phase_at(render_config.ms_to_samples(25.0) as u64);
+        assert_eq!(playback_note.track_effects.flangers, vec![expected]);
+        assert_ne!(playback_note.track_effects.flangers, vec![flanger]);
+    }
+
+    fn assert_split_matches_held(held_note: impl Fn() -> PlaybackNote) {
+        let held = render_first_track(setup_split_track_grid(held_note(), false));
+        let split = render_first_track(setup_split_track_grid(held_note(), true));
+        assert!(held.iter().any(|frame| frame.left.abs() > 0.1));
+        assert_frames_match(&held, &split);
+    }
+
+    fn assert_frames_match(held: &[StereoFrame], split: &[StereoFrame]) {
+        assert_eq!(held.len(), split.len());
+        for (held_frame, split_frame) in held.iter().zip(split.iter()) {
+            assert!((held_frame.left - split_frame.left).abs() < 1e-6);
+        }
+    }
+
+    fn render_first_track(mut track_grid: TrackGrid<TimeNoteSequence>) -> Vec<StereoFrame> {
+        let oscillator_tables = OscillatorTables::with_render_config(track_grid.render_config);
+        let mut frames = Vec::new();
+        while let Some(tracks_frames) = track_grid.next_window_frames(&oscillator_tables) {
+            frames.extend_from_slice(&tracks_frames[0]);
+        }
+        frames
+    }
+
+    // held_note on a track of its own, and if split a short note on a second track partway
+    // through it
+    fn setup_split_track_grid(held_note: PlaybackNote,
+                              split: bool) -> TrackGrid<TimeNoteSequence> {
+        let mut notes = vec![held_note];
+        if split {
+            notes.push(split_track_note(Waveform::Sine, 40.0, 60.0));
+        }
+        let low_pass = FilterBuilder::default()
+            .cutoff_hz(800.0)
+            .q(4.0)
+            .build().unwrap();
+        let tracks = notes.into_iter()
+            .map(|playback_note| {
+                let mut sequence = TimeNoteSequenceBuilder::default().build().unwrap();
+                sequence.append_note(playback_note);
+                TrackBuilder::default()
+                    .sequence(sequence)
+                    .effects(TrackEffectsBuilder::default()
+                        .filters(vec![low_pass.clone()])
+                        .build().unwrap())
+                    .build().unwrap()
+            })
+            .collect();
+        TrackGridBuilder::default()
+            .tracks(tracks)
+            .build().unwrap()
+    }
+
+    fn split_track_note(waveform: Waveform, start_time_ms: f32,
+                        end_time_ms: f32) -> PlaybackNote {
+        PlaybackNoteBuilder::default()
+            .note(setup_note()
+                .frequency(220.0)
+                .start_time_ms(start_time_ms)
+                .end_time_ms(end_time_ms)
+                .waveforms(vec![waveform])
+                .build().unwrap())
+            .playback_start_time_ms(start_time_ms)
+            .playback_end_time_ms(end_time_ms)
+            .build().unwrap()
+    }
+
     fn setup_note() -> NoteBuilder {
         NoteBuilder::default().clone()
     }